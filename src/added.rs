@@ -0,0 +1,56 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http::Uri;
+
+use crate::{config::config, data::Data};
+
+// when arts were first seen, kept in a sidecar file so it survives restarts
+
+pub(crate) fn load() -> HashMap<Uri, SystemTime> {
+    let Some(path) = &config().arts.added_path else {
+        return HashMap::new();
+    };
+    let saved = match std::fs::read_to_string(path) {
+        Ok(saved) => saved,
+        Err(err) => {
+            tracing::info!(path, error = %err, "no saved added dates");
+            return HashMap::new();
+        }
+    };
+    match serde_json::from_str::<HashMap<String, u64>>(&saved) {
+        Ok(saved) => saved
+            .into_iter()
+            .filter_map(|(url, secs)| {
+                let added_at = UNIX_EPOCH + Duration::from_secs(secs);
+                Some((url.parse().ok()?, added_at))
+            })
+            .collect(),
+        Err(err) => {
+            tracing::warn!(path, error = %err, "could not parse saved added dates");
+            HashMap::new()
+        }
+    }
+}
+
+pub(crate) fn save(data: &Data) {
+    let Some(path) = &config().arts.added_path else {
+        return;
+    };
+    let added = data
+        .arts()
+        .iter()
+        .filter_map(|art| {
+            let secs = art.added_at?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            Some((art.url.to_string(), secs))
+        })
+        .collect::<HashMap<_, _>>();
+    let result = serde_json::to_string(&added)
+        .map_err(std::io::Error::from)
+        .and_then(|added| std::fs::write(path, added));
+    if let Err(err) = result {
+        tracing::error!(path, error = %err, "could not save added dates");
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom, Write},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use http::{StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    added,
+    client_ip::ClientIp,
+    config::config,
+    data::{self, Art},
+    db,
+    error::{AppError, AppErrorKind, AppResult},
+    loader,
+    quarantine::QuarantinedArt,
+    shared_cache, AppState,
+};
+
+// admin routes are only available when an admin token is configured
+pub(crate) fn authorize(headers: &HeaderMap) -> AppResult<()> {
+    let token = &config().server.admin_token;
+    if token.is_empty() {
+        return Err(AppError::from("admin routes are disabled").status(StatusCode::NOT_FOUND));
+    }
+    let provided = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if provided != Some(token.as_str()) {
+        return Err(AppError::from("invalid admin token").status(StatusCode::UNAUTHORIZED));
+    }
+    Ok(())
+}
+
+// the same as the reload signal, but also available on windows
+pub(crate) async fn reload_arts(headers: HeaderMap, state: State<AppState>) -> AppResult<String> {
+    authorize(&headers)?;
+    let added = loader::reload(&state).await?;
+    Ok(format!("reloaded arts, {added} new\n"))
+}
+
+pub(crate) async fn show_unhealthy(
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> AppResult<String> {
+    authorize(&headers)?;
+    let data = state.data.lock().unwrap();
+    let mut body = String::new();
+    for url in data.unhealthy() {
+        body.push_str(&url.to_string());
+        body.push('\n');
+    }
+    Ok(body)
+}
+
+pub(crate) async fn show_quarantine(
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> AppResult<Json<Vec<QuarantinedArt>>> {
+    authorize(&headers)?;
+    Ok(Json(state.quarantine.quarantined()))
+}
+
+// adds the submitted urls, one per line, and appends the new ones to the arts file
+pub(crate) async fn add_arts(
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    state: State<AppState>,
+    body: String,
+) -> AppResult<String> {
+    authorize(&headers)?;
+    let arts_file = loader::submission_file(&state.arts_path)?;
+    let list: Arc<str> = arts_file.as_str().into();
+
+    let mut report = String::new();
+    let mut accepted = Vec::new();
+    for entry in body
+        .lines()
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        match Art::parse(entry, state.sources) {
+            Ok(mut art) => {
+                art.list = Some(list.clone());
+                art.added_at = Some(SystemTime::now());
+                accepted.push(art);
+            }
+            Err(err) => report.push_str(&format!("rejected {entry}: {err}\n")),
+        }
+    }
+
+    // the file lock is taken first so the file and the loaded arts stay in the same order
+    let _file_lock = state.arts_file_lock.lock().unwrap();
+    let mut added_urls = Vec::new();
+    {
+        let mut data = state.data.lock().unwrap();
+        for art in accepted {
+            let url = art.url.to_string();
+            if data.is_blocked(&art.url) {
+                report.push_str(&format!("rejected {url}: it was removed before\n"));
+            } else if data.add(art) {
+                report.push_str(&format!("accepted {url}\n"));
+                added_urls.push(url);
+            } else {
+                report.push_str(&format!("rejected {url}: already added\n"));
+            }
+        }
+        if !added_urls.is_empty() {
+            added::save(&data);
+            db::save_arts(&state, &data);
+        }
+    }
+    if !added_urls.is_empty() {
+        append_lines(&arts_file, &added_urls)?;
+        tracing::info!(path = %arts_file, added = added_urls.len(), by = %client_ip, "added arts");
+    }
+    Ok(report)
+}
+
+#[derive(Serialize)]
+pub(crate) struct CachedLink {
+    url: String,
+    quality: String,
+    image_url: String,
+    age_secs: u64,
+}
+
+pub(crate) async fn show_cache(
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> AppResult<Json<Vec<CachedLink>>> {
+    authorize(&headers)?;
+    let mut links = state
+        .direct_links
+        .iter()
+        .map(|entry| {
+            let (url, quality) = entry.key();
+            CachedLink {
+                url: url.to_string(),
+                quality: quality.to_string(),
+                image_url: entry.value().image_url.clone(),
+                age_secs: entry.value().fetched_at.elapsed().as_secs(),
+            }
+        })
+        .collect::<Vec<_>>();
+    links.sort_by(|a, b| a.url.cmp(&b.url).then_with(|| a.quality.cmp(&b.quality)));
+    Ok(Json(links))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EvictQuery {
+    url: Option<String>,
+}
+
+// evicts every cached link for the url, or the whole cache if no url is given
+pub(crate) async fn evict_cache(
+    headers: HeaderMap,
+    Query(query): Query<EvictQuery>,
+    state: State<AppState>,
+) -> AppResult<String> {
+    authorize(&headers)?;
+    // cached links are keyed like arts, so any form of the link evicts them
+    let url = query
+        .url
+        .map(|url| data::canonical_url(&url, state.sources))
+        .transpose()
+        .map_err(|err| AppErrorKind::BadRequest(format!("invalid url: {err}")))?;
+    let evicted = shared_cache::evict_links(&state, url.as_ref()).await;
+    tracing::info!(evicted, "evicted cached links");
+    Ok(format!("evicted {evicted} cached links\n"))
+}
+
+// writes every arts list with the arts whose better source we know rewritten to it, keeping their
+// annotations. each list goes to a file named after it in EXPORT_PATH
+pub(crate) async fn export_sources(
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> AppResult<String> {
+    authorize(&headers)?;
+    let arts = state.data.lock().unwrap().arts().to_vec();
+    // only sources we can serve, otherwise the list wouldn't load anymore
+    let new_sources: HashMap<Uri, Uri> = state
+        .direct_links
+        .iter()
+        .filter_map(|entry| Some((entry.key().0.clone(), entry.value().new_source.clone()?)))
+        .filter_map(|(url, src)| Some((url, Art::parse(&src.to_string(), state.sources).ok()?.url)))
+        .collect();
+
+    let mut lists: Vec<(Arc<str>, Vec<String>)> = Vec::new();
+    let mut seen = HashSet::with_capacity(arts.len());
+    let mut rewritten = 0;
+    for mut art in arts {
+        if let Some(src) = new_sources.get(&art.url) {
+            art.url = src.clone();
+            rewritten += 1;
+        }
+        // a rewritten art can be the same as one that's already in a list
+        if !seen.insert(art.url.clone()) {
+            continue;
+        }
+        let list = art.list.clone().unwrap_or_else(|| "arts.txt".into());
+        let entry = art.entry(&list);
+        match lists.iter_mut().find(|(name, _)| *name == list) {
+            Some((_, entries)) => entries.push(entry),
+            None => lists.push((list, vec![entry])),
+        }
+    }
+
+    let dir = std::path::Path::new(&config().arts.export_path);
+    std::fs::create_dir_all(dir)?;
+    let mut report = String::new();
+    for (list, entries) in lists {
+        let path = dir.join(format!("{}.txt", data::collection_name(&list)));
+        let mut contents = entries.join("\n");
+        contents.push('\n');
+        std::fs::write(&path, contents)?;
+        report.push_str(&format!(
+            "{list}: {} arts to {}\n",
+            entries.len(),
+            path.display()
+        ));
+    }
+    tracing::info!(path = %dir.display(), rewritten, arts = seen.len(), "exported sources");
+    Ok(report)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RemoveQuery {
+    url: String,
+}
+
+// removes the art right away and blocklists it, so reloading the arts file doesn't bring it back
+pub(crate) async fn remove_art(
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Query(query): Query<RemoveQuery>,
+    state: State<AppState>,
+) -> AppResult<String> {
+    authorize(&headers)?;
+    let url = data::canonical_url(&query.url, state.sources)
+        .map_err(|err| AppErrorKind::BadRequest(format!("invalid url: {err}")))?;
+
+    let _file_lock = state.arts_file_lock.lock().unwrap();
+    state.data.lock().unwrap().remove(&url)?;
+    state
+        .direct_links
+        .retain(|(cached_url, _), _| cached_url != &url);
+    state.failed_links.remove(&url);
+    append_lines(&state.blocklist_path, &[url.to_string()])?;
+    db::block(&state, &url);
+    tracing::info!(art_url = %url, by = %client_ip, "removed art");
+    Ok(format!("removed {url}\n"))
+}
+
+fn append_lines(path: &str, lines: &[String]) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    // don't glue the first new url onto the last line if the file has no trailing newline
+    let mut last = [0u8];
+    let needs_newline = file.seek(SeekFrom::End(-1)).is_ok()
+        && file.read_exact(&mut last).is_ok()
+        && last[0] != b'\n';
+    let mut contents = String::new();
+    if needs_newline {
+        contents.push('\n');
+    }
+    for line in lines {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::TEST_ADMIN_TOKEN, sources, testing};
+
+    const ART: &str = "https://twitter.com/someone/status/1";
+    const NEW_ART: &str = "https://twitter.com/someone/status/2";
+
+    // the route is how reloading works where there are no signals, like on windows
+    #[tokio::test]
+    async fn reload_route_picks_up_new_arts() {
+        let dir = std::env::temp_dir().join(format!("limbusart-reload-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("arts.txt");
+        std::fs::write(&path, format!("{ART}\n")).unwrap();
+        crate::config::init_default();
+        let state = testing::state_from_file(sources::registry(), path.to_str().unwrap());
+        let app = testing::serve(&state).await;
+
+        std::fs::write(&path, format!("{ART}\n{NEW_ART}\n")).unwrap();
+        let http = reqwest::Client::new();
+        let unauthorized = http
+            .post(format!("{app}/admin/reload"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unauthorized.status(), 401);
+        let resp = http
+            .post(format!("{app}/admin/reload"))
+            .bearer_auth(TEST_ADMIN_TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.text().await.unwrap().starts_with("reloaded"));
+
+        let data = state.data.lock().unwrap();
+        assert_eq!(data.arts().len(), 2);
+        assert!(data.art_by_url(&NEW_ART.parse().unwrap()).is_some());
+        drop(data);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // links copied from twitter rarely look like the art in the list
+    #[tokio::test]
+    async fn arts_are_removed_by_any_form_of_their_url() {
+        let dir = std::env::temp_dir().join(format!("limbusart-remove-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("arts.txt");
+        std::fs::write(&path, format!("{ART}\n{NEW_ART}\n")).unwrap();
+        crate::config::init_default();
+        let state = testing::state_from_file(sources::registry(), path.to_str().unwrap());
+        let app = testing::serve(&state).await;
+
+        let resp = reqwest::Client::new()
+            .delete(format!("{app}/admin/arts"))
+            .query(&[("url", "https://X.com/someone/status/1/?s=20")])
+            .bearer_auth(TEST_ADMIN_TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let data = state.data.lock().unwrap();
+        assert!(data.art_by_url(&ART.parse().unwrap()).is_none());
+        assert_eq!(data.arts().len(), 1);
+        drop(data);
+        let blocklist = std::fs::read_to_string(dir.join("blocklist.txt")).unwrap();
+        assert_eq!(blocklist.trim(), ART);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
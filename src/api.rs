@@ -0,0 +1,504 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{
+    admin, caching,
+    config::config,
+    data::{Art, FetchedLink, ImageQuality, PickFilter},
+    date, distinct,
+    error::{AppError, AppErrorKind, AppResult},
+    get_image_link, parse_quality, permalink, proxy, resolve_random_art, AppState,
+};
+
+// only the api is meant to be called from other sites, so only it gets cors headers
+pub(crate) fn cors_layer() -> Result<Option<CorsLayer>, String> {
+    let origins = config().server.cors_allow_origins.trim();
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if origins == "*" {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| format!("CORS_ALLOW_ORIGINS has an invalid origin: {origin}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            // /api/resolve is a post, and takes the admin token when it needs one
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::AUTHORIZATION]),
+    ))
+}
+
+#[derive(Serialize)]
+pub(crate) struct ArtInfo {
+    id: usize,
+    image_url: String,
+    // signed, so it only works for images we resolved
+    download_url: String,
+    source: String,
+    kind: String,
+    description: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    // RFC 3339, missing for arts that were there before added dates were kept
+    added_at: Option<String>,
+    // where the art is among the arts it was picked from counting from 1, and how many there are
+    position: Option<usize>,
+    total: Option<usize>,
+}
+
+impl ArtInfo {
+    pub(crate) fn new(art_no: usize, art: &Art, image_link: &FetchedLink) -> Self {
+        Self {
+            id: art_no,
+            image_url: image_link.image_url.clone(),
+            download_url: proxy::download_path(
+                &image_link.image_url,
+                &proxy::download_name(art, art_no),
+            ),
+            source: image_link
+                .new_source
+                .as_ref()
+                .unwrap_or(&art.url)
+                .to_string(),
+            kind: art.kind.to_string(),
+            description: image_link.description.clone(),
+            width: image_link.width,
+            height: image_link.height,
+            added_at: art.added_at.map(date::format_rfc3339),
+            position: None,
+            total: None,
+        }
+    }
+
+    pub(crate) fn with_position(mut self, position: Option<(usize, usize)>) -> Self {
+        self.position = position.map(|(position, _)| position);
+        self.total = position.map(|(_, total)| total);
+        self
+    }
+}
+
+const DEFAULT_ARTS_PER_PAGE: usize = 50;
+const MAX_ARTS_PER_PAGE: usize = 200;
+
+#[derive(Deserialize)]
+pub(crate) struct ArtsQuery {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ArtEntry {
+    // a hash of the source url, stays the same across reloads unlike the index
+    id: String,
+    // the current index, what permalinks use
+    index: usize,
+    permalink: String,
+    source: String,
+    kind: String,
+    artist: Option<String>,
+    tags: Vec<String>,
+    collection: Option<String>,
+    // whether an image link is cached at the default quality, so fetching it is cheap
+    cached: bool,
+    added_at: Option<String>,
+}
+
+impl ArtEntry {
+    pub(crate) fn new(state: &AppState, art_no: usize, art: &Art) -> Self {
+        Self {
+            id: art.id(),
+            index: art_no,
+            permalink: permalink(art_no),
+            source: art.url.to_string(),
+            kind: art.kind.to_string(),
+            artist: art.artist().map(str::to_owned),
+            tags: art.tags.clone(),
+            collection: art.collection.as_deref().map(str::to_owned),
+            cached: state
+                .direct_links
+                .contains_key(&(art.url.clone(), state.default_quality)),
+            added_at: art.added_at.map(date::format_rfc3339),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ArtsPage {
+    page: usize,
+    per_page: usize,
+    total: usize,
+    page_count: usize,
+    arts: Vec<ArtEntry>,
+}
+
+// every art in list order, pages past the end are empty rather than an error
+pub(crate) async fn list_arts(
+    Query(query): Query<ArtsQuery>,
+    state: State<AppState>,
+) -> AppResult<Json<ArtsPage>> {
+    let page = query.page.unwrap_or(1);
+    if page == 0 {
+        return Err(AppError::from(AppErrorKind::BadRequest("pages start at 1".to_owned())).json());
+    }
+    let per_page = query.per_page.unwrap_or(DEFAULT_ARTS_PER_PAGE);
+    if per_page == 0 || per_page > MAX_ARTS_PER_PAGE {
+        return Err(AppError::from(AppErrorKind::BadRequest(format!(
+            "per_page has to be between 1 and {MAX_ARTS_PER_PAGE}"
+        )))
+        .json());
+    }
+
+    // only copy the page while locked, the cache is checked afterwards
+    let (total, arts) = {
+        let data = state.data.lock().unwrap();
+        let arts = data
+            .arts()
+            .iter()
+            .enumerate()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .map(|(art_no, art)| (art_no, art.clone()))
+            .collect::<Vec<_>>();
+        (data.arts().len(), arts)
+    };
+    Ok(Json(ArtsPage {
+        page,
+        per_page,
+        total,
+        page_count: total.div_ceil(per_page),
+        arts: arts
+            .iter()
+            .map(|(art_no, art)| ArtEntry::new(&state, *art_no, art))
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ArtQuery {
+    quality: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ResolvedArt {
+    #[serde(flatten)]
+    entry: ArtEntry,
+    image_url: String,
+    description: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+// a single art by its stable id, resolving its image link if needed
+pub(crate) async fn art_by_id(
+    Path(id): Path<String>,
+    Query(query): Query<ArtQuery>,
+    state: State<AppState>,
+) -> AppResult<Json<ResolvedArt>> {
+    let quality = parse_quality(&state, query.quality.as_deref()).map_err(AppError::json)?;
+    let art = state
+        .data
+        .lock()
+        .unwrap()
+        .art_by_id(&id)
+        .map(|(art_no, art)| (art_no, art.clone()));
+    let (art_no, art) = art.ok_or_else(|| {
+        AppError::from(AppErrorKind::NotFound(format!("no art with id {id}"))).json()
+    })?;
+    let image_link = get_image_link(&state, &art, quality)
+        .await
+        .map_err(AppError::json)?;
+    Ok(Json(ResolvedArt {
+        entry: ArtEntry::new(&state, art_no, &art),
+        image_url: image_link.image_url,
+        description: image_link.description,
+        width: image_link.width,
+        height: image_link.height,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ResolveQuery {
+    url: String,
+    quality: Option<String>,
+}
+
+// forgets the link is being resolved however resolving it ends
+struct Resolving<'a> {
+    state: &'a AppState,
+    cache_key: (Uri, ImageQuality),
+}
+
+impl Drop for Resolving<'_> {
+    fn drop(&mut self) {
+        self.state.resolving.remove(&self.cache_key);
+    }
+}
+
+// resolves an art without showing it, for warming the cache from outside
+// only arts in the list are resolved, otherwise anyone could make us fetch anything
+pub(crate) async fn resolve_art(
+    headers: HeaderMap,
+    Query(query): Query<ResolveQuery>,
+    state: State<AppState>,
+) -> AppResult<Json<ResolvedArt>> {
+    if config().server.resolve_requires_admin {
+        admin::authorize(&headers).map_err(AppError::json)?;
+    }
+    let quality = parse_quality(&state, query.quality.as_deref()).map_err(AppError::json)?;
+    let url = Art::parse(&query.url, state.sources)
+        .map_err(|err| err.status(StatusCode::BAD_REQUEST).json())?
+        .url;
+    let art = state
+        .data
+        .lock()
+        .unwrap()
+        .art_by_url(&url)
+        .map(|(art_no, art)| (art_no, art.clone()));
+    let (art_no, art) = art.ok_or_else(|| {
+        AppError::from(AppErrorKind::NotFound(format!(
+            "{url} is not in the art list"
+        )))
+        .json()
+    })?;
+
+    let cache_key = (art.url.clone(), quality);
+    if state.resolving.insert(cache_key.clone(), ()).is_some() {
+        let err = AppError::from("the art is already being resolved").status(StatusCode::CONFLICT);
+        return Err(err.json());
+    }
+    let _resolving = Resolving {
+        state: &state,
+        cache_key,
+    };
+    // a cached link is returned as is
+    let image_link = get_image_link(&state, &art, quality)
+        .await
+        .map_err(AppError::json)?;
+    Ok(Json(ResolvedArt {
+        entry: ArtEntry::new(&state, art_no, &art),
+        image_url: image_link.image_url,
+        description: image_link.description,
+        width: image_link.width,
+        height: image_link.height,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RandomQuery {
+    quality: Option<String>,
+    collection: Option<String>,
+    // picks with the same token in a short window never repeat
+    distinct: Option<String>,
+    // comma separated art kinds to pick from, or to never pick
+    source: Option<String>,
+    exclude: Option<String>,
+    // 1 to include nsfw arts, if the site allows opting in
+    nsfw: Option<String>,
+}
+
+// the art for a random query, honoring the distinct token if there is one
+async fn resolve_query(
+    state: &AppState,
+    query: &RandomQuery,
+    quality: ImageQuality,
+) -> AppResult<(usize, Art, FetchedLink)> {
+    let collection = query.collection.as_deref();
+    let filter = PickFilter::parse(
+        state.sources,
+        query.source.as_deref(),
+        query.exclude.as_deref(),
+        query.nsfw.as_deref(),
+    )?;
+    match query.distinct.as_deref() {
+        Some(token) => distinct::resolve_distinct_art(state, token, collection, quality).await,
+        None => resolve_random_art(state, collection, &filter, &[], quality).await,
+    }
+}
+
+pub(crate) async fn random_art(
+    Query(query): Query<RandomQuery>,
+    state: State<AppState>,
+) -> AppResult<Json<ArtInfo>> {
+    let quality = parse_quality(&state, query.quality.as_deref()).map_err(AppError::json)?;
+    let (art_no, art, image_link) = resolve_query(&state, &query, quality)
+        .await
+        .map_err(AppError::json)?;
+    let position = state
+        .data
+        .lock()
+        .unwrap()
+        .position(art_no, query.collection.as_deref());
+    Ok(Json(
+        ArtInfo::new(art_no, &art, &image_link).with_position(position),
+    ))
+}
+
+// just the image url as text, for bots and monitoring
+pub(crate) async fn random_url(
+    Query(query): Query<RandomQuery>,
+    state: State<AppState>,
+) -> AppResult<Response> {
+    let (art_no, image_url) = resolve_random_image_url(&state, query).await?;
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (http::header::CACHE_CONTROL, caching::NO_CACHE),
+        ],
+        [(ART_ID_HEADER, art_no.to_string())],
+        format!("{image_url}\n"),
+    )
+        .into_response())
+}
+
+// redirects to the image, so the url can be used as an img src elsewhere
+pub(crate) async fn random_redirect(
+    Query(query): Query<RandomQuery>,
+    state: State<AppState>,
+) -> AppResult<Response> {
+    let (art_no, image_url) = resolve_random_image_url(&state, query).await?;
+    Ok((
+        StatusCode::FOUND,
+        [
+            (http::header::LOCATION, image_url.as_str()),
+            (http::header::CACHE_CONTROL, caching::NO_CACHE),
+        ],
+        [(ART_ID_HEADER, art_no.to_string())],
+    )
+        .into_response())
+}
+
+// which art was picked, so embedders using distinct tokens can tell what they got
+const ART_ID_HEADER: &str = "x-limbusart-art-id";
+
+async fn resolve_random_image_url(
+    state: &AppState,
+    query: RandomQuery,
+) -> AppResult<(usize, String)> {
+    let quality = parse_quality(state, query.quality.as_deref()).map_err(AppError::plain)?;
+    let (art_no, art, image_link) = resolve_query(state, &query, quality)
+        .await
+        .map_err(AppError::plain)?;
+    state.serve_counts.record(&art.url);
+    Ok((art_no, image_link.image_url))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OEmbedQuery {
+    url: String,
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OEmbed {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    url: String,
+    title: String,
+    author_name: Option<String>,
+    author_url: String,
+    provider_name: String,
+    provider_url: Option<String>,
+}
+
+// see https://oembed.com, only json and the photo type are supported
+pub(crate) async fn oembed(
+    Query(query): Query<OEmbedQuery>,
+    state: State<AppState>,
+) -> AppResult<Json<OEmbed>> {
+    if query
+        .format
+        .as_deref()
+        .map_or(false, |format| format != "json")
+    {
+        return Err(AppError::from(AppErrorKind::BadRequest(
+            "only json is supported".to_owned(),
+        ))
+        .status(StatusCode::NOT_IMPLEMENTED)
+        .json());
+    }
+
+    let not_found = || AppError::from(AppErrorKind::NotFound("no such art".to_owned())).json();
+    let url: Uri = query.url.parse().map_err(|_| not_found())?;
+    let art_no: usize = url
+        .path()
+        .strip_prefix("/art/")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(not_found)?;
+    let art = state
+        .data
+        .lock()
+        .unwrap()
+        .art(art_no)
+        .cloned()
+        .ok_or_else(not_found)?;
+    let image_link = get_image_link(&state, &art, state.default_quality)
+        .await
+        .map_err(AppError::json)?;
+
+    let site = &config().site;
+    Ok(Json(OEmbed {
+        kind: "photo",
+        version: "1.0",
+        url: image_link.image_url.clone(),
+        title: image_link
+            .description
+            .clone()
+            .unwrap_or_else(|| art.url.to_string()),
+        author_name: image_link
+            .artist
+            .clone()
+            .or_else(|| art.artist().map(str::to_owned)),
+        author_url: image_link
+            .new_source
+            .as_ref()
+            .unwrap_or(&art.url)
+            .to_string(),
+        provider_name: site.title.clone(),
+        provider_url: (!site.url.is_empty()).then(|| site.url.clone()),
+    }))
+}
+
+#[derive(Serialize)]
+pub(crate) struct Version {
+    version: &'static str,
+    git_hash: Option<&'static str>,
+    started_at: u64,
+    uptime_secs: u64,
+    arts: usize,
+    cached_links: usize,
+}
+
+pub(crate) async fn version(state: State<AppState>) -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("GIT_HASH"),
+        started_at: state
+            .started_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        uptime_secs: state
+            .started_at
+            .elapsed()
+            .map_or(0, |uptime| uptime.as_secs()),
+        arts: state.data.lock().unwrap().arts().len(),
+        cached_links: state.direct_links.len(),
+    })
+}
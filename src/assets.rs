@@ -0,0 +1,80 @@
+use axum::response::IntoResponse;
+use http::header;
+
+use crate::{caching, config::config};
+
+const SPINNERS_CSS: &str = include_str!("../assets/spinners.css");
+const FONTS_CSS: &str = include_str!("../assets/fonts.css");
+// PT Mono from paratype, under the open font license, for visitors who don't have it installed
+const PT_MONO_WOFF2: &[u8] = include_bytes!("../assets/PTMono-Regular.woff2");
+const FAVICON_ICO: &[u8] = include_bytes!("../assets/favicon.ico");
+const FAVICON_PNG: &[u8] = include_bytes!("../assets/favicon.png");
+
+// the version is part of asset urls, so they can be cached forever
+pub(crate) fn asset_url(name: &str) -> String {
+    format!("/static/{name}?v={}", env!("CARGO_PKG_VERSION"))
+}
+
+pub(crate) async fn spinners_css() -> impl IntoResponse {
+    css(SPINNERS_CSS)
+}
+
+pub(crate) async fn fonts_css() -> impl IntoResponse {
+    css(FONTS_CSS)
+}
+
+pub(crate) async fn pt_mono_woff2() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "font/woff2"),
+            (header::CACHE_CONTROL, caching::LONG_CACHE),
+        ],
+        PT_MONO_WOFF2,
+    )
+}
+
+pub(crate) async fn favicon_ico() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "image/x-icon"),
+            (header::CACHE_CONTROL, caching::LONG_CACHE),
+        ],
+        FAVICON_ICO,
+    )
+}
+
+pub(crate) async fn favicon_png() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, caching::LONG_CACHE),
+        ],
+        FAVICON_PNG,
+    )
+}
+
+// every crawler hit on / is an upstream fetch, so crawling is opt-in
+pub(crate) async fn robots_txt() -> impl IntoResponse {
+    let body = if config().site.robots_allow {
+        "User-agent: *\nAllow: /\n"
+    } else {
+        "User-agent: *\nDisallow: /\n"
+    };
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CACHE_CONTROL, caching::LONG_CACHE),
+        ],
+        body,
+    )
+}
+
+fn css(body: &'static str) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+            (header::CACHE_CONTROL, caching::IMMUTABLE),
+        ],
+        body,
+    )
+}
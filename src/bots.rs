@@ -0,0 +1,117 @@
+use axum::response::{Html, IntoResponse};
+use http::{header, HeaderMap, HeaderValue};
+
+use crate::{
+    caching,
+    data::{Art, FetchedLink, ImageQuality, PickFilter},
+    error::AppResult,
+    get_art_meta, get_image_link, pick_art, AppState, MAX_SKIPPED_REROLLS,
+};
+
+// lowercased, matched anywhere in the user agent
+const PREVIEW_BOTS: &[&str] = &[
+    "discordbot",
+    "telegrambot",
+    "slackbot",
+    "twitterbot",
+    "facebookexternalhit",
+];
+
+// bots that fetch a page only to show a preview of a link to it
+pub(crate) fn is_preview_bot(headers: &HeaderMap) -> bool {
+    let Some(user_agent) = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let user_agent = user_agent.to_ascii_lowercase();
+    PREVIEW_BOTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+// a random art for a preview, preferring ones that are already resolved
+// a link is posted once but previewed by every bot, so none of them get retries
+pub(crate) async fn resolve_random_art(
+    state: &AppState,
+    collection: Option<&str>,
+    filter: &PickFilter,
+    quality: ImageQuality,
+) -> AppResult<(usize, Art, FetchedLink)> {
+    let mut picked = pick_art(state, collection, filter, &[], quality)?;
+    for _ in 0..MAX_SKIPPED_REROLLS {
+        let cached = state
+            .direct_links
+            .get(&(picked.1.url.clone(), quality))
+            .map(|image_link| image_link.clone());
+        if let Some(image_link) = cached {
+            return Ok((picked.0, picked.1, image_link));
+        }
+        picked = pick_art(state, collection, filter, &[], quality)?;
+    }
+    let (art_no, art) = picked;
+    let image_link = get_image_link(state, &art, quality).await?;
+    Ok((art_no, art, image_link))
+}
+
+// only the embed metadata, without fonts, styles or scripts the bot would fetch for nothing
+pub(crate) fn render_preview(
+    state: &AppState,
+    art_no: usize,
+    art: &Art,
+    image_link: &FetchedLink,
+    collection: Option<&str>,
+) -> axum::response::Response {
+    let total = state
+        .data
+        .lock()
+        .unwrap()
+        .position(art_no, collection)
+        .map(|(_, total)| total);
+    let content = maud::html! {
+        (maud::DOCTYPE)
+        head {
+            (get_art_meta(art_no, art, image_link, collection, total))
+        }
+    };
+    let mut resp = Html(content.into_string()).into_response();
+    // the same urls serve browsers the whole page
+    let headers = resp.headers_mut();
+    headers.insert(header::VARY, HeaderValue::from_static("user-agent"));
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(caching::NO_CACHE),
+    );
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(user_agent: &'static str) -> bool {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, HeaderValue::from_static(user_agent));
+        is_preview_bot(&headers)
+    }
+
+    #[test]
+    fn link_previewers_are_bots() {
+        for user_agent in [
+            "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)",
+            "TelegramBot (like TwitterBot)",
+            "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+            "Twitterbot/1.0",
+            "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+        ] {
+            assert!(from(user_agent), "{user_agent}");
+        }
+    }
+
+    #[test]
+    fn browsers_are_not_bots() {
+        assert!(!from(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"
+        ));
+        assert!(!is_preview_bot(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+// stops contacting upstream hosts for a while after they fail too many times in a row
+pub(crate) struct CircuitBreakers {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: DashMap<String, HostState>,
+}
+
+#[derive(Default)]
+struct HostState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreakers {
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            hosts: Default::default(),
+        }
+    }
+
+    pub(crate) fn is_open(&self, host: &str) -> bool {
+        let Some(mut state) = self.hosts.get_mut(host) else {
+            return false;
+        };
+        match state.open_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // let requests through again, but a single failure opens the breaker right away
+                state.open_until = None;
+                state.consecutive_failures = self.threshold.saturating_sub(1);
+                tracing::info!(host, "circuit breaker closed, trying upstream again");
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn record_success(&self, host: &str) {
+        if let Some(mut state) = self.hosts.get_mut(host) {
+            state.consecutive_failures = 0;
+        }
+    }
+
+    pub(crate) fn record_failure(&self, host: &str) {
+        let mut state = self.hosts.entry(host.to_owned()).or_default();
+        state.consecutive_failures += 1;
+        if state.open_until.is_none() && state.consecutive_failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            tracing::warn!(
+                host,
+                failures = state.consecutive_failures,
+                cooldown_secs = self.cooldown.as_secs(),
+                "circuit breaker opened, skipping upstream"
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use axum::{
+    http::HeaderMap,
+    response::{Html, IntoResponse},
+};
+use http::{header, HeaderValue, StatusCode};
+
+use crate::stable_hash;
+
+// for pages that are different on every request
+pub(crate) const NO_CACHE: &str = "no-cache";
+// for responses whose url already pins their content
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+// for responses that rarely change but live at a fixed url
+pub(crate) const LONG_CACHE: &str = "public, max-age=86400";
+
+// responds with the page, or a 304 if the client already has this exact page
+pub(crate) fn etag_response(
+    headers: &HeaderMap,
+    body: String,
+    cache_control: &'static str,
+) -> axum::response::Response {
+    let etag = format!("\"{:016x}\"", stable_hash(&body));
+    let not_modified = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*");
+
+    let mut resp = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Html(body).into_response()
+    };
+    let resp_headers = resp.headers_mut();
+    resp_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        resp_headers.insert(header::ETAG, etag);
+    }
+    resp
+}
@@ -0,0 +1,135 @@
+use axum::{
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header, StatusCode, Uri};
+
+use crate::{client_ip, config::config, EXEMPT_PATHS};
+
+// redirects to CANONICAL_HOST, and to https with FORCE_HTTPS, keeping the path and query
+pub(crate) async fn redirect_to_canonical(req: Request, next: Next) -> Response {
+    let server = &config().server;
+    if server.canonical_host.is_empty() && !server.force_https {
+        return next.run(req).await;
+    }
+
+    let scheme = request_scheme(&req);
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    match canonical_location(
+        &server.canonical_host,
+        server.force_https,
+        scheme,
+        host,
+        req.uri(),
+    ) {
+        Some(location) => (
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+        None => next.run(req).await,
+    }
+}
+
+// we never terminate tls ourselves, so it's plain http unless one of our proxies says otherwise
+fn request_scheme(req: &Request) -> &str {
+    if !client_ip::is_trusted_peer(req.extensions()) {
+        return "http";
+    }
+    req.headers()
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http")
+}
+
+// where the request should have gone, if it isn't there already
+fn canonical_location(
+    canonical_host: &str,
+    force_https: bool,
+    scheme: &str,
+    host: &str,
+    uri: &Uri,
+) -> Option<String> {
+    if EXEMPT_PATHS.contains(&uri.path()) {
+        return None;
+    }
+
+    let wanted_scheme = if force_https { "https" } else { scheme };
+    let wanted_host = if canonical_host.is_empty() {
+        host
+    } else {
+        canonical_host
+    };
+    if scheme == wanted_scheme && host.eq_ignore_ascii_case(wanted_host) {
+        return None;
+    }
+
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(format!("{wanted_scheme}://{wanted_host}{path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+
+    use super::*;
+
+    #[test]
+    fn redirects_keep_the_query() {
+        let uri = Uri::from_static("/art/3?quality=low&lang=ko");
+        assert_eq!(
+            canonical_location("art.example", true, "http", "other.example", &uri).as_deref(),
+            Some("https://art.example/art/3?quality=low&lang=ko")
+        );
+        assert_eq!(
+            canonical_location("", true, "http", "art.example", &uri).as_deref(),
+            Some("https://art.example/art/3?quality=low&lang=ko")
+        );
+    }
+
+    #[test]
+    fn direct_plain_http_requests_are_redirected() {
+        // anyone can claim to be on https, only trusted proxies are believed
+        let req = Request::builder()
+            .uri("/art/3?quality=low")
+            .header(header::HOST, "art.example")
+            .header("x-forwarded-proto", "https")
+            .extension(ConnectInfo(SocketAddr::from(([203, 0, 113, 5], 40000))))
+            .body(Body::empty())
+            .unwrap();
+        let scheme = request_scheme(&req);
+        assert_eq!(scheme, "http");
+        assert_eq!(
+            canonical_location("", true, scheme, "art.example", req.uri()).as_deref(),
+            Some("https://art.example/art/3?quality=low")
+        );
+    }
+
+    #[test]
+    fn canonical_requests_are_not_redirected() {
+        let uri = Uri::from_static("/?collection=limbus");
+        assert_eq!(
+            canonical_location("art.example", true, "https", "ART.example", &uri),
+            None
+        );
+    }
+
+    #[test]
+    fn exempt_paths_are_not_redirected() {
+        for path in EXEMPT_PATHS {
+            let uri: Uri = path.parse().unwrap();
+            assert_eq!(
+                canonical_location("art.example", true, "http", "other.example", &uri),
+                None
+            );
+        }
+    }
+}
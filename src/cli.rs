@@ -0,0 +1,182 @@
+use std::{
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand};
+use dashmap::DashMap;
+use futures_util::StreamExt;
+
+use crate::{
+    build_http_client, config::config, data, data::Art, data::ImageQuality, loader, sources,
+};
+
+#[derive(Parser)]
+#[command(version, about = "serves random art")]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Serve the site, this is the default
+    Serve,
+    /// Check an arts list for invalid lines
+    Validate {
+        /// The arts list to check, defaults to the configured one
+        path: Option<String>,
+        /// Also resolve every entry and report the ones that fail
+        #[arg(long)]
+        check_links: bool,
+    },
+    /// Resolve a single art url and print where its image is
+    Resolve {
+        url: String,
+        #[arg(long, value_parser = parse_quality)]
+        quality: Option<ImageQuality>,
+    },
+}
+
+// AppError isn't a std error, so clap can't use FromStr directly
+fn parse_quality(s: &str) -> Result<ImageQuality, String> {
+    s.parse()
+        .map_err(|err: crate::error::AppError| err.to_string())
+}
+
+pub(crate) async fn validate(path: Option<String>, check_links: bool) -> ExitCode {
+    let path = path.unwrap_or_else(|| config().arts.path.clone());
+    let http = match build_http_client() {
+        Ok(http) => http,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let lists = match loader::load_initial(&http, &path).await {
+        Ok(lists) => lists,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut failed = false;
+    let mut arts = Vec::new();
+    for list in &lists {
+        let (parsed, errors) = data::check_list(list, sources::registry());
+        if errors.is_empty() {
+            println!("{}: {} arts ok", list.name, parsed.len());
+        }
+        arts.extend(parsed);
+        for err in errors {
+            println!("{err}");
+            failed = true;
+        }
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+    if !check_links {
+        return ExitCode::SUCCESS;
+    }
+
+    if check_all_links(&http, arts).await {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+const CHECK_CONCURRENCY: usize = 4;
+// time between starting two requests to the same host
+const CHECK_HOST_DELAY: Duration = Duration::from_millis(500);
+
+// resolves every art through the real fetchers, returning whether all of them worked
+async fn check_all_links(http: &reqwest::Client, arts: Vec<Art>) -> bool {
+    let total = arts.len();
+    let done = AtomicUsize::new(0);
+    let last_request: DashMap<String, Arc<tokio::sync::Mutex<Option<Instant>>>> = DashMap::new();
+
+    let failures = futures_util::stream::iter(arts)
+        .map(|art| {
+            let (done, last_request) = (&done, &last_request);
+            async move {
+                let host = art.url.host().unwrap_or_default().to_owned();
+                let host_lock = last_request.entry(host).or_default().clone();
+                {
+                    // be polite and don't hit the same host too often
+                    let mut last = host_lock.lock().await;
+                    if let Some(wait) =
+                        last.map(|last| CHECK_HOST_DELAY.saturating_sub(last.elapsed()))
+                    {
+                        tokio::time::sleep(wait).await;
+                    }
+                    *last = Some(Instant::now());
+                }
+                let quality = config().arts.image_quality;
+                let result = sources::registry().fetch(http, &art, quality).await;
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                match result {
+                    Ok(_) => {
+                        println!("[{done}/{total}] ok {}", art.url);
+                        None
+                    }
+                    Err(err) => {
+                        println!("[{done}/{total}] failed {}: {err}", art.url);
+                        Some((art.url, err))
+                    }
+                }
+            }
+        })
+        .buffer_unordered(CHECK_CONCURRENCY)
+        .filter_map(|failure| async move { failure })
+        .collect::<Vec<_>>()
+        .await;
+
+    println!();
+    println!("{} ok, {} failed", total - failures.len(), failures.len());
+    for (url, err) in &failures {
+        println!("failed\t{url}\t{err}");
+    }
+    failures.is_empty()
+}
+
+pub(crate) async fn resolve(url: &str, quality: Option<ImageQuality>) -> ExitCode {
+    let art = match Art::parse(url, sources::registry()) {
+        Ok(art) => art,
+        Err(err) => {
+            eprintln!("invalid art url {url}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let http = match build_http_client() {
+        Ok(http) => http,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let quality = quality.unwrap_or(config().arts.image_quality);
+    match sources::registry().fetch(&http, &art, quality).await {
+        Ok(image_link) => {
+            println!("image: {}", image_link.image_url);
+            println!(
+                "source: {}",
+                image_link.new_source.as_ref().unwrap_or(&art.url)
+            );
+            if let Some(description) = image_link.description {
+                println!("description: {description}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("could not resolve {url}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
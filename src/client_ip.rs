@@ -0,0 +1,143 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap},
+};
+
+use crate::config::config;
+
+struct TrustedProxies(Vec<(IpAddr, u32)>);
+
+impl TrustedProxies {
+    fn parse(list: &str) -> Result<Self, String> {
+        let mut ranges = Vec::new();
+        for cidr in list
+            .split(',')
+            .map(str::trim)
+            .filter(|cidr| !cidr.is_empty())
+        {
+            let (addr, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+            let addr: IpAddr = addr
+                .parse()
+                .map_err(|err| format!("invalid address in {cidr}: {err}"))?;
+            let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                "" => max_prefix,
+                prefix => prefix
+                    .parse()
+                    .ok()
+                    .filter(|prefix| *prefix <= max_prefix)
+                    .ok_or_else(|| format!("invalid prefix length in {cidr}"))?,
+            };
+            ranges.push((addr, prefix));
+        }
+        Ok(Self(ranges))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|(addr, prefix)| match (addr, ip) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(*addr) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(*addr) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+}
+
+static TRUSTED_PROXIES: OnceLock<TrustedProxies> = OnceLock::new();
+
+fn parse_trusted_proxies() -> Result<TrustedProxies, String> {
+    TrustedProxies::parse(&config().server.trusted_proxies)
+        .map_err(|err| format!("TRUSTED_PROXIES is not valid: {err}"))
+}
+
+// init checks the list at startup, so without it nothing was configured to be trusted
+fn trusted_proxies() -> &'static TrustedProxies {
+    TRUSTED_PROXIES.get_or_init(|| TrustedProxies(Vec::new()))
+}
+
+// fails startup with the config error if the proxy list is bad
+pub(crate) fn init() -> Result<(), String> {
+    let _ = TRUSTED_PROXIES.set(parse_trusted_proxies()?);
+    Ok(())
+}
+
+// whether the request came straight from one of our trusted proxies
+pub(crate) fn is_trusted_peer(extensions: &Extensions) -> bool {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(addr)| trusted_proxies().contains(addr.ip()))
+}
+
+// the connecting address, or what our trusted proxies say the client is
+fn resolve(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())?;
+    let trusted = trusted_proxies();
+    if !trusted.contains(peer) {
+        return Some(peer);
+    }
+
+    let real_ip = headers
+        .get("x-real-ip")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    if real_ip.is_some() {
+        return real_ip;
+    }
+    // everything left of the last untrusted hop could have been made up by the client
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded_for
+        .iter()
+        .rev()
+        .find(|hop| !trusted.contains(**hop))
+        .or(forwarded_for.first())
+        .copied()
+        .or(Some(peer))
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct ClientIp(pub(crate) Option<IpAddr>);
+
+impl ClientIp {
+    pub(crate) fn from_request(req: &axum::extract::Request) -> Self {
+        Self(resolve(req.headers(), req.extensions()))
+    }
+}
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(ip) => ip.fmt(f),
+            None => f.write_str("<unknown ip>"),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(resolve(&parts.headers, &parts.extensions)))
+    }
+}
@@ -0,0 +1,527 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::data::{ImageQuality, NsfwMode, PickMode, TwitterImageFormat};
+
+// everything can be set in the config file, and overridden with the env var next to it
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    // BIND
+    pub(crate) bind: String,
+    // LOG_FORMAT, compact or json
+    pub(crate) log_format: String,
+    // TRUSTED_PROXIES, comma separated CIDRs
+    pub(crate) trusted_proxies: String,
+    // ADMIN_TOKEN, admin routes are disabled without one
+    pub(crate) admin_token: String,
+    // PROXY_SIGNING_KEY, signs /img and /download links, a random one is made at startup if empty
+    pub(crate) proxy_signing_key: String,
+    // RESOLVE_REQUIRES_ADMIN, whether POST /api/resolve needs the admin token
+    pub(crate) resolve_requires_admin: bool,
+    // DEBUG_ERRORS, shows internal errors to visitors
+    pub(crate) debug_errors: bool,
+    // CANONICAL_HOST, requests for other hosts are redirected to it
+    pub(crate) canonical_host: String,
+    // FORCE_HTTPS, redirects plain http requests to https, proxies in TRUSTED_PROXIES can say
+    // a request already was https with x-forwarded-proto
+    pub(crate) force_https: bool,
+    // CORS_ALLOW_ORIGINS, comma separated origins or * that can call /api from browsers
+    pub(crate) cors_allow_origins: String,
+    // MAX_CONCURRENT_REQUESTS, requests over it wait for a slot
+    pub(crate) max_concurrent_requests: usize,
+    // MAX_WAITING_REQUESTS, requests over it get a 503 right away
+    pub(crate) max_waiting_requests: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:3000".to_owned(),
+            log_format: "compact".to_owned(),
+            trusted_proxies: String::new(),
+            admin_token: String::new(),
+            proxy_signing_key: String::new(),
+            resolve_requires_admin: true,
+            debug_errors: false,
+            canonical_host: String::new(),
+            force_https: false,
+            cors_allow_origins: String::new(),
+            max_concurrent_requests: 256,
+            max_waiting_requests: 512,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SiteConfig {
+    // SITE_TITLE
+    pub(crate) title: String,
+    // SITE_URL, needed for anything that has to link back to us absolutely
+    pub(crate) url: String,
+    // USE_CDN, loads fonts and spinners from third party CDNs
+    pub(crate) use_cdn: bool,
+    // ROBOTS_ALLOW
+    pub(crate) robots_allow: bool,
+    // PUBLIC_LIST, serves /list.txt
+    pub(crate) public_list: bool,
+    // PROXY_IMAGES
+    pub(crate) proxy_images: bool,
+    // PUBLIC_HISTORY, serves /recent, nothing is kept about served arts without it
+    pub(crate) public_history: bool,
+    // CSP_EXTRA_IMG_HOSTS
+    pub(crate) csp_extra_img_hosts: String,
+    // FRAME_ANCESTORS, the sites that can put /daily in an iframe
+    pub(crate) frame_ancestors: String,
+    // CONTACT_TEXT, the footer lines, the original author's contact if unset
+    pub(crate) contact_text: Option<String>,
+    // CONTACT_URL, where the footer links to, empty for no link
+    pub(crate) contact_url: String,
+    // CUSTOM_CSS_PATH, a stylesheet added to every page
+    pub(crate) custom_css_path: Option<String>,
+    // BG_COLOR
+    pub(crate) bg_color: String,
+    // TEXT_COLOR
+    pub(crate) text_color: String,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            title: "random project moon art".to_owned(),
+            url: String::new(),
+            use_cdn: false,
+            robots_allow: false,
+            public_list: true,
+            proxy_images: false,
+            public_history: true,
+            csp_extra_img_hosts: String::new(),
+            frame_ancestors: "*".to_owned(),
+            contact_text: None,
+            contact_url: "https://gaze.systems".to_owned(),
+            custom_css_path: None,
+            bg_color: "#0e0e0e".to_owned(),
+            text_color: "#ffffff".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct EmbedConfig {
+    // EMBED_TITLE
+    pub(crate) title: String,
+    // EMBED_DESC
+    pub(crate) description: String,
+    // EMBED_COLOR
+    pub(crate) color: String,
+    // EMBED_TITLE_{collection}
+    pub(crate) collection_titles: HashMap<String, String>,
+}
+
+impl Default for EmbedConfig {
+    fn default() -> Self {
+        Self {
+            title: "random project moon art".to_owned(),
+            description: "random project moon art".to_owned(),
+            color: "#ffffff".to_owned(),
+            collection_titles: HashMap::new(),
+        }
+    }
+}
+
+impl EmbedConfig {
+    pub(crate) fn title(&self, collection: Option<&str>) -> String {
+        let Some(collection) = collection else {
+            return self.title.clone();
+        };
+        std::env::var(format!("EMBED_TITLE_{collection}"))
+            .ok()
+            .or_else(|| self.collection_titles.get(collection).cloned())
+            .unwrap_or_else(|| self.title.clone())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ArtsConfig {
+    // ARTS_PATH
+    pub(crate) path: String,
+    // BLOCKLIST_PATH
+    pub(crate) blocklist_path: String,
+    // ARTS_REFRESH_SECS
+    pub(crate) refresh_secs: Option<u64>,
+    // PICK_MODE
+    pub(crate) pick_mode: PickMode,
+    // IMAGE_QUALITY
+    pub(crate) image_quality: ImageQuality,
+    // STATS_PATH
+    pub(crate) stats_path: Option<String>,
+    // ADDED_PATH, where the dates arts were added are kept
+    pub(crate) added_path: Option<String>,
+    // DB_PATH, a sqlite db kept instead of the stats and added dates files
+    pub(crate) db_path: Option<String>,
+    // EXPORT_PATH, the directory /admin/export-sources writes the lists to
+    pub(crate) export_path: String,
+    // SERVE_NSFW, whether arts annotated nsfw are served: always, never or opt-in
+    pub(crate) serve_nsfw: NsfwMode,
+    // HISTORY_SIZE, how many served arts /recent remembers
+    pub(crate) history_size: usize,
+}
+
+impl Default for ArtsConfig {
+    fn default() -> Self {
+        Self {
+            path: "./utils/arts.txt".to_owned(),
+            blocklist_path: "./utils/blocklist.txt".to_owned(),
+            refresh_secs: None,
+            pick_mode: PickMode::Random,
+            image_quality: ImageQuality::Sample,
+            stats_path: None,
+            added_path: None,
+            db_path: None,
+            export_path: "./utils/exported".to_owned(),
+            serve_nsfw: NsfwMode::OptIn,
+            history_size: 100,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CacheConfig {
+    // WARMUP
+    pub(crate) warmup: usize,
+    // HEALTH_CHECK_INTERVAL_SECS
+    pub(crate) health_check_interval_secs: Option<u64>,
+    // HEALTH_CHECK_MAX_FAILURES
+    pub(crate) health_check_max_failures: usize,
+    // STATS_LOG_INTERVAL_SECS, how often to log the cache and failure counters, 0 to never
+    pub(crate) stats_log_interval_secs: Option<u64>,
+    // DEGRADED_FAILURE_RATE, the share of recent fetches from an upstream that have to fail
+    // before cached arts are preferred, above 1 to never
+    pub(crate) degraded_failure_rate: f64,
+    // QUARANTINE_THRESHOLD, failures in a row before an art isn't picked for a while
+    pub(crate) quarantine_threshold: u32,
+    // REVALIDATE_INTERVAL_SECS, time between re-checking two cached image links
+    pub(crate) revalidate_interval_secs: Option<u64>,
+    // LINK_TTL_SECS, how old a cached link can get before the revalidator resolves it again
+    pub(crate) link_ttl_secs: Option<u64>,
+    // REDIS_URL, shares resolved links with other instances through redis
+    pub(crate) redis_url: Option<String>,
+    // REDIS_TTL_SECS, how long links are kept in redis
+    pub(crate) redis_ttl_secs: u64,
+    // PREVIEWS, downloads every resolved image once to show a placeholder while it loads
+    pub(crate) previews: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            warmup: 0,
+            health_check_interval_secs: None,
+            health_check_max_failures: 3,
+            stats_log_interval_secs: Some(600),
+            degraded_failure_rate: 0.5,
+            quarantine_threshold: 3,
+            revalidate_interval_secs: None,
+            link_ttl_secs: None,
+            redis_url: None,
+            redis_ttl_secs: 86400,
+            previews: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct UpstreamConfig {
+    // UPSTREAM_USER_AGENT
+    pub(crate) user_agent: Option<String>,
+    // UPSTREAM_PROXY
+    pub(crate) proxy: Option<String>,
+    // UPSTREAM_ACCEPT_INVALID_CERTS
+    pub(crate) accept_invalid_certs: bool,
+    // BREAKER_THRESHOLD
+    pub(crate) breaker_threshold: u32,
+    // BREAKER_COOLDOWN_SECS
+    pub(crate) breaker_cooldown_secs: u64,
+    // FETCH_TIMEOUT_SECS, including the wait for a concurrency slot
+    pub(crate) fetch_timeout_secs: u64,
+    // SOURCE_CONCURRENCY, concurrent fetches per source for sources without their own limit
+    pub(crate) source_concurrency: usize,
+    // concurrent fetches for specific sources, by name
+    pub(crate) source_concurrency_overrides: HashMap<String, usize>,
+    // FXTWITTER_URL, where twitter image links are resolved
+    pub(crate) fxtwitter_url: String,
+    // FXTWITTER_API_URL, where tweet texts are fetched from
+    pub(crate) fxtwitter_api_url: String,
+    // SAFEBOORU_URL, where safebooru posts are looked up
+    pub(crate) safebooru_url: String,
+    // SAFEBOORU_MAX_RETRIES
+    pub(crate) safebooru_max_retries: u32,
+    // TWITTER_IMAGE_FORMAT, webp is the cheapest
+    pub(crate) twitter_image_format: TwitterImageFormat,
+    // PIXIV_PROXIES, mirrors for pixiv images to try in order, separated by commas
+    pub(crate) pixiv_proxies: String,
+    // EXTRA_BOORUS, hosts of other dapi boorus to accept arts from, separated by commas
+    pub(crate) extra_boorus: String,
+    // FEDI_INSTANCES, misskey and mastodon instances to accept posts from, separated by commas
+    pub(crate) fedi_instances: String,
+    // TUMBLR_API_KEY, tumblr posts are scraped from their page without one
+    pub(crate) tumblr_api_key: Option<String>,
+    // IMGUR_CLIENT_ID, imgur albums are scraped from their page without one
+    pub(crate) imgur_client_id: Option<String>,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: None,
+            proxy: None,
+            accept_invalid_certs: false,
+            breaker_threshold: 5,
+            breaker_cooldown_secs: 60,
+            fetch_timeout_secs: 30,
+            source_concurrency: 4,
+            // safebooru throttles quickly, fxtwitter copes fine with more
+            source_concurrency_overrides: HashMap::from([
+                ("safebooru".to_owned(), 2),
+                ("twitter".to_owned(), 8),
+            ]),
+            fxtwitter_url: "https://d.fxtwitter.com".to_owned(),
+            fxtwitter_api_url: "https://api.fxtwitter.com".to_owned(),
+            safebooru_url: "https://safebooru.org".to_owned(),
+            safebooru_max_retries: 5,
+            twitter_image_format: TwitterImageFormat::Webp,
+            pixiv_proxies: "i.pixiv.re,i.pixiv.cat".to_owned(),
+            extra_boorus: String::new(),
+            fedi_instances: String::new(),
+            tumblr_api_key: None,
+            imgur_client_id: None,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    pub(crate) fn extra_boorus(&self) -> impl Iterator<Item = &str> {
+        self.extra_boorus
+            .split([',', ' '])
+            .filter(|host| !host.is_empty())
+    }
+
+    pub(crate) fn pixiv_proxies(&self) -> impl Iterator<Item = &str> {
+        self.pixiv_proxies
+            .split([',', ' '])
+            .filter(|host| !host.is_empty())
+    }
+
+    pub(crate) fn fedi_instances(&self) -> impl Iterator<Item = &str> {
+        self.fedi_instances
+            .split([',', ' '])
+            .filter(|host| !host.is_empty())
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) server: ServerConfig,
+    pub(crate) site: SiteConfig,
+    pub(crate) embed: EmbedConfig,
+    pub(crate) arts: ArtsConfig,
+    pub(crate) cache: CacheConfig,
+    pub(crate) upstream: UpstreamConfig,
+}
+
+fn env_override<T: FromStr>(name: &str, value: &mut T) -> Result<(), String>
+where
+    T::Err: Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        *value = raw
+            .parse()
+            .map_err(|err| format!("{name}={raw} is not valid: {err}"))?;
+    }
+    Ok(())
+}
+
+fn env_override_opt<T: FromStr>(name: &str, value: &mut Option<T>) -> Result<(), String>
+where
+    T::Err: Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        let parsed = raw
+            .parse()
+            .map_err(|err| format!("{name}={raw} is not valid: {err}"))?;
+        *value = Some(parsed);
+    }
+    Ok(())
+}
+
+// flags used to be set with 1 and 0, so keep accepting those
+fn env_flag(name: &str, value: &mut bool) -> Result<(), String> {
+    if let Ok(raw) = std::env::var(name) {
+        *value = match raw.as_str() {
+            "1" | "true" => true,
+            "0" | "false" | "" => false,
+            _ => return Err(format!("{name}={raw} is not valid: expected 1 or 0")),
+        };
+    }
+    Ok(())
+}
+
+impl Config {
+    // reads CONFIG_PATH if it's set, then applies the env vars on top
+    fn load() -> Result<Self, String> {
+        let mut config = match std::env::var("CONFIG_PATH") {
+            Ok(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("could not read config file {path}: {err}"))?;
+                // toml errors already point at the offending key and line
+                toml::from_str(&contents)
+                    .map_err(|err| format!("invalid config file {path}: {err}"))?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), String> {
+        let Self {
+            server,
+            site,
+            embed,
+            arts,
+            cache,
+            upstream,
+        } = self;
+        env_override("BIND", &mut server.bind)?;
+        env_override("LOG_FORMAT", &mut server.log_format)?;
+        env_override("TRUSTED_PROXIES", &mut server.trusted_proxies)?;
+        env_override("ADMIN_TOKEN", &mut server.admin_token)?;
+        env_override("PROXY_SIGNING_KEY", &mut server.proxy_signing_key)?;
+        env_flag("RESOLVE_REQUIRES_ADMIN", &mut server.resolve_requires_admin)?;
+        env_flag("DEBUG_ERRORS", &mut server.debug_errors)?;
+        env_override("CANONICAL_HOST", &mut server.canonical_host)?;
+        env_flag("FORCE_HTTPS", &mut server.force_https)?;
+        env_override("CORS_ALLOW_ORIGINS", &mut server.cors_allow_origins)?;
+        env_override(
+            "MAX_CONCURRENT_REQUESTS",
+            &mut server.max_concurrent_requests,
+        )?;
+        env_override("MAX_WAITING_REQUESTS", &mut server.max_waiting_requests)?;
+
+        env_override("SITE_TITLE", &mut site.title)?;
+        env_override("SITE_URL", &mut site.url)?;
+        env_flag("USE_CDN", &mut site.use_cdn)?;
+        env_flag("ROBOTS_ALLOW", &mut site.robots_allow)?;
+        env_flag("PUBLIC_LIST", &mut site.public_list)?;
+        env_flag("PROXY_IMAGES", &mut site.proxy_images)?;
+        env_flag("PUBLIC_HISTORY", &mut site.public_history)?;
+        env_override("CSP_EXTRA_IMG_HOSTS", &mut site.csp_extra_img_hosts)?;
+        env_override("FRAME_ANCESTORS", &mut site.frame_ancestors)?;
+        env_override_opt("CONTACT_TEXT", &mut site.contact_text)?;
+        env_override("CONTACT_URL", &mut site.contact_url)?;
+        env_override_opt("CUSTOM_CSS_PATH", &mut site.custom_css_path)?;
+        env_override("BG_COLOR", &mut site.bg_color)?;
+        env_override("TEXT_COLOR", &mut site.text_color)?;
+
+        env_override("EMBED_TITLE", &mut embed.title)?;
+        env_override("EMBED_DESC", &mut embed.description)?;
+        env_override("EMBED_COLOR", &mut embed.color)?;
+
+        env_override("ARTS_PATH", &mut arts.path)?;
+        env_override("BLOCKLIST_PATH", &mut arts.blocklist_path)?;
+        env_override_opt("ARTS_REFRESH_SECS", &mut arts.refresh_secs)?;
+        env_override("PICK_MODE", &mut arts.pick_mode)?;
+        env_override("SERVE_NSFW", &mut arts.serve_nsfw)?;
+        env_override("IMAGE_QUALITY", &mut arts.image_quality)?;
+        env_override_opt("STATS_PATH", &mut arts.stats_path)?;
+        env_override_opt("ADDED_PATH", &mut arts.added_path)?;
+        env_override_opt("DB_PATH", &mut arts.db_path)?;
+        env_override("EXPORT_PATH", &mut arts.export_path)?;
+        env_override("HISTORY_SIZE", &mut arts.history_size)?;
+
+        env_override("WARMUP", &mut cache.warmup)?;
+        env_override_opt(
+            "HEALTH_CHECK_INTERVAL_SECS",
+            &mut cache.health_check_interval_secs,
+        )?;
+        env_override(
+            "HEALTH_CHECK_MAX_FAILURES",
+            &mut cache.health_check_max_failures,
+        )?;
+        env_override_opt(
+            "STATS_LOG_INTERVAL_SECS",
+            &mut cache.stats_log_interval_secs,
+        )?;
+        env_override("DEGRADED_FAILURE_RATE", &mut cache.degraded_failure_rate)?;
+        env_override("QUARANTINE_THRESHOLD", &mut cache.quarantine_threshold)?;
+        env_override_opt(
+            "REVALIDATE_INTERVAL_SECS",
+            &mut cache.revalidate_interval_secs,
+        )?;
+        env_override_opt("LINK_TTL_SECS", &mut cache.link_ttl_secs)?;
+        env_override_opt("REDIS_URL", &mut cache.redis_url)?;
+        env_override("REDIS_TTL_SECS", &mut cache.redis_ttl_secs)?;
+        env_flag("PREVIEWS", &mut cache.previews)?;
+
+        env_override_opt("UPSTREAM_USER_AGENT", &mut upstream.user_agent)?;
+        env_override_opt("UPSTREAM_PROXY", &mut upstream.proxy)?;
+        env_flag(
+            "UPSTREAM_ACCEPT_INVALID_CERTS",
+            &mut upstream.accept_invalid_certs,
+        )?;
+        env_override("BREAKER_THRESHOLD", &mut upstream.breaker_threshold)?;
+        env_override("BREAKER_COOLDOWN_SECS", &mut upstream.breaker_cooldown_secs)?;
+        env_override("FETCH_TIMEOUT_SECS", &mut upstream.fetch_timeout_secs)?;
+        env_override("SOURCE_CONCURRENCY", &mut upstream.source_concurrency)?;
+        env_override("FXTWITTER_URL", &mut upstream.fxtwitter_url)?;
+        env_override("FXTWITTER_API_URL", &mut upstream.fxtwitter_api_url)?;
+        env_override("SAFEBOORU_URL", &mut upstream.safebooru_url)?;
+        env_override("SAFEBOORU_MAX_RETRIES", &mut upstream.safebooru_max_retries)?;
+        env_override("TWITTER_IMAGE_FORMAT", &mut upstream.twitter_image_format)?;
+        env_override("PIXIV_PROXIES", &mut upstream.pixiv_proxies)?;
+        env_override("EXTRA_BOORUS", &mut upstream.extra_boorus)?;
+        env_override("FEDI_INSTANCES", &mut upstream.fedi_instances)?;
+        env_override_opt("TUMBLR_API_KEY", &mut upstream.tumblr_api_key)?;
+        env_override_opt("IMGUR_CLIENT_ID", &mut upstream.imgur_client_id)?;
+        Ok(())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// must be called before anything reads the config
+pub(crate) fn init() -> Result<(), String> {
+    let config = Config::load()?;
+    if CONFIG.set(config).is_err() {
+        panic!("config was initialized twice");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) const TEST_ADMIN_TOKEN: &str = "test";
+
+// the defaults with admin routes on, for tests that don't care about the config
+#[cfg(test)]
+pub(crate) fn init_default() {
+    CONFIG.get_or_init(|| Config {
+        server: ServerConfig {
+            admin_token: TEST_ADMIN_TOKEN.to_owned(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+}
+
+// global rather than in the app state, since error pages are rendered without any state
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get().expect("config is not initialized yet")
+}
@@ -1,23 +1,188 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 
 use http::Uri;
+use serde::Deserialize;
 
-use crate::error::{AppError, AppResult};
+use crate::{
+    config::config,
+    error::{AppError, AppErrorKind, AppResult},
+    preview::Preview,
+    revalidate::Validators,
+    sources::{ArtSource, Registry},
+};
 
-#[derive(Clone)]
-pub(crate) enum ArtKind {
-    Twitter,
-    Safebooru,
+// which source an art is from, named after it
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ArtKind(&'static str);
+
+impl Display for ArtKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
 }
 
-impl FromStr for ArtKind {
+impl ArtKind {
+    pub(crate) fn parse(s: &str, sources: &Registry) -> AppResult<Self> {
+        sources
+            .all()
+            .find(|source| source.name() == s)
+            .map(|source| Self(source.name()))
+            .ok_or_else(|| format!("unknown source kind: {s}").into())
+    }
+}
+
+// which arts random picks can come from
+pub(crate) struct PickFilter {
+    // empty means every kind
+    include: Vec<ArtKind>,
+    exclude: Vec<ArtKind>,
+    nsfw: bool,
+}
+
+impl Default for PickFilter {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            nsfw: config().arts.serve_nsfw.allows(None),
+        }
+    }
+}
+
+impl PickFilter {
+    // the kinds are comma separated lists
+    pub(crate) fn parse(
+        sources: &Registry,
+        include: Option<&str>,
+        exclude: Option<&str>,
+        nsfw: Option<&str>,
+    ) -> AppResult<Self> {
+        let kinds = |list: Option<&str>| {
+            list.unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|kind| !kind.is_empty())
+                .map(|kind| {
+                    ArtKind::parse(kind, sources)
+                        .map_err(|err: AppError| err.status(http::StatusCode::BAD_REQUEST))
+                })
+                .collect::<AppResult<Vec<ArtKind>>>()
+        };
+        Ok(Self {
+            include: kinds(include)?,
+            exclude: kinds(exclude)?,
+            nsfw: config().arts.serve_nsfw.allows(nsfw),
+        })
+    }
+
+    pub(crate) fn allows(&self, art: &Art) -> bool {
+        (self.include.is_empty() || self.include.contains(&art.kind))
+            && !self.exclude.contains(&art.kind)
+            && (self.nsfw || !art.nsfw)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum NsfwMode {
+    Always,
+    Never,
+    // only when asked for with ?nsfw=1
+    OptIn,
+}
+
+impl NsfwMode {
+    // whether nsfw arts can be shown, given the request's nsfw parameter
+    pub(crate) fn allows(self, requested: Option<&str>) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::OptIn => matches!(requested, Some("1" | "true")),
+        }
+    }
+}
+
+impl FromStr for NsfwMode {
     type Err = AppError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "twitter.com" => Ok(Self::Twitter),
-            "safebooru.org" => Ok(Self::Safebooru),
-            _ => Err("not support website".into()),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            "opt-in" => Ok(Self::OptIn),
+            _ => Err(format!("unknown nsfw mode: {s}").into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageQuality {
+    #[serde(alias = "orig")]
+    Original,
+    Sample,
+    // the most compressed variant there is, for slow connections
+    Low,
+}
+
+impl FromStr for ImageQuality {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" | "orig" => Ok(Self::Original),
+            "sample" => Ok(Self::Sample),
+            "low" => Ok(Self::Low),
+            _ => Err(format!("unknown image quality: {s}").into()),
+        }
+    }
+}
+
+impl Display for ImageQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Original => f.write_str("original"),
+            Self::Sample => f.write_str("sample"),
+            Self::Low => f.write_str("low"),
+        }
+    }
+}
+
+// what format twitter serves images in
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TwitterImageFormat {
+    Webp,
+    #[serde(alias = "jpeg")]
+    Jpg,
+    Png,
+}
+
+impl FromStr for TwitterImageFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webp" => Ok(Self::Webp),
+            "jpg" | "jpeg" => Ok(Self::Jpg),
+            "png" => Ok(Self::Png),
+            _ => Err(format!("unknown twitter image format: {s}").into()),
+        }
+    }
+}
+
+impl Display for TwitterImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Webp => f.write_str("webp"),
+            Self::Jpg => f.write_str("jpg"),
+            Self::Png => f.write_str("png"),
         }
     }
 }
@@ -26,52 +191,568 @@ impl FromStr for ArtKind {
 pub(crate) struct Art {
     pub(crate) url: Uri,
     pub(crate) kind: ArtKind,
+    // the source the url is from, which fetches it
+    pub(crate) source: &'static dyn ArtSource,
+    // when this art was first seen, unknown for arts that were there before we kept track
+    pub(crate) added_at: Option<SystemTime>,
+    // the arts list this art was loaded from
+    pub(crate) list: Option<Arc<str>>,
+    // the collection this art can be picked from on its own
+    pub(crate) collection: Option<Arc<str>>,
+    // from the artist= annotation, for sources where the url doesn't tell
+    pub(crate) artist: Option<String>,
+    // from the tags= annotation, separated by commas
+    pub(crate) tags: Vec<String>,
+    // from the nsfw annotation, only served to visitors who opted in
+    pub(crate) nsfw: bool,
+}
+
+impl Art {
+    // the annotated artist, or the one the url tells
+    pub(crate) fn artist(&self) -> Option<&str> {
+        if let Some(artist) = &self.artist {
+            return Some(artist);
+        }
+        self.source.artist(&self.url)
+    }
+
+    // whether the source url, artist or tags contain the already lowercased query
+    pub(crate) fn matches(&self, query: &str) -> bool {
+        self.url.to_string().to_lowercase().contains(query)
+            || self
+                .artist()
+                .map_or(false, |artist| artist.to_lowercase().contains(query))
+            || self
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(query))
+    }
+
+    // unlike the index, this doesn't change when arts are added or removed
+    pub(crate) fn id(&self) -> String {
+        format!("{:016x}", crate::stable_hash(&self.url.to_string()))
+    }
+
+    // the art as an arts list line, with the annotations it was loaded with
+    pub(crate) fn entry(&self, list: &str) -> String {
+        let mut entry = self.url.to_string();
+        if let Some(collection) = self
+            .collection
+            .as_ref()
+            .filter(|collection| **collection != collection_name(list))
+        {
+            entry.push_str(&format!(" collection={collection}"));
+        }
+        if let Some(artist) = &self.artist {
+            entry.push_str(&format!(" artist={artist}"));
+        }
+        if !self.tags.is_empty() {
+            entry.push_str(&format!(" tags={}", self.tags.join(",")));
+        }
+        if self.nsfw {
+            entry.push_str(" nsfw");
+        }
+        entry
+    }
+
+    // the art for the url, if one of the sources can serve it
+    pub(crate) fn parse(s: &str, sources: &'static Registry) -> AppResult<Self> {
+        let url = normalize_url(s.parse()?)?;
+        let host = url.host().ok_or("url has no host")?;
+        let source = sources.get(host).ok_or("not support website")?;
+        if !source.accepts(&url) {
+            return Err(format!("not a {} post url", source.name()).into());
+        }
+        let kind = ArtKind(source.name());
+        let url = source.canonical(url);
+
+        Ok(Self {
+            url,
+            kind,
+            source,
+            added_at: None,
+            list: None,
+            collection: None,
+            artist: None,
+            tags: Vec::new(),
+            nsfw: false,
+        })
+    }
+}
+
+// hosts are case insensitive and trailing slashes don't change the page, so these would only
+// split one art into several cache keys. sources clean up the rest in their canonical
+fn normalize_url(url: Uri) -> Result<Uri, AppError> {
+    let host = url.host().ok_or("url has no host")?.to_ascii_lowercase();
+    let scheme = url.scheme_str().unwrap_or("https");
+    let port = url
+        .port_u16()
+        .map(|port| format!(":{port}"))
+        .unwrap_or_default();
+    let path = url.path().trim_end_matches('/');
+    let query = url
+        .query()
+        .map(|query| format!("?{query}"))
+        .unwrap_or_default();
+    Ok(format!("{scheme}://{host}{port}{path}{query}").parse()?)
+}
+
+// the url arts are keyed by, the same however the link is written, like Art::parse makes it
+pub(crate) fn canonical_url(s: &str, sources: &Registry) -> AppResult<Uri> {
+    let url = normalize_url(s.parse()?)?;
+    let host = url.host().ok_or("url has no host")?;
+    Ok(match sources.get(host) {
+        Some(source) => source.canonical(url),
+        None => url,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PickMode {
+    // every pick is independently random
+    Random,
+    // every art is served once before any art repeats
+    Shuffle,
 }
 
-impl FromStr for Art {
+impl FromStr for PickMode {
     type Err = AppError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let url: Uri = s.parse()?;
-        let kind: ArtKind = url.authority().unwrap().host().parse()?;
+        match s {
+            "random" => Ok(Self::Random),
+            "shuffle" => Ok(Self::Shuffle),
+            _ => Err(format!("unknown pick mode: {s}").into()),
+        }
+    }
+}
+
+const MAX_REROLLS: usize = 5;
+
+// the contents of one arts file, named so errors can point at it
+pub(crate) struct ArtList {
+    pub(crate) name: Arc<str>,
+    pub(crate) contents: String,
+}
+
+// arts are in the collection named after their list file, like ruina for ruina.txt
+pub(crate) fn collection_name(list: &str) -> Arc<str> {
+    let name = std::path::Path::new(list)
+        .file_stem()
+        .map_or_else(|| list.into(), |stem| stem.to_string_lossy());
+    Arc::from(name.as_ref())
+}
 
-        Ok(Self { url, kind })
+// an art url, optionally followed by annotations like collection=ruina, artist=someone,
+// tags=ryoshu,outis or nsfw
+fn parse_entry(
+    sources: &'static Registry,
+    list: &Arc<str>,
+    line: usize,
+    entry: &str,
+) -> AppResult<Art> {
+    let invalid = |reason: String| {
+        AppError::from(AppErrorKind::InvalidArtEntry {
+            list: list.to_string(),
+            line: line + 1,
+            reason,
+        })
+    };
+    let mut parts = entry.split_whitespace();
+    let mut art = Art::parse(parts.next().unwrap_or_default(), sources)
+        .map_err(|err| invalid(err.to_string()))?;
+    art.list = Some(list.clone());
+    art.collection = Some(collection_name(list));
+    for annotation in parts {
+        if annotation == "nsfw" {
+            art.nsfw = true;
+            continue;
+        }
+        match annotation.split_once('=') {
+            Some(("collection", name)) if !name.is_empty() => art.collection = Some(name.into()),
+            Some(("artist", artist)) if !artist.is_empty() => art.artist = Some(artist.to_owned()),
+            Some(("tags", tags)) => art.tags.extend(
+                tags.split(',')
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_owned),
+            ),
+            _ => return Err(invalid(format!("unknown annotation {annotation}"))),
+        }
+    }
+    Ok(art)
+}
+
+// the arts in the list and every invalid line, instead of stopping at the first one like
+// parsing does
+pub(crate) fn check_list(list: &ArtList, sources: &'static Registry) -> (Vec<Art>, Vec<AppError>) {
+    let mut arts = Vec::new();
+    let mut errors = Vec::new();
+    for (line, entry) in list.contents.lines().enumerate() {
+        match parse_entry(sources, &list.name, line, entry) {
+            Ok(art) => arts.push(art),
+            Err(err) => errors.push(err),
+        }
     }
+    (arts, errors)
+}
+
+// parses every list before adding anything, so a bad line doesn't leave arts half loaded
+fn parse_lists(lists: &[ArtList], sources: &'static Registry) -> AppResult<Vec<Vec<Art>>> {
+    lists
+        .iter()
+        .map(|list| {
+            list.contents
+                .lines()
+                .enumerate()
+                .map(|(line, entry)| parse_entry(sources, &list.name, line, entry))
+                .collect()
+        })
+        .collect()
+}
+
+// one url per line, lines that aren't urls are ignored
+pub(crate) fn parse_blocklist(data: &str, sources: &Registry) -> HashSet<Uri> {
+    data.lines()
+        .filter_map(|line| canonical_url(line.trim(), sources).ok())
+        .collect()
 }
 
 pub(crate) struct Data {
     // actual arts
     art: Vec<Art>,
     art_indices: HashMap<Uri, usize>,
+    // removed arts, which are never added back
+    blocked: HashSet<Uri>,
+    // arts that failed the health check too many times
+    unhealthy: HashSet<Uri>,
+    pick_mode: PickMode,
+    // indices of arts not yet served in this cycle, used in shuffle mode
+    shuffle_bag: Vec<usize>,
+    // indices of the arts in each collection
+    collections: HashMap<Arc<str>, Vec<usize>>,
+    // what reloaded lists are parsed with
+    sources: &'static Registry,
 }
 
 impl Data {
-    pub(crate) fn parse(data: &str) -> AppResult<Self> {
+    pub(crate) fn parse(
+        lists: &[ArtList],
+        sources: &'static Registry,
+        pick_mode: PickMode,
+        blocked: HashSet<Uri>,
+        added_at: &HashMap<Uri, SystemTime>,
+    ) -> AppResult<Self> {
         let mut this = Self {
             art: Default::default(),
             art_indices: Default::default(),
+            blocked,
+            unhealthy: Default::default(),
+            pick_mode,
+            shuffle_bag: Default::default(),
+            collections: Default::default(),
+            sources,
         };
 
-        for entry in data.lines() {
-            let art: Art = entry.parse()?;
-            this.art_indices.insert(art.url.clone(), this.art.len());
-            this.art.push(art);
+        this.add_lists(lists, |url| added_at.get(url).copied())?;
+
+        if this.art.is_empty() {
+            return Err(AppErrorKind::NoArtConfigured.into());
         }
 
         Ok(this)
     }
 
-    pub(crate) fn pick_random_art(&self) -> &Art {
-        let no = fastrand::usize(0..self.art.len());
-        &self.art[no]
+    pub(crate) fn has_collection(&self, collection: &str) -> bool {
+        self.collections.contains_key(collection)
     }
 
-    pub(crate) fn reload(&mut self, data: &str) -> AppResult<()> {
-        for entry in data.lines() {
-            let art: Art = entry.parse()?;
-            if !self.art_indices.contains_key(&art.url) {
-                self.art_indices.insert(art.url.clone(), self.art.len());
-                self.art.push(art);
+    // where the art is among all arts or the collection's counting from 1, and how many there are
+    pub(crate) fn position(
+        &self,
+        art_no: usize,
+        collection: Option<&str>,
+    ) -> Option<(usize, usize)> {
+        match collection {
+            Some(collection) => {
+                let indices = self.collections.get(collection)?;
+                let position = indices.iter().position(|index| *index == art_no)?;
+                Some((position + 1, indices.len()))
+            }
+            None => (art_no < self.art.len()).then(|| (art_no + 1, self.art.len())),
+        }
+    }
+
+    // picks a random art, re-rolling a few times if it was recently served
+    pub(crate) fn pick_random_art(
+        &mut self,
+        collection: Option<&str>,
+        recent: &[usize],
+    ) -> (usize, &Art) {
+        let picked = collection
+            .and_then(|collection| self.collections.get(collection))
+            .map(|indices| self.pick_from_collection(indices, recent));
+        if let Some(no) = picked {
+            return (no, &self.art[no]);
+        }
+        let no = match self.pick_mode {
+            // the shuffle bag already avoids repeats
+            PickMode::Shuffle => self.pop_shuffled_index(),
+            PickMode::Random => {
+                let mut no = self.pick_random_index();
+                for _ in 0..MAX_REROLLS {
+                    if !recent.contains(&no) {
+                        break;
+                    }
+                    no = self.pick_random_index();
+                }
+                no
+            }
+        };
+        (no, &self.art[no])
+    }
+
+    // whether picks need to go through pick_filtered_art, which doesn't use the shuffle bag
+    pub(crate) fn needs_filtering(&self, filter: &PickFilter) -> bool {
+        !filter.include.is_empty()
+            || !filter.exclude.is_empty()
+            || (!filter.nsfw && self.art.iter().any(|art| art.nsfw))
+    }
+
+    // picks uniformly from the allowed arts, none if there are no such arts
+    pub(crate) fn pick_filtered_art(
+        &self,
+        collection: Option<&str>,
+        filter: &PickFilter,
+        recent: &[usize],
+    ) -> Option<(usize, &Art)> {
+        let candidates = match collection {
+            Some(collection) => self.collections.get(collection)?.clone(),
+            None => (0..self.art.len()).collect(),
+        };
+        let matching = candidates
+            .into_iter()
+            .filter(|no| filter.allows(&self.art[*no]))
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        let no = self.pick_from_collection(&matching, recent);
+        Some((no, &self.art[no]))
+    }
+
+    // picks uniformly from the arts that weren't served yet, preferring healthy ones
+    pub(crate) fn pick_unseen_art(
+        &self,
+        collection: Option<&str>,
+        served: &HashSet<Uri>,
+    ) -> Option<(usize, &Art)> {
+        let candidates = match collection {
+            Some(collection) => self.collections.get(collection)?.clone(),
+            None => (0..self.art.len()).collect(),
+        };
+        let unseen = candidates
+            .into_iter()
+            .filter(|no| !served.contains(&self.art[*no].url))
+            .collect::<Vec<_>>();
+        let healthy = unseen
+            .iter()
+            .copied()
+            .filter(|no| !self.unhealthy.contains(&self.art[*no].url))
+            .collect::<Vec<_>>();
+        let candidates = if healthy.is_empty() { unseen } else { healthy };
+        if candidates.is_empty() {
+            return None;
+        }
+        let no = candidates[fastrand::usize(0..candidates.len())];
+        Some((no, &self.art[no]))
+    }
+
+    // collections are picked from independently of the shuffle bag, which is for the whole pool
+    fn pick_from_collection(&self, indices: &[usize], recent: &[usize]) -> usize {
+        let healthy = indices
+            .iter()
+            .copied()
+            .filter(|no| !self.unhealthy.contains(&self.art[*no].url))
+            .collect::<Vec<_>>();
+        let candidates = if healthy.is_empty() {
+            indices
+        } else {
+            &healthy
+        };
+        let mut no = candidates[fastrand::usize(0..candidates.len())];
+        for _ in 0..MAX_REROLLS {
+            if !recent.contains(&no) {
+                break;
+            }
+            no = candidates[fastrand::usize(0..candidates.len())];
+        }
+        no
+    }
+
+    // picks an art purely based on the seed, so the same seed always gets the same art
+    pub(crate) fn pick_seeded_art(&self, seed: u64) -> (usize, &Art) {
+        let no = fastrand::Rng::with_seed(seed).usize(0..self.art.len());
+        (no, &self.art[no])
+    }
+
+    fn pop_shuffled_index(&mut self) -> usize {
+        let all_unhealthy = self.unhealthy.len() >= self.art.len();
+        loop {
+            if self.shuffle_bag.is_empty() {
+                self.shuffle_bag = (0..self.art.len()).collect();
+                fastrand::shuffle(&mut self.shuffle_bag);
+            }
+            let no = self
+                .shuffle_bag
+                .pop()
+                .expect("there are no arts to pick from");
+            // skip arts that were removed or became unhealthy since the bag was filled
+            if no < self.art.len() && (all_unhealthy || !self.unhealthy.contains(&self.art[no].url))
+            {
+                return no;
+            }
+        }
+    }
+
+    fn pick_random_index(&self) -> usize {
+        let healthy_count = self.art.len() - self.unhealthy.len();
+        if self.unhealthy.is_empty() || healthy_count == 0 {
+            return fastrand::usize(0..self.art.len());
+        }
+        let no = fastrand::usize(0..healthy_count);
+        self.art
+            .iter()
+            .enumerate()
+            .filter(|(_, art)| !self.unhealthy.contains(&art.url))
+            .nth(no)
+            .map(|(index, _)| index)
+            .unwrap()
+    }
+
+    pub(crate) fn art(&self, no: usize) -> Option<&Art> {
+        self.art.get(no)
+    }
+
+    pub(crate) fn art_by_url(&self, url: &Uri) -> Option<(usize, &Art)> {
+        let no = *self.art_indices.get(url)?;
+        Some((no, &self.art[no]))
+    }
+
+    pub(crate) fn arts(&self) -> &[Art] {
+        &self.art
+    }
+
+    pub(crate) fn art_by_id(&self, id: &str) -> Option<(usize, &Art)> {
+        self.art.iter().enumerate().find(|(_, art)| art.id() == id)
+    }
+
+    pub(crate) fn set_healthy(&mut self, url: &Uri, healthy: bool) {
+        if healthy {
+            self.unhealthy.remove(url);
+        } else if self.art_indices.contains_key(url) {
+            self.unhealthy.insert(url.clone());
+        }
+    }
+
+    pub(crate) fn unhealthy(&self) -> impl Iterator<Item = &Uri> {
+        self.unhealthy.iter()
+    }
+
+    // arts that are new since the last load count as added now, returns how many there were
+    pub(crate) fn reload(&mut self, lists: &[ArtList]) -> AppResult<usize> {
+        let now = SystemTime::now();
+        self.add_lists(lists, |_| Some(now))
+    }
+
+    fn add_lists(
+        &mut self,
+        lists: &[ArtList],
+        added_at: impl Fn(&Uri) -> Option<SystemTime>,
+    ) -> AppResult<usize> {
+        let parsed = parse_lists(lists, self.sources)?;
+        let mut total_added = 0;
+        for (list, arts) in lists.iter().zip(parsed) {
+            let entries = arts.len();
+            let added = arts
+                .into_iter()
+                .map(|mut art| {
+                    art.added_at = added_at(&art.url);
+                    self.add(art)
+                })
+                .filter(|added| *added)
+                .count();
+            // duplicates and blocked arts are skipped, this makes it visible how many
+            tracing::info!(list = %list.name, entries, added, "loaded arts list");
+            total_added += added;
+        }
+        Ok(total_added)
+    }
+
+    // adds the art unless it's already there or blocked, returning whether it was added
+    pub(crate) fn add(&mut self, art: Art) -> bool {
+        if self.art_indices.contains_key(&art.url) || self.blocked.contains(&art.url) {
+            return false;
+        }
+        // new arts should still be served in the current shuffle cycle
+        if self.pick_mode == PickMode::Shuffle {
+            let pos = fastrand::usize(0..=self.shuffle_bag.len());
+            self.shuffle_bag.insert(pos, self.art.len());
+        }
+        if let Some(collection) = &art.collection {
+            self.collections
+                .entry(collection.clone())
+                .or_default()
+                .push(self.art.len());
+        }
+        self.art_indices.insert(art.url.clone(), self.art.len());
+        self.art.push(art);
+        true
+    }
+
+    pub(crate) fn is_blocked(&self, url: &Uri) -> bool {
+        self.blocked.contains(url)
+    }
+
+    // removes the art and blocks it from being added again
+    pub(crate) fn remove(&mut self, url: &Uri) -> AppResult<()> {
+        let no = *self
+            .art_indices
+            .get(url)
+            .ok_or_else(|| AppErrorKind::NotFound(format!("{url} is not in the art list")))?;
+        if self.art.len() == 1 {
+            return Err(AppErrorKind::BadRequest("can't remove the last art".to_owned()).into());
+        }
+
+        let art = self.art.remove(no);
+        self.art_indices.remove(url);
+        self.unhealthy.remove(url);
+        self.blocked.insert(url.clone());
+        // everything after the removed art moved down by one
+        for index in self.art_indices.values_mut() {
+            if *index > no {
+                *index -= 1;
+            }
+        }
+        self.shuffle_bag.retain(|index| *index != no);
+        for index in &mut self.shuffle_bag {
+            if *index > no {
+                *index -= 1;
+            }
+        }
+        if let Some(collection) = &art.collection {
+            if let Some(indices) = self.collections.get_mut(collection) {
+                indices.retain(|index| *index != no);
+                if indices.is_empty() {
+                    self.collections.remove(collection);
+                }
+            }
+        }
+        for indices in self.collections.values_mut() {
+            for index in indices {
+                if *index > no {
+                    *index -= 1;
+                }
             }
         }
         Ok(())
@@ -82,4 +763,121 @@ impl Data {
 pub(crate) struct FetchedLink {
     pub(crate) image_url: String,
     pub(crate) new_source: Option<Uri>,
+    // alt text for the image, from booru tags or tweet text
+    pub(crate) description: Option<String>,
+    // the size of the image, if the upstream told us
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    // for sources that only know the artist after fetching
+    pub(crate) artist: Option<String>,
+    // filled in after the link is cached, see preview::spawn
+    pub(crate) preview: Option<Preview>,
+    // for asking the upstream whether the link changed, see revalidate.rs
+    pub(crate) validators: Option<Validators>,
+    pub(crate) fetched_at: Instant,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::async_trait;
+
+    use super::*;
+
+    // serves art.test/<artist>/<id> urls, so parsing doesn't depend on the builtin sources
+    struct MockSource;
+
+    #[async_trait]
+    impl ArtSource for MockSource {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn hosts(&self) -> &[&'static str] {
+            &["art.test"]
+        }
+
+        fn image_hosts(&self) -> &[&'static str] {
+            &["img.art.test"]
+        }
+
+        fn accepts(&self, url: &Uri) -> bool {
+            url.path()
+                .split('/')
+                .filter(|part| !part.is_empty())
+                .count()
+                == 2
+        }
+
+        fn artist<'a>(&self, url: &'a Uri) -> Option<&'a str> {
+            url.path().split('/').nth(1)
+        }
+
+        async fn fetch(
+            &self,
+            _http: &reqwest::Client,
+            _url: &Uri,
+            _quality: ImageQuality,
+        ) -> AppResult<FetchedLink> {
+            Err("mock arts can't be fetched".into())
+        }
+    }
+
+    fn mock_registry() -> &'static Registry {
+        Box::leak(Box::new(Registry::new(vec![Box::new(MockSource)])))
+    }
+
+    #[test]
+    fn parses_arts_with_the_given_sources() {
+        let sources = mock_registry();
+        let art = Art::parse("https://ART.test/someone/1/", sources).unwrap();
+        assert_eq!(art.url, "https://art.test/someone/1");
+        assert_eq!(art.kind.to_string(), "mock");
+        assert_eq!(art.artist(), Some("someone"));
+
+        assert!(Art::parse("https://art.test/someone", sources).is_err());
+        // builtin sources aren't in the mock registry
+        assert!(Art::parse("https://safebooru.org/index.php?id=1", sources).is_err());
+    }
+
+    #[test]
+    fn annotated_artist_wins() {
+        let sources = mock_registry();
+        let list = ArtList {
+            name: "ruina.txt".into(),
+            contents: "https://art.test/someone/1 artist=else tags=a,b nsfw\n".to_owned(),
+        };
+        let data = Data::parse(
+            &[list],
+            sources,
+            PickMode::Random,
+            HashSet::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let art = data.art(0).unwrap();
+        assert_eq!(art.artist(), Some("else"));
+        assert_eq!(art.tags, ["a", "b"]);
+        assert!(art.nsfw);
+        assert_eq!(art.collection.as_deref(), Some("ruina"));
+    }
+
+    #[test]
+    fn entries_keep_their_annotations() {
+        let sources = mock_registry();
+        let list: Arc<str> = "ruina.txt".into();
+        let line = "https://art.test/someone/1 collection=other artist=else tags=a,b nsfw";
+        let art = parse_entry(sources, &list, 0, line).unwrap();
+        assert_eq!(art.entry(&list), line);
+
+        let plain = parse_entry(sources, &list, 0, "https://art.test/someone/2").unwrap();
+        assert_eq!(plain.entry(&list), "https://art.test/someone/2");
+    }
+
+    #[test]
+    fn filters_kinds_of_the_given_sources() {
+        crate::config::init_default();
+        let sources = mock_registry();
+        assert!(PickFilter::parse(sources, Some("mock"), None, None).is_ok());
+        assert!(PickFilter::parse(sources, None, Some("mock, twitter"), None).is_err());
+    }
 }
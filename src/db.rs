@@ -0,0 +1,289 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use http::Uri;
+use rusqlite::{params, Connection};
+
+use crate::{
+    data::{Data, FetchedLink, ImageQuality},
+    AppState,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS arts (
+    url TEXT PRIMARY KEY,
+    kind TEXT,
+    artist TEXT,
+    tags TEXT NOT NULL DEFAULT '',
+    added_at INTEGER,
+    healthy INTEGER NOT NULL DEFAULT 1,
+    blocked INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS links (
+    url TEXT NOT NULL,
+    quality TEXT NOT NULL,
+    image_url TEXT NOT NULL,
+    description TEXT,
+    width INTEGER,
+    height INTEGER,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (url, quality)
+);
+CREATE TABLE IF NOT EXISTS counters (
+    url TEXT PRIMARY KEY,
+    served INTEGER NOT NULL
+);
+";
+
+// runtime state that used to live in sidecar files, the arts lists still decide which arts exist
+pub(crate) struct Db {
+    conn: Mutex<Connection>,
+}
+
+// what the db had at startup
+#[derive(Default)]
+pub(crate) struct Saved {
+    pub(crate) added_at: HashMap<Uri, SystemTime>,
+    pub(crate) blocked: HashSet<Uri>,
+    pub(crate) unhealthy: Vec<Uri>,
+    pub(crate) serve_counts: HashMap<Uri, u64>,
+    pub(crate) links: Vec<((Uri, ImageQuality), FetchedLink)>,
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+fn from_unix_secs(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+impl Db {
+    pub(crate) fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub(crate) fn load(&self) -> rusqlite::Result<Saved> {
+        let conn = self.conn.lock().unwrap();
+        let mut saved = Saved::default();
+
+        let mut stmt = conn.prepare("SELECT url, added_at, healthy, blocked FROM arts")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, bool>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (url, added_at, healthy, blocked) = row?;
+            let Ok(url) = url.parse::<Uri>() else {
+                continue;
+            };
+            if let Some(added_at) = added_at {
+                saved.added_at.insert(url.clone(), from_unix_secs(added_at));
+            }
+            if blocked {
+                saved.blocked.insert(url);
+            } else if !healthy {
+                saved.unhealthy.push(url);
+            }
+        }
+
+        let mut stmt = conn.prepare("SELECT url, served FROM counters")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        for row in rows {
+            let (url, served) = row?;
+            if let Ok(url) = url.parse() {
+                saved.serve_counts.insert(url, served.max(0) as u64);
+            }
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT url, quality, image_url, description, width, height, fetched_at FROM links",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                FetchedLink {
+                    image_url: row.get(2)?,
+                    new_source: None,
+                    description: row.get(3)?,
+                    width: row.get(4)?,
+                    height: row.get(5)?,
+                    artist: None,
+                    preview: None,
+                    validators: None,
+                    // instants can't be stored, so the age is carried over instead
+                    fetched_at: {
+                        let age = SystemTime::now()
+                            .duration_since(from_unix_secs(row.get(6)?))
+                            .unwrap_or_default();
+                        Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+                    },
+                },
+            ))
+        })?;
+        for row in rows {
+            let (url, quality, link) = row?;
+            if let (Ok(url), Ok(quality)) = (url.parse(), quality.parse()) {
+                saved.links.push(((url, quality), link));
+            }
+        }
+        Ok(saved)
+    }
+
+    // brings the arts table in line with the loaded arts, only blocked arts are kept after
+    // leaving the lists so they stay blocked
+    fn save_arts(&self, data: &Data) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let unhealthy: HashSet<&Uri> = data.unhealthy().collect();
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO arts (url, kind, artist, tags, added_at, healthy, blocked)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0)
+                 ON CONFLICT (url) DO UPDATE SET kind = excluded.kind, artist = excluded.artist,
+                    tags = excluded.tags, added_at = COALESCE(excluded.added_at, arts.added_at),
+                    healthy = excluded.healthy, blocked = 0",
+            )?;
+            for art in data.arts() {
+                upsert.execute(params![
+                    art.url.to_string(),
+                    art.kind.to_string(),
+                    art.artist(),
+                    art.tags.join(","),
+                    art.added_at.map(unix_secs),
+                    !unhealthy.contains(&art.url),
+                ])?;
+            }
+
+            let current: HashSet<String> =
+                data.arts().iter().map(|art| art.url.to_string()).collect();
+            let stored = tx
+                .prepare("SELECT url FROM arts WHERE blocked = 0")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let mut delete = tx.prepare("DELETE FROM arts WHERE url = ?1")?;
+            for url in stored.iter().filter(|url| !current.contains(*url)) {
+                delete.execute([url])?;
+            }
+        }
+        tx.commit()
+    }
+
+    fn set_healthy(&self, url: &Uri, healthy: bool) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE arts SET healthy = ?2 WHERE url = ?1 AND healthy != ?2",
+            params![url.to_string(), healthy],
+        )?;
+        Ok(())
+    }
+
+    fn block(&self, url: &Uri) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO arts (url, blocked) VALUES (?1, 1)
+             ON CONFLICT (url) DO UPDATE SET blocked = 1",
+            [url.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn clear_links(&self, url: Option<&Uri>) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        match url {
+            Some(url) => conn.execute("DELETE FROM links WHERE url = ?1", [url.to_string()])?,
+            None => conn.execute("DELETE FROM links", [])?,
+        };
+        Ok(())
+    }
+
+    // counters and links change on nearly every request, so they are written all at once
+    fn save_snapshot(&self, state: &AppState) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO counters (url, served) VALUES (?1, ?2)
+                 ON CONFLICT (url) DO UPDATE SET served = excluded.served",
+            )?;
+            for (url, served) in state.serve_counts.counts() {
+                upsert.execute(params![url.to_string(), served as i64])?;
+            }
+
+            tx.execute("DELETE FROM links", [])?;
+            let mut insert = tx.prepare(
+                "INSERT INTO links (url, quality, image_url, description, width, height, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for entry in state.direct_links.iter() {
+                let ((url, quality), link) = entry.pair();
+                insert.execute(params![
+                    url.to_string(),
+                    quality.to_string(),
+                    link.image_url,
+                    link.description,
+                    link.width,
+                    link.height,
+                    unix_secs(SystemTime::now() - link.fetched_at.elapsed()),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+// failing to write only loses what changed, so it's logged and serving goes on
+
+pub(crate) fn save_arts(state: &AppState, data: &Data) {
+    if let Some(db) = &state.db {
+        if let Err(err) = db.save_arts(data) {
+            tracing::error!(error = %err, "could not save arts to the db");
+        }
+    }
+}
+
+pub(crate) fn set_healthy(state: &AppState, url: &Uri, healthy: bool) {
+    if let Some(db) = &state.db {
+        if let Err(err) = db.set_healthy(url, healthy) {
+            tracing::error!(art_url = %url, error = %err, "could not save art health to the db");
+        }
+    }
+}
+
+pub(crate) fn block(state: &AppState, url: &Uri) {
+    if let Some(db) = &state.db {
+        if let Err(err) = db.block(url) {
+            tracing::error!(art_url = %url, error = %err, "could not save blocked art to the db");
+        }
+    }
+}
+
+pub(crate) fn clear_links(state: &AppState, url: Option<&Uri>) {
+    if let Some(db) = &state.db {
+        if let Err(err) = db.clear_links(url) {
+            tracing::error!(error = %err, "could not clear links in the db");
+        }
+    }
+}
+
+pub(crate) fn save_snapshot(state: &AppState) {
+    if let Some(db) = &state.db {
+        match db.save_snapshot(state) {
+            Ok(()) => tracing::info!("saved serve counts and links to the db"),
+            Err(err) => tracing::error!(error = %err, "could not save serve counts and links"),
+        }
+    }
+}
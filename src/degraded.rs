@@ -0,0 +1,76 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::data::ArtKind;
+
+// how far back fetches count towards the failure rate
+const WINDOW: Duration = Duration::from_secs(300);
+// a few failed fetches don't say much about an upstream
+const MIN_FETCHES: usize = 10;
+
+// tracks how often fetches for each kind failed recently, so picks can prefer cached arts
+// while an upstream is struggling
+pub(crate) struct DegradedKinds {
+    threshold: f64,
+    kinds: DashMap<ArtKind, KindState>,
+}
+
+#[derive(Default)]
+struct KindState {
+    // when each recent fetch happened, and whether it failed
+    fetches: VecDeque<(Instant, bool)>,
+    degraded: bool,
+}
+
+impl KindState {
+    fn last_fetch_is_recent(&self) -> bool {
+        self.fetches
+            .back()
+            .map_or(false, |(at, _)| at.elapsed() < WINDOW)
+    }
+}
+
+impl DegradedKinds {
+    pub(crate) fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            kinds: Default::default(),
+        }
+    }
+
+    pub(crate) fn record(&self, kind: ArtKind, failed: bool) {
+        let mut state = self.kinds.entry(kind).or_default();
+        state.fetches.push_back((Instant::now(), failed));
+        while state
+            .fetches
+            .front()
+            .map_or(false, |(at, _)| at.elapsed() >= WINDOW)
+        {
+            state.fetches.pop_front();
+        }
+
+        let fetches = state.fetches.len();
+        let failures = state.fetches.iter().filter(|(_, failed)| *failed).count();
+        let degraded = fetches >= MIN_FETCHES && failures as f64 / fetches as f64 >= self.threshold;
+        if degraded == state.degraded {
+            return;
+        }
+        state.degraded = degraded;
+        if degraded {
+            tracing::warn!(%kind, failures, fetches, "upstream is degraded, preferring cached arts");
+        } else {
+            tracing::info!(%kind, failures, fetches, "upstream recovered, picking arts uniformly again");
+        }
+    }
+
+    // a kind stops being degraded once we haven't fetched from it for a while
+    pub(crate) fn is_degraded(&self, kind: &ArtKind) -> bool {
+        self.kinds.get(kind).map_or(false, |state| {
+            state.degraded && state.last_fetch_is_recent()
+        })
+    }
+}
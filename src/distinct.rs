@@ -0,0 +1,151 @@
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use http::Uri;
+
+use crate::{
+    data::{Art, FetchedLink, ImageQuality},
+    error::{AppErrorKind, AppResult},
+    get_image_link, AppState, MAX_RESOLVE_ATTEMPTS,
+};
+
+// how long a token remembers what it was served
+const DISTINCT_WINDOW: Duration = Duration::from_secs(10);
+const MAX_TOKEN_LEN: usize = 64;
+// so a flood of random tokens can't grow the map forever
+const MAX_TOKENS: usize = 10_000;
+
+struct Served {
+    last_used: Instant,
+    // by url, since indices shift when arts are removed
+    arts: HashSet<Uri>,
+}
+
+// arts recently served per distinct token, so embeds on the same page don't repeat
+#[derive(Default)]
+pub(crate) struct DistinctPicks {
+    tokens: DashMap<String, Served>,
+    last_cleanup: Mutex<Option<Instant>>,
+}
+
+impl DistinctPicks {
+    fn served(&self, token: &str) -> HashSet<Uri> {
+        self.tokens
+            .get(token)
+            .filter(|served| served.last_used.elapsed() < DISTINCT_WINDOW)
+            .map(|served| served.arts.clone())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, token: &str, served: HashSet<Uri>) {
+        // expired tokens are cleaned up at most once a window, not on every pick
+        {
+            let mut last_cleanup = self.last_cleanup.lock().unwrap();
+            if last_cleanup.map_or(true, |at| at.elapsed() >= DISTINCT_WINDOW) {
+                *last_cleanup = Some(Instant::now());
+                self.tokens
+                    .retain(|_, served| served.last_used.elapsed() < DISTINCT_WINDOW);
+            }
+        }
+        // a flood of tokens pushes out the least recently used one
+        if !self.tokens.contains_key(token) && self.tokens.len() >= MAX_TOKENS {
+            let oldest = self
+                .tokens
+                .iter()
+                .min_by_key(|entry| entry.last_used)
+                .map(|entry| entry.key().clone());
+            if let Some(oldest) = oldest {
+                self.tokens.remove(&oldest);
+            }
+        }
+        self.tokens.insert(
+            token.to_owned(),
+            Served {
+                last_used: Instant::now(),
+                arts: served,
+            },
+        );
+    }
+}
+
+// like resolve_random_art, but never repeats an art for the same token until every art was served
+pub(crate) async fn resolve_distinct_art(
+    state: &AppState,
+    token: &str,
+    collection: Option<&str>,
+    quality: ImageQuality,
+) -> AppResult<(usize, Art, FetchedLink)> {
+    if token.len() > MAX_TOKEN_LEN {
+        return Err(AppErrorKind::BadRequest(format!(
+            "distinct tokens can be at most {MAX_TOKEN_LEN} characters"
+        ))
+        .into());
+    }
+    if let Some(collection) = collection {
+        let exists = state.data.lock().unwrap().has_collection(collection);
+        if !exists {
+            return Err(AppErrorKind::NotFound(format!("no collection named {collection}")).into());
+        }
+    }
+
+    let mut served = state.distinct_picks.served(token);
+    let mut last_err = None;
+    for _ in 0..MAX_RESOLVE_ATTEMPTS {
+        let picked = {
+            let data = state.data.lock().unwrap();
+            let picked = match data.pick_unseen_art(collection, &served) {
+                Some(picked) => Some(picked),
+                // everything was served, so start over
+                None => {
+                    served.clear();
+                    data.pick_unseen_art(collection, &served)
+                }
+            };
+            picked.map(|(art_no, art)| (art_no, art.clone()))
+        };
+        let Some((art_no, art)) = picked else {
+            break;
+        };
+        served.insert(art.url.clone());
+        match get_image_link(state, &art, quality).await {
+            Ok(image_link) => {
+                state.distinct_picks.record(token, served);
+                return Ok((art_no, art, image_link));
+            }
+            Err(err) => {
+                tracing::warn!(art_url = %art.url, error = %err, "could not resolve art, trying another one");
+                last_err = Some(err);
+            }
+        }
+    }
+    state.distinct_picks.record(token, served);
+    Err(last_err.unwrap_or_else(|| AppErrorKind::NoArtConfigured.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_map_evicts_the_oldest_token() {
+        let picks = DistinctPicks::default();
+        let start = Instant::now();
+        for no in 0..MAX_TOKENS {
+            let served = Served {
+                last_used: start + Duration::from_micros(no as u64),
+                arts: HashSet::new(),
+            };
+            picks.tokens.insert(no.to_string(), served);
+        }
+        let url: Uri = "https://twitter.com/someone/status/1".parse().unwrap();
+        picks.record("new", HashSet::from([url.clone()]));
+
+        assert_eq!(picks.tokens.len(), MAX_TOKENS);
+        assert!(!picks.tokens.contains_key("0"));
+        assert!(picks.served("new").contains(&url));
+    }
+}
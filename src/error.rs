@@ -1,16 +1,29 @@
-use std::fmt::Display;
+use std::{any::Any, fmt::Display, time::Duration};
 
-use axum::response::{Html, IntoResponse};
+use axum::{
+    response::{Html, IntoResponse},
+    Json,
+};
 use http::StatusCode;
 
-type BoxedError = Box<dyn std::error::Error>;
+type BoxedError = Box<dyn std::error::Error + Send + Sync>;
 
 pub(crate) type AppResult<T> = Result<T, AppError>;
 
+#[derive(Debug, Clone, Copy)]
+enum ErrorFormat {
+    Html,
+    // for api routes
+    Json,
+    // for routes that respond with a bare url
+    Plain,
+}
+
 #[derive(Debug)]
 pub(crate) struct AppError {
     internal: BoxedError,
     status: Option<StatusCode>,
+    format: ErrorFormat,
 }
 
 impl AppError {
@@ -18,6 +31,172 @@ impl AppError {
         self.status = Some(code);
         self
     }
+
+    pub(crate) fn json(mut self) -> Self {
+        self.format = ErrorFormat::Json;
+        self
+    }
+
+    pub(crate) fn plain(mut self) -> Self {
+        self.format = ErrorFormat::Plain;
+        self
+    }
+
+    pub(crate) fn kind(&self) -> Option<&AppErrorKind> {
+        self.internal.downcast_ref::<AppErrorKind>()
+    }
+
+    // the status code an upstream responded with, if this error came from one
+    pub(crate) fn upstream_status(&self) -> Option<StatusCode> {
+        if let Some(AppErrorKind::UpstreamThrottled { status, .. }) = self.kind() {
+            return Some(*status);
+        }
+        self.internal
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|err| err.status())
+    }
+
+    // how long the upstream asked us to wait before trying again
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self.kind() {
+            Some(AppErrorKind::UpstreamThrottled { retry_after, .. }) => *retry_after,
+            _ => None,
+        }
+    }
+
+    // whether the upstream itself is having trouble, as opposed to a single art being broken
+    pub(crate) fn is_upstream_unavailable(&self) -> bool {
+        match self.kind() {
+            Some(
+                AppErrorKind::UpstreamUnavailable(_)
+                | AppErrorKind::UpstreamThrottled { .. }
+                | AppErrorKind::UpstreamBlocked(_),
+            ) => return true,
+            Some(_) => return false,
+            None => {}
+        }
+        self.internal
+            .downcast_ref::<reqwest::Error>()
+            .map_or(false, |err| {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err
+                        .status()
+                        .map_or(false, |status| status.is_server_error())
+            })
+    }
+
+    // messages that are safe to show to visitors even outside debug mode
+    fn public_message(&self) -> Option<String> {
+        match self.kind() {
+            Some(kind @ (AppErrorKind::NotFound(_) | AppErrorKind::BadRequest(_))) => {
+                Some(kind.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn default_status(&self) -> StatusCode {
+        if let Some(kind) = self.kind() {
+            return kind.status();
+        }
+        match self.internal.downcast_ref::<reqwest::Error>() {
+            Some(err) if err.status() == Some(StatusCode::NOT_FOUND) => StatusCode::NOT_FOUND,
+            Some(_) => StatusCode::BAD_GATEWAY,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum AppErrorKind {
+    // the art was deleted upstream
+    UpstreamNotFound(String),
+    // the upstream is down or returned something we don't understand
+    UpstreamUnavailable(String),
+    // the upstream told us to slow down (429) or is overloaded (503)
+    UpstreamThrottled {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    InvalidArtEntry {
+        list: String,
+        line: usize,
+        reason: String,
+    },
+    // the art url doesn't look like what its source expects
+    InvalidArtUrl(String),
+    // the upstream won't show the art without logging in
+    UpstreamRestricted(String),
+    // the upstream refuses to talk to us at all, like a cloudflare challenge
+    UpstreamBlocked(String),
+    // none of the mirrors for an image that can't be hotlinked had it
+    NoWorkingMirror(String),
+    NoArtConfigured,
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl AppErrorKind {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            Self::UpstreamNotFound(_) | Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::UpstreamUnavailable(_)
+            | Self::UpstreamRestricted(_)
+            | Self::UpstreamBlocked(_)
+            | Self::NoWorkingMirror(_) => StatusCode::BAD_GATEWAY,
+            Self::UpstreamThrottled { .. } | Self::NoArtConfigured => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::InvalidArtEntry { .. } | Self::InvalidArtUrl(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Display for AppErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UpstreamNotFound(reason) => write!(f, "art not found upstream: {reason}"),
+            Self::UpstreamUnavailable(reason) => write!(f, "upstream unavailable: {reason}"),
+            Self::UpstreamThrottled { status, .. } => {
+                write!(f, "upstream is throttling us ({status})")
+            }
+            Self::InvalidArtEntry { list, line, reason } => {
+                write!(f, "invalid art entry on line {line} in {list}: {reason}")
+            }
+            Self::UpstreamRestricted(reason) => write!(f, "upstream restricted the art: {reason}"),
+            Self::UpstreamBlocked(reason) => write!(f, "upstream blocked us: {reason}"),
+            Self::InvalidArtUrl(reason) => write!(f, "invalid art url: {reason}"),
+            Self::NoWorkingMirror(image) => write!(f, "no pixiv mirror has {image}"),
+            Self::NoArtConfigured => f.write_str("no arts are configured"),
+            Self::NotFound(reason) | Self::BadRequest(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl std::error::Error for AppErrorKind {}
+
+// errors out if an upstream told us to slow down (429) or is overloaded (503)
+pub(crate) fn check_throttled(resp: &reqwest::Response) -> AppResult<()> {
+    let status = resp.status();
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return Ok(());
+    }
+    // only the delay-seconds form, upstreams we care about don't send http dates
+    let retry_after = resp
+        .headers()
+        .get(http::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs);
+    Err(AppErrorKind::UpstreamThrottled {
+        status,
+        retry_after,
+    }
+    .into())
 }
 
 impl<E> From<E> for AppError
@@ -28,36 +207,162 @@ where
         Self {
             internal: err.into(),
             status: None,
+            format: ErrorFormat::Html,
         }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        self.render(crate::config::config().server.debug_errors)
+    }
+}
+
+impl AppError {
+    // with debug, the message of any error is shown, not just the public ones
+    fn render(self, debug: bool) -> axum::response::Response {
+        let status = self.status.unwrap_or_else(|| self.default_status());
+        // so visitors can report an error without us showing them the details
+        let error_id =
+            crate::request_id::current().unwrap_or_else(|| format!("{:08x}", fastrand::u32(..)));
+        tracing::error!(
+            error_id,
+            status = status.as_u16(),
+            error = %self.internal,
+            "request failed"
+        );
+
+        let strings = crate::i18n::current();
+        let message = if debug {
+            self.internal.to_string()
+        } else {
+            self.public_message()
+                .unwrap_or_else(|| strings.try_again_later.to_owned())
+        };
+
+        match self.format {
+            ErrorFormat::Json => {
+                let body = serde_json::json!({
+                    "error": message,
+                    "status": status.as_u16(),
+                    "error_id": error_id,
+                });
+                return (status, Json(body)).into_response();
+            }
+            ErrorFormat::Plain => {
+                return (status, format!("error: {message} (error id: {error_id})\n"))
+                    .into_response();
+            }
+            ErrorFormat::Html => {}
+        }
+
         let pre_escaped = maud::html! {
             (maud::DOCTYPE)
             head {
-                (crate::get_page_head_common())
+                (crate::get_page_head_common(None))
             }
-            body style=(crate::BODY_STYLE) {
+            body {
                 p style=("display: block; margin: auto; font-size: 1.3em;") {
-                    "Something went wrong: "
+                    (strings.error)
+                    br;
+                    (message);
                     br;
-                    (self.internal.to_string());
+                    span style="font-size: 0.6em;" { (strings.error_id) (error_id) }
                 }
-                (crate::get_page_contact())
+                (crate::get_page_contact(strings))
             }
         };
         let mut resp = Html(pre_escaped.into_string()).into_response();
 
-        *resp.status_mut() = self.status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        *resp.status_mut() = status;
 
         resp
     }
 }
 
+// renders the error page for a panicking handler, instead of dropping the connection
+pub(crate) fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> axum::response::Response {
+    let payload = payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("<no message>");
+    // the request span has the path
+    tracing::error!(payload, "handler panicked");
+    AppError::from("the request handler panicked").into_response()
+}
+
 impl Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.internal.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::AppError;
+    use crate::{request_id::REQUEST_ID_HEADER, testing};
+
+    #[tokio::test]
+    async fn debug_messages_are_escaped() {
+        crate::config::init_default();
+        let resp = AppError::from("<script>alert(1)</script>").render(true);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!page.contains("<script>alert(1)"));
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_json() {
+        let state = testing::builtin_state();
+        let app = testing::serve(&state).await;
+
+        let resp = reqwest::get(format!("{app}/api/arts/nope")).await.unwrap();
+        assert_eq!(resp.status(), 404);
+        let content_type = resp.headers()[http::header::CONTENT_TYPE].clone();
+        assert_eq!(content_type, "application/json");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["error"], "no art with id nope");
+    }
+
+    #[tokio::test]
+    async fn page_errors_are_the_error_page() {
+        let state = testing::builtin_state();
+        let app = testing::serve(&state).await;
+
+        let resp = reqwest::get(format!("{app}/art/99")).await.unwrap();
+        assert_eq!(resp.status(), 404);
+        let content_type = resp.headers()[http::header::CONTENT_TYPE].clone();
+        assert!(content_type.to_str().unwrap().starts_with("text/html"));
+        let page = resp.text().await.unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("no such art"));
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_get_the_error_page() {
+        let state = testing::builtin_state();
+        let routes = Router::new().route("/panic", get(|| async { panic!("oh no") }));
+        let app = testing::serve_routes(routes, &state).await;
+
+        let resp = reqwest::get(format!("{app}/panic")).await.unwrap();
+        assert_eq!(resp.status(), 500);
+        let content_type = resp.headers()[http::header::CONTENT_TYPE].clone();
+        assert!(content_type.to_str().unwrap().starts_with("text/html"));
+        let request_id = resp.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let page = resp.text().await.unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        // the page is rendered inside the request, so it can tell which one failed
+        assert!(page.contains(&request_id));
+        assert!(!page.contains("oh no"));
+    }
+}
@@ -0,0 +1,124 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use http::header;
+use serde::Deserialize;
+
+use crate::{config::config, date, permalink, stable_hash, AppState};
+
+const MAX_FEED_ENTRIES: usize = 50;
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FeedQuery {
+    nsfw: Option<String>,
+}
+
+// the most recently added arts as an atom feed
+pub(crate) async fn show_feed(
+    Query(query): Query<FeedQuery>,
+    state: State<AppState>,
+) -> impl IntoResponse {
+    let nsfw = config().arts.serve_nsfw.allows(query.nsfw.as_deref());
+    let mut arts = {
+        let data = state.data.lock().unwrap();
+        data.arts()
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|(_, art)| nsfw || !art.nsfw)
+            .collect::<Vec<_>>()
+    };
+    // arts loaded at the same time are ordered by their place in the list
+    arts.sort_by(|(a_no, a), (b_no, b)| b.added_at.cmp(&a.added_at).then(b_no.cmp(a_no)));
+    arts.truncate(MAX_FEED_ENTRIES);
+
+    let site_url = config().site.url.trim_end_matches('/');
+    let title = &config().site.title;
+    let updated = arts
+        .first()
+        .and_then(|(_, art)| art.added_at)
+        .unwrap_or_else(std::time::SystemTime::now);
+
+    let mut feed = String::new();
+    feed.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    feed.push_str(&format!(
+        r#"<feed xmlns="http://www.w3.org/2005/Atom" xml:base="{}/">"#,
+        xml_escape(site_url)
+    ));
+    feed.push_str(&format!("<title>{}</title>", xml_escape(title)));
+    feed.push_str(&format!(
+        r#"<id>{}/feed.xml</id><link rel="self" href="{}/feed.xml"/>"#,
+        xml_escape(site_url),
+        xml_escape(site_url)
+    ));
+    feed.push_str(&format!(
+        "<updated>{}</updated>",
+        date::format_rfc3339(updated)
+    ));
+    feed.push_str(&format!(
+        "<author><name>{}</name></author>",
+        xml_escape(title)
+    ));
+    for (art_no, art) in arts {
+        let source = art.url.to_string();
+        let art_url = format!("{site_url}{}", permalink(art_no));
+        feed.push_str("<entry>");
+        // keyed on the source, so the id doesn't change if the art moves around in the list
+        feed.push_str(&format!(
+            "<id>urn:limbusart:{:016x}</id>",
+            stable_hash(&source)
+        ));
+        feed.push_str(&format!("<title>{}</title>", xml_escape(&source)));
+        feed.push_str(&format!(r#"<link href="{}"/>"#, xml_escape(&art_url)));
+        feed.push_str(&format!(
+            "<updated>{}</updated>",
+            // arts from before we kept track count as updated with the feed
+            date::format_rfc3339(art.added_at.unwrap_or(updated))
+        ));
+        let image_link = state
+            .direct_links
+            .get(&(art.url.clone(), state.default_quality))
+            .map(|link| link.image_url.clone());
+        // only embed images we already know, building the feed shouldn't hit upstreams
+        let content = match image_link {
+            Some(image_url) => format!(
+                r#"<img src="{}" referrerpolicy="no-referrer"><br><a href="{}">{}</a>"#,
+                xml_escape(&image_url),
+                xml_escape(&source),
+                xml_escape(&source)
+            ),
+            None => format!(
+                r#"<a href="{}">{}</a>"#,
+                xml_escape(&source),
+                xml_escape(&source)
+            ),
+        };
+        feed.push_str(&format!(
+            r#"<content type="html">{}</content>"#,
+            xml_escape(&content)
+        ));
+        feed.push_str("</entry>");
+    }
+    feed.push_str("</feed>");
+
+    (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        feed,
+    )
+}
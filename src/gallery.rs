@@ -0,0 +1,160 @@
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+};
+use http::header;
+use serde::Deserialize;
+
+use crate::{
+    config::config,
+    error::{AppErrorKind, AppResult},
+    get_page_contact, get_page_head_common, i18n, image_src, parse_quality, permalink, AppState,
+};
+
+const ARTS_PER_PAGE: usize = 24;
+
+#[derive(Deserialize)]
+pub(crate) struct GalleryQuery {
+    page: Option<usize>,
+    quality: Option<String>,
+    // newest for the most recently added arts first, list order otherwise
+    sort: Option<String>,
+    nsfw: Option<String>,
+}
+
+// lists every art, but only shows images that are already resolved so browsing doesn't hit upstreams
+pub(crate) async fn show_gallery(
+    Query(query): Query<GalleryQuery>,
+    state: State<AppState>,
+) -> AppResult<Html<String>> {
+    let quality = parse_quality(&state, query.quality.as_deref())?;
+    let page = query.page.unwrap_or(1);
+    if page == 0 {
+        return Err(AppErrorKind::BadRequest("pages start at 1".to_owned()).into());
+    }
+
+    let newest_first = match query.sort.as_deref() {
+        None => false,
+        Some("newest") => true,
+        Some(sort) => {
+            return Err(AppErrorKind::BadRequest(format!("unknown sort order: {sort}")).into())
+        }
+    };
+
+    let nsfw = config().arts.serve_nsfw.allows(query.nsfw.as_deref());
+    let (total, arts) = {
+        let data = state.data.lock().unwrap();
+        let start = (page - 1).saturating_mul(ARTS_PER_PAGE);
+        let mut ordered = data
+            .arts()
+            .iter()
+            .enumerate()
+            .filter(|(_, art)| nsfw || !art.nsfw)
+            .collect::<Vec<_>>();
+        let total = ordered.len();
+        if newest_first {
+            // arts added at the same time stay in list order, unknown dates go last
+            ordered.sort_by(|(_, a), (_, b)| b.added_at.cmp(&a.added_at));
+        }
+        let page_arts = ordered
+            .into_iter()
+            .skip(start)
+            .take(ARTS_PER_PAGE)
+            .map(|(art_no, art)| (art_no, art.clone()))
+            .collect::<Vec<_>>();
+        (total, page_arts)
+    };
+    let page_count = total.div_ceil(ARTS_PER_PAGE);
+    if arts.is_empty() {
+        return Err(AppErrorKind::NotFound(format!(
+            "there is no page {page}, there are only {page_count} pages"
+        ))
+        .into());
+    }
+
+    let tiles = arts.into_iter().map(|(art_no, art)| {
+        let image_link = state
+            .direct_links
+            .get(&(art.url.clone(), quality))
+            .map(|link| link.clone());
+        (art_no, image_link)
+    });
+    let tile_style = "width: 200px; height: 200px; display: flex; align-items: center; justify-content: center; background: #1e1e1e; overflow: hidden;";
+    let content = maud::html! {
+        (maud::DOCTYPE)
+        head {
+            (get_page_head_common(None))
+        }
+        body style="flex-direction: column; height: auto; min-height: 100vh; overflow-x: hidden;" {
+            h1 style="margin: 2vh auto; font-size: 1.5vmax;" { (total) " arts" }
+            div style="display: flex; flex-wrap: wrap; gap: 8px; justify-content: center; padding: 0 2vw;" {
+                @for (art_no, image_link) in tiles {
+                    a style=(tile_style) href=(permalink(art_no)) {
+                        @if let Some(image_link) = image_link {
+                            img style="max-width: 100%; max-height: 100%;" loading="lazy" referrerpolicy="no-referrer" src=(image_src(art_no, quality, &image_link)) alt=[image_link.description.as_deref()];
+                        } @else {
+                            // resolving happens when the permalink is opened
+                            span class="about" { "#" (art_no) " resolve" }
+                        }
+                    }
+                }
+            }
+            div style="display: flex; gap: 2vw; margin: 2vh auto;" {
+                @if page > 1 {
+                    a class="about" href=(page_href(page - 1, newest_first, nsfw)) { "← previous" }
+                }
+                span class="about" { "page " (page) " of " (page_count) }
+                @if page < page_count {
+                    a class="about" href=(page_href(page + 1, newest_first, nsfw)) { "next →" }
+                }
+            }
+            div style="margin: 2vh auto;" {
+                (get_page_contact(i18n::current()))
+            }
+        }
+    };
+    Ok(Html(content.into_string()))
+}
+
+fn page_href(page: usize, newest_first: bool, nsfw: bool) -> String {
+    let mut href = format!("/all?page={page}");
+    if newest_first {
+        href.push_str("&sort=newest");
+    }
+    // only set when it was asked for, or when the site always shows nsfw arts anyway
+    if nsfw {
+        href.push_str("&nsfw=1");
+    }
+    href
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListQuery {
+    nsfw: Option<String>,
+}
+
+// the sources in the same format as the arts file, in the order they were added
+pub(crate) async fn show_list(
+    Query(query): Query<ListQuery>,
+    state: State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    if !config().site.public_list {
+        return Err(AppErrorKind::NotFound("the art list is not public".to_owned()).into());
+    }
+    let nsfw = config().arts.serve_nsfw.allows(query.nsfw.as_deref());
+    // only copy the urls while locked, formatting can happen afterwards
+    let urls = {
+        let data = state.data.lock().unwrap();
+        data.arts()
+            .iter()
+            .filter(|art| nsfw || !art.nsfw)
+            .map(|art| art.url.clone())
+            .collect::<Vec<_>>()
+    };
+    let mut body = String::new();
+    for url in urls {
+        body.push_str(&url.to_string());
+        body.push('\n');
+    }
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body))
+}
@@ -0,0 +1,47 @@
+use std::{collections::HashMap, time::Duration};
+
+use http::Uri;
+
+use crate::{db, fetch_limited, AppState};
+
+// slowly walks over all arts, marking the ones that keep failing as unhealthy
+pub(crate) async fn run(state: AppState, interval: Duration, max_failures: usize) {
+    let mut failures: HashMap<Uri, usize> = HashMap::new();
+    loop {
+        let arts = state.data.lock().unwrap().arts().to_vec();
+        for art in arts {
+            tokio::time::sleep(interval).await;
+
+            let quality = state.default_quality;
+            match fetch_limited(&state, &art, quality).await {
+                Ok(image_link) => {
+                    failures.remove(&art.url);
+                    state.data.lock().unwrap().set_healthy(&art.url, true);
+                    db::set_healthy(&state, &art.url, true);
+                    state
+                        .direct_links
+                        .insert((art.url.clone(), quality), image_link);
+                }
+                Err(err) => {
+                    let count = failures.entry(art.url.clone()).or_default();
+                    *count += 1;
+                    tracing::warn!(art_url = %art.url, failures = *count, error = %err, "health check failed");
+                    if *count >= max_failures {
+                        state.data.lock().unwrap().set_healthy(&art.url, false);
+                        db::set_healthy(&state, &art.url, false);
+                    }
+                }
+            }
+        }
+
+        let data = state.data.lock().unwrap();
+        tracing::info!(
+            arts = data.arts().len(),
+            unhealthy = data.unhealthy().count(),
+            "finished health check pass"
+        );
+        for url in data.unhealthy() {
+            tracing::info!(art_url = %url, "unhealthy art");
+        }
+    }
+}
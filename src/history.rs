@@ -0,0 +1,125 @@
+use std::{collections::VecDeque, sync::Mutex, time::SystemTime};
+
+use axum::{
+    extract::{Query, State},
+    response::Html,
+};
+use http::Uri;
+use serde::Deserialize;
+
+use crate::{
+    config::config,
+    data::{Art, ImageQuality},
+    date,
+    error::{AppErrorKind, AppResult},
+    get_page_contact, get_page_head_common, i18n, image_src, permalink, AppState,
+};
+
+#[derive(Clone)]
+struct ServedArt {
+    art_no: usize,
+    url: Uri,
+    nsfw: bool,
+    quality: ImageQuality,
+    served_at: SystemTime,
+}
+
+// the last arts that were served to anyone, newest first
+pub(crate) struct ServedHistory {
+    size: usize,
+    served: Mutex<VecDeque<ServedArt>>,
+}
+
+impl ServedHistory {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            size,
+            served: Mutex::new(VecDeque::with_capacity(size)),
+        }
+    }
+
+    pub(crate) fn record(&self, art_no: usize, art: &Art, quality: ImageQuality) {
+        if self.size == 0 || !config().site.public_history {
+            return;
+        }
+        let served = ServedArt {
+            art_no,
+            url: art.url.clone(),
+            nsfw: art.nsfw,
+            quality,
+            served_at: SystemTime::now(),
+        };
+        // only held for the push, so serving arts doesn't wait on the history page
+        let mut history = self.served.lock().unwrap();
+        if history.len() >= self.size {
+            history.pop_back();
+        }
+        history.push_front(served);
+    }
+
+    fn entries(&self) -> Vec<ServedArt> {
+        self.served.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RecentQuery {
+    nsfw: Option<String>,
+}
+
+// only shows images that are still cached, so looking back doesn't refetch anything
+pub(crate) async fn show_recent(
+    Query(query): Query<RecentQuery>,
+    state: State<AppState>,
+) -> AppResult<Html<String>> {
+    if !config().site.public_history {
+        return Err(AppErrorKind::NotFound("the served history is not public".to_owned()).into());
+    }
+    let nsfw = config().arts.serve_nsfw.allows(query.nsfw.as_deref());
+    let entries = state
+        .served_history
+        .entries()
+        .into_iter()
+        .filter(|served| nsfw || !served.nsfw)
+        .map(|served| {
+            let image_link = state
+                .direct_links
+                .get(&(served.url.clone(), served.quality))
+                .map(|link| link.clone());
+            (served, image_link)
+        })
+        .collect::<Vec<_>>();
+
+    let content = maud::html! {
+        (maud::DOCTYPE)
+        head {
+            (get_page_head_common(None))
+        }
+        body style="flex-direction: column; height: auto; min-height: 100vh; overflow-x: hidden;" {
+            h1 style="margin: 2vh auto; font-size: 1.5vmax;" { "recently served" }
+            div style="display: flex; flex-direction: column; gap: 2vh; margin: 0 auto; max-width: 90vw;" {
+                @if entries.is_empty() {
+                    span class="about" { "nothing was served yet" }
+                }
+                @for (served, image_link) in &entries {
+                    div style="display: flex; align-items: center; gap: 2vw;" {
+                        @if let Some(image_link) = image_link {
+                            a href=(permalink(served.art_no)) {
+                                img style="max-width: 120px; max-height: 120px;" loading="lazy" referrerpolicy="no-referrer" src=(image_src(served.art_no, served.quality, image_link)) alt=[image_link.description.as_deref()];
+                            }
+                        }
+                        div style="display: flex; flex-direction: column;" {
+                            span class="about" { (date::format_rfc3339(served.served_at)) }
+                            a class="about" href=(permalink(served.art_no)) { "#" (served.art_no) }
+                            a class="about" href=(served.url) target="_blank" { (served.url) }
+                        }
+                    }
+                }
+            }
+            div style="margin: 2vh auto;" {
+                (get_page_contact(i18n::current()))
+            }
+        }
+    };
+    Ok(Html(content.into_string()))
+}
@@ -0,0 +1,148 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::{header, HeaderValue};
+
+// the text on our pages, in one language
+pub(crate) struct Strings {
+    pub(crate) lang: &'static str,
+    pub(crate) source: &'static str,
+    // with {position} and {total} filled in, by the page script too
+    pub(crate) art_position: &'static str,
+    pub(crate) added: &'static str,
+    pub(crate) share: &'static str,
+    pub(crate) next: &'static str,
+    pub(crate) full_quality: &'static str,
+    pub(crate) low_bandwidth: &'static str,
+    pub(crate) download: &'static str,
+    // the names and the handle stay as they are, they are what people look for
+    pub(crate) made_by: &'static str,
+    pub(crate) report_problems: &'static str,
+    pub(crate) error: &'static str,
+    pub(crate) error_id: &'static str,
+    pub(crate) try_again_later: &'static str,
+}
+
+pub(crate) const EN: Strings = Strings {
+    lang: "en",
+    source: "source: ",
+    art_position: "art {position} of {total}",
+    added: "added ",
+    share: "share this roll",
+    next: "next →",
+    full_quality: "full quality",
+    low_bandwidth: "low bandwidth",
+    download: "download",
+    made_by: "website made by dusk",
+    report_problems: "report problems / feedback @ yusdacra on Discord",
+    error: "Something went wrong: ",
+    error_id: "error id: ",
+    try_again_later: "please try again later.",
+};
+
+const KO: Strings = Strings {
+    lang: "ko",
+    source: "출처: ",
+    art_position: "{total}개 중 {position}번째",
+    added: "추가일 ",
+    share: "이 결과 공유",
+    next: "다음 →",
+    full_quality: "원본 화질",
+    low_bandwidth: "저용량",
+    download: "다운로드",
+    made_by: "웹사이트 제작: dusk",
+    report_problems: "문제 신고 / 피드백: Discord의 yusdacra",
+    error: "문제가 발생했습니다: ",
+    error_id: "오류 ID: ",
+    try_again_later: "잠시 후 다시 시도해 주세요.",
+};
+
+const JA: Strings = Strings {
+    lang: "ja",
+    source: "出典: ",
+    art_position: "{total}枚中{position}枚目",
+    added: "追加日 ",
+    share: "この結果を共有",
+    next: "次へ →",
+    full_quality: "高画質",
+    low_bandwidth: "低画質",
+    download: "ダウンロード",
+    made_by: "ウェブサイト制作: dusk",
+    report_problems: "不具合報告・フィードバック: Discordの yusdacra",
+    error: "エラーが発生しました: ",
+    error_id: "エラーID: ",
+    try_again_later: "しばらくしてからもう一度お試しください。",
+};
+
+impl Strings {
+    pub(crate) fn art_position(&self, position: usize, total: usize) -> String {
+        self.art_position
+            .replace("{position}", &position.to_string())
+            .replace("{total}", &total.to_string())
+    }
+}
+
+const LOCALES: &[&Strings] = &[&EN, &KO, &JA];
+
+tokio::task_local! {
+    static STRINGS: &'static Strings;
+}
+
+// ?lang= wins over the browser's languages, anything we don't have falls back to english
+fn select(req: &Request) -> &'static Strings {
+    let requested = req
+        .uri()
+        .query()
+        .and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(name, _)| name == "lang")
+                .map(|(_, lang)| lang.into_owned())
+        })
+        .and_then(|lang| find(&lang));
+    if let Some(strings) = requested {
+        return strings;
+    }
+
+    let mut languages = req
+        .headers()
+        .get_all(header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|language| {
+            let mut parts = language.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            Some((tag, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+    // stable, so languages with the same quality keep the order they were sent in
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages
+        .into_iter()
+        .find_map(|(tag, _)| find(tag))
+        .unwrap_or(&EN)
+}
+
+// by the primary language, so ko-KR and ja-JP work too
+fn find(tag: &str) -> Option<&'static Strings> {
+    let primary = tag.split(['-', '_']).next()?.to_ascii_lowercase();
+    LOCALES
+        .iter()
+        .find(|strings| strings.lang == primary)
+        .copied()
+}
+
+pub(crate) async fn select_locale(req: Request, next: Next) -> Response {
+    let strings = select(&req);
+    let mut resp = STRINGS.scope(strings, next.run(req)).await;
+    resp.headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-language"));
+    resp
+}
+
+// the strings for the request being handled, english outside of one
+pub(crate) fn current() -> &'static Strings {
+    STRINGS.try_with(|strings| *strings).unwrap_or(&EN)
+}
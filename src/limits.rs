@@ -0,0 +1,60 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{AppError, AppErrorKind, AppResult};
+
+// bounds how many fetches can hit a source at once, shared by everything that fetches. keyed by
+// source rather than host, since a source can be reached through several hosts
+pub(crate) struct SourceLimits {
+    semaphores: DashMap<String, Arc<Semaphore>>,
+    limits: HashMap<String, usize>,
+    default_limit: usize,
+}
+
+impl SourceLimits {
+    pub(crate) fn new(limits: HashMap<String, usize>, default_limit: usize) -> Self {
+        Self {
+            semaphores: DashMap::new(),
+            limits,
+            default_limit,
+        }
+    }
+
+    pub(crate) async fn acquire(&self, source: &str) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .semaphores
+            .entry(source.to_owned())
+            .or_insert_with(|| {
+                let limit = self
+                    .limits
+                    .get(source)
+                    .copied()
+                    .unwrap_or(self.default_limit);
+                Arc::new(Semaphore::new(limit.max(1)))
+            })
+            .clone();
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("source semaphores are never closed")
+    }
+}
+
+// the wait for a permit counts toward the timeout, so visitors don't queue forever behind a warmup
+pub(crate) async fn with_timeout<T>(
+    timeout: Duration,
+    source: &str,
+    fut: impl std::future::Future<Output = AppResult<T>>,
+) -> AppResult<T> {
+    tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or_else(|_| {
+            let err = AppErrorKind::UpstreamUnavailable(format!(
+                "fetching from {source} took longer than {}s",
+                timeout.as_secs()
+            ));
+            Err(AppError::from(err).status(http::StatusCode::GATEWAY_TIMEOUT))
+        })
+}
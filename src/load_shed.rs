@@ -0,0 +1,107 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header, HeaderValue, StatusCode};
+use tokio::sync::Semaphore;
+
+use crate::{error::AppError, EXEMPT_PATHS};
+
+const RETRY_AFTER_SECS: &str = "5";
+
+// caps how many requests are handled at once, and turns requests away once too many are waiting
+pub(crate) struct LoadShedder {
+    permits: Semaphore,
+    waiting: AtomicUsize,
+    max_waiting: usize,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(max_concurrent: usize, max_waiting: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrent.max(1)),
+            waiting: AtomicUsize::new(0),
+            max_waiting,
+        }
+    }
+}
+
+// counts a request as waiting until it's dropped, which is also what happens when its client leaves
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) async fn shed_load(
+    State(shedder): State<Arc<LoadShedder>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if EXEMPT_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let _permit = match shedder.permits.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            let waiting = shedder.waiting.fetch_add(1, Ordering::Relaxed);
+            let _waiting = Waiting(&shedder.waiting);
+            if waiting >= shedder.max_waiting {
+                tracing::warn!(waiting, "too many requests waiting, shedding load");
+                return overloaded(req.uri().path());
+            }
+            shedder
+                .permits
+                .acquire()
+                .await
+                .expect("the request semaphore is never closed")
+        }
+    };
+    next.run(req).await
+}
+
+// api clients get the error as json, like the api routes' own errors
+fn overloaded(path: &str) -> Response {
+    let mut err =
+        AppError::from("too many requests are waiting").status(StatusCode::SERVICE_UNAVAILABLE);
+    if path.starts_with("/api/") {
+        err = err.json();
+    }
+    let mut resp = err.into_response();
+    resp.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from_static(RETRY_AFTER_SECS),
+    );
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_type(resp: &Response) -> &str {
+        resp.headers()[header::CONTENT_TYPE].to_str().unwrap()
+    }
+
+    #[test]
+    fn api_clients_are_turned_away_with_json() {
+        crate::config::init_default();
+        let resp = overloaded("/api/random");
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(content_type(&resp), "application/json");
+        assert_eq!(resp.headers()[header::RETRY_AFTER], RETRY_AFTER_SECS);
+
+        let resp = overloaded("/");
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(content_type(&resp).starts_with("text/html"));
+    }
+}
@@ -0,0 +1,150 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    added,
+    data::ArtList,
+    db,
+    error::{AppErrorKind, AppResult},
+    AppState,
+};
+
+const STARTUP_FETCH_ATTEMPTS: u32 = 5;
+const STARTUP_RETRY_WAIT: Duration = Duration::from_secs(2);
+
+pub(crate) fn is_remote(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+// ARTS_PATH is a comma (or, for local paths, colon) separated list of files, directories and urls
+fn sources(path: &str) -> AppResult<Vec<String>> {
+    let mut sources = Vec::new();
+    for source in path.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if is_remote(source) {
+            sources.push(source.to_owned());
+            continue;
+        }
+        let local_paths: Vec<&str> = if cfg!(windows) {
+            vec![source]
+        } else {
+            source.split(':').filter(|s| !s.is_empty()).collect()
+        };
+        for local_path in local_paths {
+            if std::path::Path::new(local_path).is_dir() {
+                let mut files = std::fs::read_dir(local_path)?
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .filter(|path| path.extension().map_or(false, |ext| ext == "txt"))
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>();
+                // sorted, so arts are numbered the same way every time
+                files.sort();
+                sources.extend(files);
+            } else {
+                sources.push(local_path.to_owned());
+            }
+        }
+    }
+    Ok(sources)
+}
+
+// the local file new arts are written to, which is the first one configured
+pub(crate) fn submission_file(path: &str) -> AppResult<String> {
+    sources(path)?
+        .into_iter()
+        .next()
+        .filter(|source| !is_remote(source))
+        .ok_or_else(|| {
+            AppErrorKind::BadRequest("the arts list is remote, add arts there instead".to_owned())
+                .into()
+        })
+}
+
+async fn fetch(http: &reqwest::Client, url: &str) -> AppResult<String> {
+    let resp = http.get(url).send().await?.error_for_status()?;
+    Ok(resp.text().await?)
+}
+
+// retries remote lists for a bit, since the network might not be up yet at startup
+async fn fetch_with_retries(http: &reqwest::Client, url: &str) -> AppResult<String> {
+    let mut attempt = 1;
+    loop {
+        match fetch(http, url).await {
+            Ok(arts) => return Ok(arts),
+            Err(err) if attempt < STARTUP_FETCH_ATTEMPTS => {
+                tracing::warn!(url, attempt, error = %err, "could not fetch arts, retrying");
+                tokio::time::sleep(STARTUP_RETRY_WAIT * attempt).await;
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(AppErrorKind::UpstreamUnavailable(format!(
+                    "could not fetch arts from {url} after {attempt} attempts: {err}"
+                ))
+                .into())
+            }
+        }
+    }
+}
+
+pub(crate) async fn load_initial(http: &reqwest::Client, path: &str) -> AppResult<Vec<ArtList>> {
+    let mut lists = Vec::new();
+    for source in sources(path)? {
+        let contents = if is_remote(&source) {
+            fetch_with_retries(http, &source)
+                .await
+                .map_err(|err| format!("could not fetch arts list {source}: {err}"))?
+        } else {
+            std::fs::read_to_string(&source)
+                .map_err(|err| format!("could not read arts file {source}: {err}"))?
+        };
+        lists.push(ArtList {
+            name: Arc::from(source),
+            contents,
+        });
+    }
+    Ok(lists)
+}
+
+async fn load(state: &AppState) -> AppResult<Vec<ArtList>> {
+    let mut lists = Vec::new();
+    for source in sources(&state.arts_path)? {
+        let contents = if is_remote(&source) {
+            fetch(&state.http, &source).await?
+        } else {
+            let _file_lock = state.arts_file_lock.lock().unwrap();
+            std::fs::read_to_string(&source)?
+        };
+        lists.push(ArtList {
+            name: Arc::from(source),
+            contents,
+        });
+    }
+    Ok(lists)
+}
+
+// re-reads every arts list, keeping the current arts if that fails. returns how many were added
+pub(crate) async fn reload(state: &AppState) -> AppResult<usize> {
+    let result = load(state).await.and_then(|lists| {
+        let mut data = state.data.lock().unwrap();
+        let added = data.reload(&lists)?;
+        added::save(&data);
+        db::save_arts(state, &data);
+        Ok((added, data.arts().len()))
+    });
+    match &result {
+        Ok((added, arts)) => tracing::info!(path = %state.arts_path, added, arts, "reloaded arts"),
+        Err(err) => {
+            tracing::error!(path = %state.arts_path, error = %err, "could not reload arts, keeping the old ones")
+        }
+    }
+    result.map(|(added, _)| added)
+}
+
+pub(crate) async fn run_refresh(state: AppState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes right away, and we just loaded the arts
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        // already logged
+        let _ = reload(&state).await;
+    }
+}
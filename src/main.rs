@@ -1,304 +1,1501 @@
 use axum::{
-    extract::State,
+    extract::{Path, Query, RawQuery, State},
     response::{Html, IntoResponse},
     routing::get,
-    Router,
+    Json, Router,
 };
 use dashmap::DashMap;
-use data::{Art, ArtKind, Data, FetchedLink};
-use error::{AppError, AppResult};
-use futures_util::{future::BoxFuture, FutureExt};
+use data::{Art, Data, FetchedLink, ImageQuality, PickFilter};
+use error::{AppError, AppErrorKind, AppResult};
 use http::Uri;
 use maud::PreEscaped;
+use serde::Deserialize;
 use std::{
     ops::Deref,
-    str::FromStr,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
+use tracing::Instrument;
 
+mod added;
+mod admin;
+mod api;
+mod assets;
+mod bots;
+mod breaker;
+mod caching;
+mod canonical;
+mod cli;
+mod client_ip;
+mod config;
 mod data;
+mod date;
+mod db;
+mod degraded;
+mod distinct;
 mod error;
+mod feed;
+mod gallery;
+mod health;
+mod history;
+mod i18n;
+mod limits;
+mod load_shed;
+mod loader;
+mod preview;
+mod proxy;
+mod quarantine;
+mod request_id;
+mod resolve_all;
+mod revalidate;
+mod search;
+mod security;
+mod shared_cache;
+mod sources;
+mod stats;
+mod systemd;
+#[cfg(test)]
+mod testing;
+mod theme;
+mod warmup;
 
 #[tokio::main]
-async fn main() {
-    let arts_file_path = get_conf("ARTS_PATH", "./utils/arts.txt");
-    let arts = std::fs::read_to_string(&arts_file_path).unwrap();
-    let state = AppState::new(Data::parse(&arts).unwrap());
+async fn main() -> std::process::ExitCode {
+    let cli = <cli::Cli as clap::Parser>::parse();
+    if let Err(err) = config::init() {
+        eprintln!("{err}");
+        return StartupError::Config(err).exit_code();
+    }
+    init_logging();
+
+    // one shot link check for deployments that can't pass arguments
+    let default_command = if std::env::var("CHECK_LINKS").as_deref() == Ok("1") {
+        cli::Command::Validate {
+            path: None,
+            check_links: true,
+        }
+    } else {
+        cli::Command::Serve
+    };
+    match cli.command.unwrap_or(default_command) {
+        cli::Command::Serve => match serve().await {
+            Ok(()) => std::process::ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{err}");
+                err.exit_code()
+            }
+        },
+        cli::Command::Validate { path, check_links } => cli::validate(path, check_links).await,
+        cli::Command::Resolve { url, quality } => cli::resolve(&url, quality).await,
+    }
+}
+
+fn init_logging() {
+    let log_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    if config::config().server.log_format == "json" {
+        // one json object per line, with the request span fields flattened in
+        tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_env_filter(log_filter)
+            .init();
+    } else {
+        tracing_subscriber::fmt()
+            .compact()
+            .with_env_filter(log_filter)
+            .init();
+    }
+}
+
+// why the server couldn't start or stopped, each with its own exit code
+enum StartupError {
+    Config(String),
+    Arts(String),
+    Bind(String),
+    Server(String),
+}
+
+impl StartupError {
+    fn exit_code(&self) -> std::process::ExitCode {
+        let code = match self {
+            Self::Server(_) => 1,
+            Self::Config(_) => 2,
+            Self::Arts(_) => 3,
+            Self::Bind(_) => 4,
+        };
+        std::process::ExitCode::from(code)
+    }
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(msg) | Self::Arts(msg) | Self::Bind(msg) | Self::Server(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+async fn serve() -> Result<(), StartupError> {
+    let config = config::config();
+    client_ip::init().map_err(StartupError::Config)?;
+
+    let http = build_http_client().map_err(StartupError::Config)?;
+    theme::load_custom_css();
+    let arts_file_path = config.arts.path.clone();
+    let art_lists = loader::load_initial(&http, &arts_file_path)
+        .await
+        .map_err(|err| StartupError::Arts(err.to_string()))?;
+    let blocklist_path = config.arts.blocklist_path.clone();
+    // there is nothing blocked until the first art is removed
+    let blocklist = std::fs::read_to_string(&blocklist_path).unwrap_or_default();
+    let db = config
+        .arts
+        .db_path
+        .as_deref()
+        .map(db::Db::open)
+        .transpose()
+        .map_err(|err| StartupError::Config(format!("could not open DB_PATH: {err}")))?;
+    let saved = db
+        .as_ref()
+        .map(db::Db::load)
+        .transpose()
+        .map_err(|err| StartupError::Config(format!("could not load DB_PATH: {err}")))?;
+    let mut blocked = data::parse_blocklist(&blocklist, sources::registry());
+    let added_at = match &saved {
+        Some(saved) => {
+            blocked.extend(saved.blocked.iter().cloned());
+            saved.added_at.clone()
+        }
+        None => added::load(),
+    };
+    let redis = config
+        .cache
+        .redis_url
+        .as_deref()
+        .map(|url| shared_cache::Redis::new(url, config.cache.redis_ttl_secs).map(Arc::new))
+        .transpose()
+        .map_err(|err| StartupError::Config(format!("invalid REDIS_URL: {err}")))?;
+    let state = AppState::new(
+        Data::parse(
+            &art_lists,
+            sources::registry(),
+            config.arts.pick_mode,
+            blocked,
+            &added_at,
+        )
+        .map_err(|err| StartupError::Arts(err.to_string()))?,
+        config.arts.image_quality,
+        arts_file_path,
+        blocklist_path,
+        http,
+        redis
+            .clone()
+            .map(|redis| redis as Arc<dyn shared_cache::SharedCache>),
+        db,
+        sources::registry(),
+    );
+    if let Some(saved) = saved {
+        let mut data = state.data.lock().unwrap();
+        for url in &saved.unhealthy {
+            data.set_healthy(url, false);
+        }
+        state.serve_counts.restore(saved.serve_counts);
+        for (cache_key, image_link) in saved.links {
+            state.direct_links.insert(cache_key, image_link);
+        }
+        // picks up arts added to the lists while we weren't running
+        db::save_arts(&state, &data);
+    }
+    if let Some(redis) = redis {
+        tokio::spawn(
+            redis
+                .listen(state.clone())
+                .instrument(tracing::info_span!("shared_cache")),
+        );
+    }
 
     #[cfg(not(windows))]
     std::thread::spawn({
-        use signal_hook::{consts::SIGUSR2, iterator::Signals};
+        use signal_hook::{
+            consts::{SIGHUP, SIGUSR1, SIGUSR2},
+            iterator::Signals,
+        };
 
         let state = state.clone();
+        let runtime = tokio::runtime::Handle::current();
         move || {
-            let mut signals = Signals::new(&[SIGUSR2]).unwrap();
-            for _ in signals.forever() {
-                let data = std::fs::read_to_string(&arts_file_path).unwrap();
-                state.data.lock().unwrap().reload(&data).unwrap();
+            let mut signals = match Signals::new([SIGHUP, SIGUSR1, SIGUSR2]) {
+                Ok(signals) => signals,
+                Err(err) => {
+                    tracing::error!(error = %err, "could not listen for signals");
+                    return;
+                }
+            };
+            for signal in signals.forever() {
+                if signal == SIGUSR1 {
+                    // for when an upstream cdn moved everything at once
+                    let evicted = runtime.block_on(shared_cache::evict_links(&state, None));
+                    tracing::info!(evicted, "cleared image link cache on SIGUSR1");
+                } else {
+                    tracing::info!(signal, "reloading arts on signal");
+                    systemd::notify("RELOADING=1");
+                    theme::load_custom_css();
+                    // a failed reload is already logged, and keeps the old arts
+                    if let Ok(added) = runtime.block_on(loader::reload(&state)) {
+                        tracing::info!(signal, added, "reloaded arts on signal");
+                    }
+                    systemd::notify("READY=1");
+                }
             }
         }
     });
 
-    let app = Router::new().route("/", get(show_art)).with_state(state);
+    if let Some(interval) = config.arts.refresh_secs {
+        let interval = Duration::from_secs(interval);
+        tokio::spawn(
+            loader::run_refresh(state.clone(), interval).instrument(tracing::info_span!("refresh")),
+        );
+    }
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    if let Some(interval) = config.cache.health_check_interval_secs {
+        let interval = Duration::from_secs(interval);
+        let max_failures = config.cache.health_check_max_failures;
+        tokio::spawn(
+            health::run(state.clone(), interval, max_failures)
+                .instrument(tracing::info_span!("health")),
+        );
+    }
+
+    if let Some(interval) = config.cache.revalidate_interval_secs {
+        let interval = Duration::from_secs(interval);
+        tokio::spawn(
+            revalidate::run(state.clone(), interval).instrument(tracing::info_span!("revalidate")),
+        );
+    }
+
+    let app = app(&state)?;
+
+    let bind = config.server.bind.as_str();
+    let activated = systemd::activated_listener().map_err(|err| {
+        StartupError::Bind(format!("could not use the socket from systemd: {err}"))
+    })?;
+    let listener = match activated {
+        Some(listener) => tokio::net::TcpListener::from_std(listener).map_err(|err| {
+            StartupError::Bind(format!("could not use the socket from systemd: {err}"))
+        })?,
+        None => tokio::net::TcpListener::bind(bind)
+            .await
+            .map_err(|err| StartupError::Bind(format!("failed to bind {bind}: {err}")))?,
+    };
+    match listener.local_addr() {
+        Ok(addr) => tracing::info!(%addr, "listening"),
+        Err(_) => tracing::info!(addr = bind, "listening"),
+    }
+    // the arts are loaded and we are accepting connections
+    systemd::notify("READY=1");
+
+    // 0 turns the summary off
+    if let Some(interval) = config
+        .cache
+        .stats_log_interval_secs
+        .filter(|secs| *secs > 0)
+    {
+        tokio::spawn(stats::run_summary(
+            state.clone(),
+            Duration::from_secs(interval),
+        ));
+    }
+
+    let warmup_count = config.cache.warmup;
+    if warmup_count > 0 {
+        tokio::spawn(
+            warmup::run(state.clone(), warmup_count).instrument(tracing::info_span!("warmup")),
+        );
+    }
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .map_err(|err| StartupError::Server(format!("server failed: {err}")))?;
+
+    if let Some(stats_path) = &config.arts.stats_path {
+        state.serve_counts.save(stats_path);
+    }
+    db::save_snapshot(&state);
+    Ok(())
+}
+
+// every route with its middleware, also what tests send requests to
+fn app(state: &AppState) -> Result<Router, StartupError> {
+    with_middleware(routes()?, state)
+}
+
+fn routes() -> Result<Router<AppState>, StartupError> {
+    let mut api_routes = Router::new()
+        .route("/api/random", get(api::random_art))
+        .route("/api/arts", get(api::list_arts))
+        .route("/api/arts/:id", get(api::art_by_id))
+        .route("/api/search", get(search::api_search))
+        .route("/api/resolve", axum::routing::post(api::resolve_art));
+    if let Some(cors) = api::cors_layer().map_err(StartupError::Config)? {
+        api_routes = api_routes.layer(cors);
+    }
+
+    Ok(Router::new()
+        .merge(api_routes)
+        .route("/", get(show_art))
+        .route("/daily", get(show_daily))
+        .route("/art/:id", get(show_art_by_id))
+        .route("/slideshow", get(show_slideshow))
+        .route("/all", get(gallery::show_gallery))
+        .route("/search", get(search::show_search))
+        .route("/list.txt", get(gallery::show_list))
+        .route("/feed.xml", get(feed::show_feed))
+        .route("/recent", get(history::show_recent))
+        .route("/stats", get(stats::show_stats))
+        .route("/random/url", get(api::random_url))
+        .route("/random/redirect", get(api::random_redirect))
+        .route("/oembed", get(api::oembed))
+        .route("/version", get(api::version))
+        .route("/img/:url/:signature", get(proxy::proxy_image))
+        .route("/download/:url/:signature", get(proxy::download_image))
+        .route("/static/spinners.css", get(assets::spinners_css))
+        .route("/static/fonts.css", get(assets::fonts_css))
+        .route("/static/PTMono-Regular.woff2", get(assets::pt_mono_woff2))
+        .route("/favicon.ico", get(assets::favicon_ico))
+        .route("/favicon.png", get(assets::favicon_png))
+        .route("/robots.txt", get(assets::robots_txt))
+        .route("/:collection", get(show_collection))
+        .route("/admin/unhealthy", get(admin::show_unhealthy))
+        .route("/admin/quarantine", get(admin::show_quarantine))
+        .route("/admin/reload", axum::routing::post(admin::reload_arts))
+        .route(
+            "/admin/cache",
+            get(admin::show_cache).delete(admin::evict_cache),
+        )
+        .route(
+            "/admin/export-sources",
+            axum::routing::post(admin::export_sources),
+        )
+        .route(
+            "/admin/resolve-all",
+            axum::routing::post(resolve_all::start),
+        )
+        .route("/admin/resolve-all/:job", get(resolve_all::show))
+        .route(
+            "/admin/arts",
+            axum::routing::post(admin::add_arts).delete(admin::remove_art),
+        ))
+}
+
+// monitoring hits these directly, so they skip the canonical redirect and load shedding
+pub(crate) const EXEMPT_PATHS: &[&str] = &["/version", "/stats"];
+
+fn with_middleware(routes: Router<AppState>, state: &AppState) -> Result<Router, StartupError> {
+    Ok(routes
+        .layer(CatchPanicLayer::custom(error::handle_panic))
+        .layer(axum::middleware::from_fn(i18n::select_locale))
+        .layer(axum::middleware::from_fn(theme::select_theme))
+        .layer(axum::middleware::from_fn(canonical::redirect_to_canonical))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(load_shed::LoadShedder::new(
+                config::config().server.max_concurrent_requests,
+                config::config().server.max_waiting_requests,
+            )),
+            load_shed::shed_load,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            security::content_security_policy(state.sources).map_err(StartupError::Config)?,
+            security::security_headers,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(log_response),
+        )
+        .layer(axum::middleware::from_fn(request_id::assign_request_id))
+        .with_state(state.clone()))
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.unwrap();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("shutting down");
+    systemd::notify("STOPPING=1");
+}
+
+#[derive(Deserialize)]
+struct ArtQuery {
+    quality: Option<String>,
+    seed: Option<String>,
+    // comma separated art kinds to pick from, or to never pick
+    source: Option<String>,
+    exclude: Option<String>,
+    // 1 to include nsfw arts, if the site allows opting in
+    nsfw: Option<String>,
+    // which image of a multi image post, only for seeded rolls
+    photo: Option<usize>,
 }
 
 async fn show_art(
     headers: axum::http::HeaderMap,
+    Query(query): Query<ArtQuery>,
+    RawQuery(raw_query): RawQuery,
+    state: State<AppState>,
+) -> axum::response::Response {
+    serve_art_response(&headers, None, query, raw_query.as_deref(), &state).await
+}
+
+// like the index, but only picking from one collection
+async fn show_collection(
+    headers: axum::http::HeaderMap,
+    Path(collection): Path<String>,
+    Query(query): Query<ArtQuery>,
+    RawQuery(raw_query): RawQuery,
     state: State<AppState>,
+) -> axum::response::Response {
+    serve_art_response(
+        &headers,
+        Some(&collection),
+        query,
+        raw_query.as_deref(),
+        &state,
+    )
+    .await
+}
+
+async fn serve_art_response(
+    headers: &axum::http::HeaderMap,
+    collection: Option<&str>,
+    query: ArtQuery,
+    raw_query: Option<&str>,
+    state: &AppState,
+) -> axum::response::Response {
+    let wants_json = prefers_json(headers);
+    let mut resp = match serve_art(headers, collection, query, raw_query, state, wants_json).await {
+        Ok(resp) => resp,
+        Err(err) if wants_json => err.json().into_response(),
+        Err(err) => err.into_response(),
+    };
+    let resp_headers = resp.headers_mut();
+    resp_headers.insert(http::header::VARY, http::HeaderValue::from_static("accept"));
+    // a different art is served every time
+    resp_headers.insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static(caching::NO_CACHE),
+    );
+    resp
+}
+
+async fn serve_art(
+    headers: &axum::http::HeaderMap,
+    collection: Option<&str>,
+    query: ArtQuery,
+    raw_query: Option<&str>,
+    state: &AppState,
+    wants_json: bool,
 ) -> AppResult<axum::response::Response> {
-    let ua = headers
-        .get(http::header::USER_AGENT)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("<unknown agent>");
-    let realip = headers
-        .get("x-real-ip")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("<unknown ip>");
+    let quality = parse_quality(state, query.quality.as_deref())?;
+    let filter = PickFilter::parse(
+        state.sources,
+        query.source.as_deref(),
+        query.exclude.as_deref(),
+        query.nsfw.as_deref(),
+    )?;
 
-    println!("serving user {ua} from {realip}");
+    let mut recent = recent_arts(headers);
+    let preview_bot = !wants_json && bots::is_preview_bot(headers);
+    let (art_no, art, image_link) = match query.seed.as_deref() {
+        Some(seed) => {
+            let (art_no, art) = {
+                let data = state.data.lock().unwrap();
+                let (art_no, art) = data.pick_seeded_art(stable_hash(seed));
+                (art_no, art.clone())
+            };
+            let image_link = get_variant_link(state, &art, query.photo, quality).await?;
+            (art_no, art, image_link)
+        }
+        None if preview_bot => {
+            bots::resolve_random_art(state, collection, &filter, quality).await?
+        }
+        None => resolve_random_art(state, collection, &filter, &recent, quality).await?,
+    };
+
+    // nobody has seen the art yet, the bot only shows a preview of it
+    if preview_bot {
+        return Ok(bots::render_preview(
+            state,
+            art_no,
+            &art,
+            &image_link,
+            collection,
+        ));
+    }
+
+    state.serve_counts.record(&art.url);
+    state.served_history.record(art_no, &art, quality);
+
+    if wants_json {
+        let position = state.data.lock().unwrap().position(art_no, collection);
+        let info = api::ArtInfo::new(art_no, &art, &image_link).with_position(position);
+        return Ok(Json(info).into_response());
+    }
+
+    recent.push(art_no);
+    let recent_start = recent.len().saturating_sub(MAX_RECENT_ARTS);
+    let recent_cookie = format!(
+        "{RECENT_COOKIE}={}; Path=/; Max-Age=86400; SameSite=Lax; HttpOnly",
+        recent[recent_start..]
+            .iter()
+            .map(|no| no.to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    );
+
+    let share_url = query.seed.as_deref().map(|seed| {
+        let seed: String = form_urlencoded::byte_serialize(seed.as_bytes()).collect();
+        format!("/?seed={seed}")
+    });
+    // a random roll has no photo pinned, it's whatever the source picked
+    let photo = query.seed.is_some().then_some(query.photo).flatten();
+    let options = PageOptions {
+        share_url: share_url.as_deref(),
+        photo,
+        collection,
+        position: state.data.lock().unwrap().position(art_no, collection),
+        query: raw_query,
+        ..Default::default()
+    };
+    let page = render_page(art_no, &art, quality, &image_link, options, i18n::current());
+    let mut resp = page.into_response();
+    resp.headers_mut().insert(
+        http::header::SET_COOKIE,
+        http::HeaderValue::from_str(&recent_cookie)?,
+    );
+    Ok(resp)
+}
+
+// whether the client asked for json before html, quality values are ignored
+fn prefers_json(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get_all(http::header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+        .find(|media_type| *media_type == "application/json" || *media_type == "text/html")
+        .map_or(false, |media_type| media_type == "application/json")
+}
+
+#[derive(Deserialize)]
+struct DailyQuery {
+    date: Option<String>,
+    quality: Option<String>,
+}
 
-    let art = state.data.lock().unwrap().pick_random_art().clone();
-    let image_link = if let Some(image_link) = state.direct_links.get(&art.url) {
-        image_link.clone()
+// everyone gets the same art on the same (UTC) day
+async fn show_daily(
+    headers: axum::http::HeaderMap,
+    Query(query): Query<DailyQuery>,
+    RawQuery(raw_query): RawQuery,
+    state: State<AppState>,
+) -> AppResult<axum::response::Response> {
+    let day = match query.date {
+        Some(date) => date::parse_date(&date)?,
+        None => date::today(),
+    };
+    let quality = parse_quality(&state, query.quality.as_deref())?;
+
+    let (art_no, art) = {
+        let data = state.data.lock().unwrap();
+        let (art_no, art) = data.pick_seeded_art(day as u64);
+        (art_no, art.clone())
+    };
+    let image_link = get_image_link(&state, &art, quality).await?;
+    if bots::is_preview_bot(&headers) {
+        return Ok(bots::render_preview(
+            &state,
+            art_no,
+            &art,
+            &image_link,
+            None,
+        ));
+    }
+
+    let options = PageOptions {
+        position: state.data.lock().unwrap().position(art_no, None),
+        query: raw_query.as_deref(),
+        ..Default::default()
+    };
+    Ok(render_page(art_no, &art, quality, &image_link, options, i18n::current()).into_response())
+}
+
+#[derive(Deserialize)]
+struct PermalinkQuery {
+    quality: Option<String>,
+    // which image of a multi image post, counting from 1
+    photo: Option<usize>,
+}
+
+// permalinks can change when the cached link is refreshed, so don't cache them forever
+const PERMALINK_CACHE_CONTROL: &str = "public, max-age=3600";
+
+async fn show_art_by_id(
+    headers: axum::http::HeaderMap,
+    Path(art_no): Path<usize>,
+    Query(query): Query<PermalinkQuery>,
+    RawQuery(raw_query): RawQuery,
+    state: State<AppState>,
+) -> AppResult<axum::response::Response> {
+    let quality = parse_quality(&state, query.quality.as_deref())?;
+    let art = state
+        .data
+        .lock()
+        .unwrap()
+        .art(art_no)
+        .cloned()
+        .ok_or_else(|| AppErrorKind::NotFound("no such art".to_owned()))?;
+    let image_link = get_variant_link(&state, &art, query.photo, quality).await?;
+    if bots::is_preview_bot(&headers) {
+        return Ok(bots::render_preview(
+            &state,
+            art_no,
+            &art,
+            &image_link,
+            None,
+        ));
+    }
+
+    let options = PageOptions {
+        position: state.data.lock().unwrap().position(art_no, None),
+        photo: query.photo,
+        query: raw_query.as_deref(),
+        ..Default::default()
+    };
+    let page = render_page(art_no, &art, quality, &image_link, options, i18n::current());
+    Ok(caching::etag_response(
+        &headers,
+        page.0,
+        PERMALINK_CACHE_CONTROL,
+    ))
+}
+
+fn permalink(art_no: usize) -> String {
+    format!("/art/{art_no}")
+}
+
+// a permalink to exactly the image that was shown
+fn variant_permalink(art_no: usize, photo: Option<usize>, quality: ImageQuality) -> String {
+    with_variant(permalink(art_no), photo, quality)
+}
+
+// adds the photo and quality to a link, the default quality is left out
+fn with_variant(path: String, photo: Option<usize>, quality: ImageQuality) -> String {
+    let mut params = form_urlencoded::Serializer::new(String::new());
+    if let Some(photo) = photo {
+        params.append_pair("photo", &photo.to_string());
+    }
+    if quality != config::config().arts.image_quality {
+        params.append_pair("quality", &quality.to_string());
+    }
+    let params = params.finish();
+    if params.is_empty() {
+        path
     } else {
-        let image_link_fn = match art.kind {
-            ArtKind::Twitter => fetch_twitter_image_link,
-            ArtKind::Safebooru => fetch_safebooru_image_link,
+        format!("{path}?{params}")
+    }
+}
+
+#[derive(Deserialize)]
+struct SlideshowQuery {
+    interval: Option<u64>,
+    quality: Option<String>,
+}
+
+const DEFAULT_SLIDESHOW_INTERVAL: u64 = 30;
+// don't let slideshows hammer the upstreams
+const MIN_SLIDESHOW_INTERVAL: u64 = 5;
+
+async fn show_slideshow(
+    Query(query): Query<SlideshowQuery>,
+    RawQuery(raw_query): RawQuery,
+    state: State<AppState>,
+) -> AppResult<axum::response::Response> {
+    let interval = query
+        .interval
+        .unwrap_or(DEFAULT_SLIDESHOW_INTERVAL)
+        .max(MIN_SLIDESHOW_INTERVAL);
+    let quality = parse_quality(&state, query.quality.as_deref())?;
+
+    let (art_no, art, image_link) =
+        resolve_random_art(&state, None, &PickFilter::default(), &[], quality).await?;
+
+    let options = PageOptions {
+        slideshow_interval: Some(interval),
+        position: state.data.lock().unwrap().position(art_no, None),
+        query: raw_query.as_deref(),
+        ..Default::default()
+    };
+    Ok(render_page(art_no, &art, quality, &image_link, options, i18n::current()).into_response())
+}
+
+// FNV-1a, so hashes stay the same regardless of platform or rust version
+fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const MAX_RESOLVE_ATTEMPTS: usize = 3;
+const MAX_SKIPPED_REROLLS: usize = 10;
+// how long an art that failed to resolve is avoided for
+const FAILED_LINK_TTL: Duration = Duration::from_secs(300);
+
+// picks and resolves a random art, trying other arts if resolving one fails
+async fn resolve_random_art(
+    state: &AppState,
+    collection: Option<&str>,
+    filter: &PickFilter,
+    recent: &[usize],
+    quality: ImageQuality,
+) -> AppResult<(usize, Art, FetchedLink)> {
+    if let Some(collection) = collection {
+        let exists = state.data.lock().unwrap().has_collection(collection);
+        if !exists {
+            return Err(AppErrorKind::NotFound(format!("no collection named {collection}")).into());
+        }
+    }
+    let mut exclude = recent.to_vec();
+    let mut last_err = None;
+    for _ in 0..MAX_RESOLVE_ATTEMPTS {
+        let (art_no, art) = pick_art(state, collection, filter, &exclude, quality)?;
+        match get_image_link(state, &art, quality).await {
+            Ok(image_link) => return Ok((art_no, art, image_link)),
+            Err(err) => {
+                tracing::warn!(art_url = %art.url, error = %err, "could not resolve art, trying another one");
+                exclude.push(art_no);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("at least one art was tried"))
+}
+
+// picks a random art, avoiding ones that are likely to fail right now
+// while an upstream is degraded, cached arts and arts of healthy kinds are preferred
+fn pick_art(
+    state: &AppState,
+    collection: Option<&str>,
+    filter: &PickFilter,
+    exclude: &[usize],
+    quality: ImageQuality,
+) -> AppResult<(usize, Art)> {
+    let mut data = state.data.lock().unwrap();
+    let pick = |data: &mut Data| -> AppResult<(usize, Art)> {
+        if !data.needs_filtering(filter) {
+            let (art_no, art) = data.pick_random_art(collection, exclude);
+            return Ok((art_no, art.clone()));
+        }
+        // an empty filtered set is an error right away, rerolling wouldn't change it
+        let (art_no, art) = data
+            .pick_filtered_art(collection, filter, exclude)
+            .ok_or_else(|| AppErrorKind::NotFound("no arts match the filter".to_owned()))?;
+        Ok((art_no, art.clone()))
+    };
+    let mut picked = pick(&mut data)?;
+    for _ in 0..MAX_SKIPPED_REROLLS {
+        if !should_skip_art(state, &picked.1, quality) && !exclude.contains(&picked.0) {
+            break;
+        }
+        picked = pick(&mut data)?;
+    }
+    Ok(picked)
+}
+
+// expired failures are never looked at again, so they'd only pile up
+fn prune_failed_links(state: &AppState) {
+    state
+        .failed_links
+        .retain(|_, failed_at| failed_at.elapsed() < FAILED_LINK_TTL);
+}
+
+fn should_skip_art(state: &AppState, art: &Art, quality: ImageQuality) -> bool {
+    if state.direct_links.contains_key(&(art.url.clone(), quality)) {
+        return false;
+    }
+    let recently_failed = state
+        .failed_links
+        .get(&art.url)
+        .map_or(false, |failed_at| failed_at.elapsed() < FAILED_LINK_TTL);
+    let host = art.url.host().unwrap_or_default();
+    recently_failed
+        || state.quarantine.is_quarantined(&art.url)
+        || state.breakers.is_open(host)
+        || state.degraded_kinds.is_degraded(&art.kind)
+}
+
+fn parse_quality(state: &AppState, quality: Option<&str>) -> AppResult<ImageQuality> {
+    match quality {
+        Some(quality) => quality
+            .parse()
+            .map_err(|err: AppError| err.status(http::StatusCode::BAD_REQUEST)),
+        None => Ok(state.default_quality),
+    }
+}
+
+// gets the direct link for an art from the cache, fetching it if it isn't cached yet
+async fn get_image_link(
+    state: &AppState,
+    art: &Art,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    resolve_link(state, art, quality, true).await
+}
+
+// like get_image_link, for resolving without a visitor waiting, which doesn't count towards
+// quarantining arts and also tries quarantined ones
+async fn get_background_link(
+    state: &AppState,
+    art: &Art,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    resolve_link(state, art, quality, false).await
+}
+
+async fn resolve_link(
+    state: &AppState,
+    art: &Art,
+    quality: ImageQuality,
+    live: bool,
+) -> AppResult<FetchedLink> {
+    let cache_key = (art.url.clone(), quality);
+    let span = tracing::Span::current();
+    span.record("art_url", tracing::field::display(&art.url));
+    if let Some(image_link) = state.direct_links.get(&cache_key) {
+        span.record("cache_hit", true);
+        state.fetch_counters.record_hit();
+        tracing::debug!(art_url = %art.url, kind = %art.kind, "image link cache hit");
+        return Ok(image_link.clone());
+    }
+    if let Some(image_link) = shared_cache::load(state, &cache_key).await {
+        span.record("cache_hit", true);
+        state.fetch_counters.record_hit();
+        tracing::debug!(art_url = %art.url, kind = %art.kind, "image link shared cache hit");
+        return Ok(image_link);
+    }
+    span.record("cache_hit", false);
+    state.fetch_counters.record_miss();
+
+    let host = art.url.host().unwrap_or_default();
+    if state.breakers.is_open(host) {
+        let err = AppErrorKind::UpstreamUnavailable(format!("{host} is being skipped for a while"));
+        return Err(AppError::from(err).status(http::StatusCode::SERVICE_UNAVAILABLE));
+    }
+    let _probe = if live {
+        let Some(probe) = state.quarantine.allows(&art.url) else {
+            let err = AppErrorKind::UpstreamUnavailable(format!(
+                "{} kept failing and is being skipped for a while",
+                art.url
+            ));
+            return Err(AppError::from(err).status(http::StatusCode::SERVICE_UNAVAILABLE));
         };
-        let image_link = (image_link_fn)(&state.http, &art.url).await?;
-        state
-            .direct_links
-            .insert(art.url.clone(), image_link.clone());
-        image_link
+        Some(probe)
+    } else {
+        None
     };
 
-    let page = render_page(&art, &image_link);
-    Ok(page.into_response())
+    let start = Instant::now();
+    let result = fetch_limited(state, art, quality).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let image_link = match result {
+        Ok(image_link) => {
+            state.breakers.record_success(host);
+            state.degraded_kinds.record(art.kind, false);
+            state.failed_links.remove(&art.url);
+            if live {
+                state.quarantine.record_success(&art.url);
+            }
+            image_link
+        }
+        Err(err) => {
+            if err.is_upstream_unavailable() {
+                state.breakers.record_failure(host);
+            }
+            state.fetch_counters.record_failure(&art.kind);
+            state.degraded_kinds.record(art.kind, true);
+            state.failed_links.insert(art.url.clone(), Instant::now());
+            if live {
+                state.quarantine.record_failure(&art.url);
+            }
+            tracing::error!(
+                art_url = %art.url,
+                kind = %art.kind,
+                latency_ms,
+                upstream_status = err.upstream_status().map(|status| status.as_u16()),
+                error = %err,
+                "could not fetch image link"
+            );
+            return Err(err);
+        }
+    };
+    tracing::info!(art_url = %art.url, kind = %art.kind, latency_ms, "fetched image link");
+    preview::spawn(state, cache_key.clone(), &image_link);
+    shared_cache::store(state, cache_key.clone(), &image_link);
+    state.direct_links.insert(cache_key, image_link.clone());
+    Ok(image_link)
+}
+
+// like get_image_link, but for one image of a multi image post if a photo is given
+// the same photo and quality always resolve to the same image, they are cached on their own
+async fn get_variant_link(
+    state: &AppState,
+    art: &Art,
+    photo: Option<usize>,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    match photo {
+        Some(photo) => get_image_link(state, &photo_art(state, art, photo)?, quality).await,
+        None => get_image_link(state, art, quality).await,
+    }
+}
+
+// the art with its url pointing at one of its images, which is what that image is cached under
+fn photo_art(state: &AppState, art: &Art, photo: usize) -> AppResult<Art> {
+    if photo == 0 {
+        return Err(AppErrorKind::BadRequest("photos are counted from 1".to_owned()).into());
+    }
+    let photo_url = state
+        .sources
+        .get(art.url.host().unwrap_or_default())
+        .and_then(|source| source.photo(&art.url, photo));
+    match photo_url {
+        Some(url) => Ok(Art { url, ..art.clone() }),
+        // every art has a first image
+        None if photo == 1 => Ok(art.clone()),
+        None => Err(AppErrorKind::NotFound(format!("{} has no photo {photo}", art.url)).into()),
+    }
 }
 
-const BODY_STYLE: &str =
-"color: #ffffff; margin: 0px; background: #0e0e0e; height: 100vh; width: 100vw; display: flex; font-family: \"PT Mono\", monospace; font-weight: 400; font-style: normal; font-optical-sizing: auto;";
-const ABOUT_STYLE: &str = "font-size: 1vmax; color: #ffffff;";
+const RECENT_COOKIE: &str = "recent";
+const MAX_RECENT_ARTS: usize = 10;
 
-fn get_page_head_common() -> PreEscaped<String> {
-    let title = get_conf("SITE_TITLE", "random project moon art");
-    let embed_title = get_conf("EMBED_TITLE", "random project moon art");
-    let embed_content = get_conf("EMBED_DESC", "random project moon art");
-    let embed_color = get_conf("EMBED_COLOR", "#ffffff");
+// the arts recently served to this client, so we can avoid repeating them
+fn recent_arts(headers: &axum::http::HeaderMap) -> Vec<usize> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|cookie| cookie.trim().strip_prefix(RECENT_COOKIE)?.strip_prefix('='))
+        .flat_map(|v| v.split('.'))
+        .filter_map(|no| no.parse().ok())
+        .take(MAX_RECENT_ARTS)
+        .collect()
+}
+
+fn get_page_head_common(collection: Option<&str>) -> PreEscaped<String> {
+    maud::html! {
+        (get_embed_meta(collection, None))
+        (get_page_assets())
+    }
+}
+
+// what the embed of a page showing an art can tell about it
+struct EmbedArt<'a> {
+    // the average color of the art, if we know it
+    color: Option<&'a str>,
+    artist: Option<&'a str>,
+    source_host: &'a str,
+    kind: String,
+    art_id: usize,
+    total_count: Option<usize>,
+}
+
+// fills in {artist}, {source_host}, {kind}, {art_id} and {total_count}
+// without an art, or a value for them, they are left out, unknown placeholders are kept as written
+fn fill_embed_placeholders(template: &str, art: Option<&EmbedArt>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start..start + len + 1];
+        let name = &placeholder[1..placeholder.len() - 1];
+        let value = match name {
+            "artist" => art.and_then(|art| art.artist.map(str::to_owned)),
+            "source_host" => art.map(|art| art.source_host.to_owned()),
+            "kind" => art.map(|art| art.kind.clone()),
+            "art_id" => art.map(|art| art.art_id.to_string()),
+            "total_count" => art
+                .and_then(|art| art.total_count)
+                .map(|total| total.to_string()),
+            _ => Some(placeholder.to_owned()),
+        };
+        filled.push_str(&value.unwrap_or_default());
+        rest = &rest[start + len + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+// the title and embed texts, with the color and placeholders filled in for the art if there is one
+fn get_embed_meta(collection: Option<&str>, art: Option<&EmbedArt>) -> PreEscaped<String> {
+    let config = config::config();
+    let title = &config.site.title;
+    // collections can have their own title
+    let embed_title = fill_embed_placeholders(&config.embed.title(collection), art);
+    let embed_content = fill_embed_placeholders(&config.embed.description, art);
+    // the art's own color, or the page's when the visitor picked a theme
+    let embed_color = art
+        .and_then(|art| art.color)
+        .or_else(|| theme::current().map(theme::Theme::background))
+        .unwrap_or(&config.embed.color);
 
     maud::html! {
         meta charset="utf8";
         meta property="og:title" content=(embed_title);
         meta property="og:description" content=(embed_content);
         meta name="theme-color" content=(embed_color);
-        link rel="preconnect" href="https://fonts.googleapis.com";
-        link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
-        link rel="stylesheet" href="https://fonts.googleapis.com/css2?family=PT+Mono&display=swap";
-        link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/@chgibb/css-spinners@2.2.1/css/spinners.min.css";
         title { (title) }
     }
 }
 
-fn get_page_contact() -> PreEscaped<String> {
+// what a browser needs to show our pages, preview bots don't
+fn get_page_assets() -> PreEscaped<String> {
+    maud::html! {
+        link rel="icon" type="image/png" href="/favicon.png";
+        // serving these ourselves means visitors don't have to talk to any third party
+        @if config::config().site.use_cdn {
+            link rel="preconnect" href="https://fonts.googleapis.com";
+            link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
+            link rel="stylesheet" href="https://fonts.googleapis.com/css2?family=PT+Mono&display=swap";
+            link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/@chgibb/css-spinners@2.2.1/css/spinners.min.css";
+        } @else {
+            link rel="stylesheet" href=(assets::asset_url("fonts.css"));
+            link rel="stylesheet" href=(assets::asset_url("spinners.css"));
+        }
+        (theme::page_style())
+    }
+}
+
+// the title and embed metadata for a specific art
+fn get_art_meta(
+    art_no: usize,
+    art: &Art,
+    image_link: &FetchedLink,
+    collection: Option<&str>,
+    total_count: Option<usize>,
+) -> PreEscaped<String> {
+    // og:url has to be absolute, so we can only emit it if we know where we are hosted
+    let site_url = config::config().site.url.trim_end_matches('/');
+    let art_url = (!site_url.is_empty()).then(|| format!("{site_url}{}", permalink(art_no)));
+    let oembed_url = art_url.as_ref().map(|art_url| {
+        let art_url: String = form_urlencoded::byte_serialize(art_url.as_bytes()).collect();
+        format!("{site_url}/oembed?url={art_url}&format=json")
+    });
+    let embed_art = EmbedArt {
+        color: image_link
+            .preview
+            .as_ref()
+            .map(|preview| preview.color.as_str()),
+        artist: image_link.artist.as_deref().or_else(|| art.artist()),
+        source_host: art.url.host().unwrap_or_default(),
+        kind: art.kind.to_string(),
+        art_id: art_no,
+        total_count,
+    };
+
+    maud::html! {
+        (get_embed_meta(collection, Some(&embed_art)))
+        meta property="og:image" content=(image_link.image_url);
+        meta name="twitter:card" content="summary_large_image";
+        @if let Some(art_url) = art_url {
+            meta property="og:url" content=(art_url);
+        }
+        @if let Some(oembed_url) = oembed_url {
+            link rel="alternate" type="application/json+oembed" href=(oembed_url);
+        }
+    }
+}
+
+fn get_page_contact(strings: &i18n::Strings) -> PreEscaped<String> {
+    let site = &config::config().site;
+    // env vars can't easily hold newlines, so a literal \n splits lines too
+    let lines = match &site.contact_text {
+        Some(text) => text
+            .split('\n')
+            .flat_map(|line| line.split("\\n"))
+            .collect::<Vec<_>>(),
+        None => vec![strings.made_by, strings.report_problems],
+    };
+    let contact = maud::html! {
+        @for (i, line) in lines.iter().enumerate() {
+            @if i > 0 { br; }
+            (line)
+        }
+    };
     maud::html! {
-        a style=(format!("{ABOUT_STYLE} right: 0;")) href="https://gaze.systems" target="_blank" {
-            "website made by dusk"
-            br;
-            "report problems / feedback @ yusdacra on Discord"
+        @if site.contact_url.is_empty() {
+            span class="about" { (contact) }
+        } @else {
+            a class="about" href=(site.contact_url) target="_blank" { (contact) }
         }
     }
 }
 
-fn render_page(art: &Art, image_link: &FetchedLink) -> Html<String> {
+#[derive(Default)]
+struct PageOptions<'a> {
+    share_url: Option<&'a str>,
+    // swap to a new art every this many seconds
+    slideshow_interval: Option<u64>,
+    // only pick new arts from this collection
+    collection: Option<&'a str>,
+    // shown as "art 57 of 312"
+    position: Option<(usize, usize)>,
+    // the image of a multi image post that was asked for
+    photo: Option<usize>,
+    // what the page was requested with, so the quality toggle keeps the rest of it
+    query: Option<&'a str>,
+}
+
+fn render_page(
+    art_no: usize,
+    art: &Art,
+    quality: ImageQuality,
+    image_link: &FetchedLink,
+    options: PageOptions,
+    strings: &i18n::Strings,
+) -> Html<String> {
     let art_url = image_link.new_source.as_ref().unwrap_or(&art.url);
+    let image_src = image_src(image_link);
     let content = maud::html! {
         (maud::DOCTYPE)
         head {
-            (get_page_head_common())
+            (get_art_meta(art_no, art, image_link, options.collection, options.position.map(|(_, total)| total)))
+            (get_page_assets())
+            // starts loading the image before the body is parsed, it has to be the exact same url
+            // and referrer policy as the img or it's downloaded twice
+            link rel="preload" as="image" href=(image_src) referrerpolicy="no-referrer";
+            @if options.slideshow_interval.is_some() {
+                // only show the info bar when hovering over it, so it doesn't cover the art
+                style { "#info { opacity: 0; transition: opacity 0.5s; } #info:hover { opacity: 1; }" }
+            }
         }
-        body style=(BODY_STYLE) {
+        body data-quality=(quality) data-slideshow=[options.slideshow_interval] data-collection=[options.collection] data-source-label=(strings.source) data-position-label=(strings.art_position) data-added-label=(strings.added) {
             div style="display: block; margin: auto; max-height: 98vh; max-width: 98vw;" {
                 div class="throbber-loader" style="position: absolute; top: 50%; left: 50%; z-index: -1;" {}
-                img style="max-height: 98vh; max-width: 98vw;" src=(image_link.image_url);
+                img #art style=(img_style(image_link)) width=[image_link.width] height=[image_link.height] referrerpolicy="no-referrer" src=(image_src) alt=[image_link.description.as_deref()] title=[image_link.description.as_deref()];
             }
-            div style="position: absolute; bottom: 0; display: flex; flex-direction: column; gap: 2vh; background-color: #0e0e0eaa;" {
-                a style=(format!("{ABOUT_STYLE} left: 0;")) href=(art_url) target="_blank" {
-                    "source: " (art_url)
+            div #info style="position: absolute; bottom: 0; display: flex; flex-direction: column; gap: 2vh; background-color: color-mix(in srgb, var(--bg-color) 67%, transparent);" {
+                a #source class="about" href=(art_url) target="_blank" {
+                    (strings.source) (art_url)
                 }
-                (get_page_contact())
+                @if let Some((position, total)) = options.position {
+                    a #position class="about" href=(variant_permalink(art_no, options.photo, quality)) data-position=(position) data-total=(total) {
+                        (strings.art_position(position, total))
+                    }
+                }
+                // kept even for undated arts, so the page script can fill it in for the next art
+                span #added class="about" hidden[art.added_at.is_none()] data-added=[art.added_at.map(date::format_rfc3339)] {
+                    @if let Some(added_at) = art.added_at {
+                        (strings.added) (date::format_date(added_at))
+                    }
+                }
+                @if let Some(share_url) = options.share_url {
+                    a #share class="about" href=(share_url) {
+                        (strings.share)
+                    }
+                }
+                // without js this is just a link to a new random art
+                a #next class="about" href=(next_href(options.collection, quality)) {
+                    (strings.next)
+                }
+                a #quality class="about" href=(quality_toggle_href(options.query, quality)) {
+                    @if quality == ImageQuality::Low { (strings.full_quality) } @else { (strings.low_bandwidth) }
+                }
+                a #download class="about" href=(proxy::download_path(&image_link.image_url, &proxy::download_name(art, art_no))) {
+                    (strings.download)
+                }
+                (get_page_contact(strings))
             }
+            script { (PreEscaped(PAGE_SCRIPT)) }
         }
     };
     Html(content.into_string())
 }
 
-fn fetch_safebooru_image_link<'a>(
-    http: &'a reqwest::Client,
-    url: &'a Uri,
-) -> BoxFuture<'a, AppResult<FetchedLink>> {
-    _fetch_safebooru_image_link(http, url).boxed()
+// the current query with low bandwidth mode switched, like the seed and photo that are shown
+fn quality_toggle_href(query: Option<&str>, quality: ImageQuality) -> String {
+    let mut params = form_urlencoded::Serializer::new(String::new());
+    params.extend_pairs(
+        form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .filter(|(name, _)| name != "quality"),
+    );
+    if quality != ImageQuality::Low {
+        params.append_pair("quality", "low");
+    } else if config::config().arts.image_quality == ImageQuality::Low {
+        params.append_pair("quality", "sample");
+    }
+    format!("?{}", params.finish())
 }
 
-fn fetch_twitter_image_link<'a>(
-    http: &'a reqwest::Client,
-    url: &'a Uri,
-) -> BoxFuture<'a, AppResult<FetchedLink>> {
-    _fetch_twitter_image_link(http, url).boxed()
+// low bandwidth mode sticks while clicking through arts
+fn next_href(collection: Option<&str>, quality: ImageQuality) -> String {
+    let path = collection.map_or_else(|| "/".to_owned(), |collection| format!("/{collection}"));
+    if quality == ImageQuality::Low {
+        format!("{path}?quality=low")
+    } else {
+        path
+    }
 }
 
-async fn _fetch_safebooru_image_link(http: &reqwest::Client, url: &Uri) -> AppResult<FetchedLink> {
-    let mut id = String::new();
-    for (name, value) in form_urlencoded::parse(url.query().unwrap().as_bytes()) {
-        if name == "id" {
-            id = value.into_owned();
+// with a known size the browser reserves space for the image before it loads
+fn img_style(image_link: &FetchedLink) -> String {
+    let mut style = "max-height: 98vh; max-width: 98vw;".to_owned();
+    if let (Some(width), Some(height)) = (image_link.width, image_link.height) {
+        style.push_str(&format!(" height: auto; aspect-ratio: {width} / {height};"));
+        // the image covers its own background once it loads, until then it covers the throbber
+        if let Some(preview) = &image_link.preview {
+            style.push_str(&format!(
+                " background: url({}) center / 100% 100% no-repeat;",
+                preview.placeholder
+            ));
         }
     }
-    if id.is_empty() {
-        return Err("no id?".into());
-    }
-
-    let url = format!("https://safebooru.org/index.php?page=dapi&s=post&q=index&json=1&id={id}");
-    type Data = Vec<serde_json::Map<String, serde_json::Value>>;
-    let try_request = || {
-        let url = url.clone();
-        let http = http.clone();
-        async move {
-            println!("[safebooru] trying to fetch url: {url}");
-            let req = http.get(url).build()?;
-            let resp = http.execute(req).await?.error_for_status()?;
-            let data = resp.json::<Data>().await?;
-            AppResult::Ok(data)
-        }
+    style
+}
+
+fn image_src(image_link: &FetchedLink) -> String {
+    if config::config().site.proxy_images {
+        proxy::image_path(&image_link.image_url)
+    } else {
+        image_link.image_url.clone()
+    }
+}
+
+// swaps arts in place using the json api, so we don't reload the whole page for every art
+const PAGE_SCRIPT: &str = r#"
+const quality = document.body.dataset.quality;
+const collection = document.body.dataset.collection;
+const previous = [];
+
+// the placeholder would show through transparent images, so drop it once the image is there
+const artImg = document.getElementById("art");
+const dropPlaceholder = () => { artImg.style.background = ""; };
+if (artImg.complete) dropPlaceholder(); else artImg.addEventListener("load", dropPlaceholder);
+
+function currentArt() {
+    const img = document.getElementById("art");
+    const source = document.getElementById("source");
+    const position = document.getElementById("position");
+    return {
+        image_url: img.src,
+        source: source.href,
+        description: img.alt,
+        position: position?.dataset.position,
+        total: position?.dataset.total,
+        permalink: position?.href,
+        added_at: document.getElementById("added")?.dataset.added,
     };
+}
 
-    let mut attempts: usize = 0;
-    let (data, _) = futures_retry::FutureRetry::new(try_request, |e| {
-        if attempts > 4 {
-            futures_retry::RetryPolicy::<error::AppError>::ForwardError(e)
+function showArt(art, preload) {
+    const img = document.getElementById("art");
+    const swap = () => {
+        img.style.background = "";
+        img.src = art.image_url;
+        img.alt = img.title = art.description ?? "";
+        if (art.width && art.height) {
+            img.width = art.width;
+            img.height = art.height;
+            img.style.height = "auto";
+            img.style.aspectRatio = art.width + " / " + art.height;
         } else {
-            attempts += 1;
-            println!("[safebooru] retrying url fetch (attempt {attempts}): {url}");
-            futures_retry::RetryPolicy::<error::AppError>::Repeat
+            img.removeAttribute("width");
+            img.removeAttribute("height");
+            img.style.aspectRatio = "";
         }
-    })
-    .await
-    .map_err(|(e, _)| e)?;
-
-    let source_url = data[0]
-        .get("source")
-        .and_then(|src| Uri::from_str(src.as_str()?).ok())
-        .map(|src| {
-            if src.host() == Some("i.pximg.net") {
-                let post_id = src
-                    .path()
-                    .split('/')
-                    .last()
-                    .unwrap()
-                    .split("_")
-                    .next()
-                    .unwrap();
-                return Uri::builder()
-                    .scheme("https")
-                    .authority("pixiv.net")
-                    .path_and_query(format!("/en/artworks/{post_id}"))
-                    .build()
-                    .unwrap();
-            } else {
-                src
-            }
-        });
-
-    if source_url.as_ref().map_or(false, |src| {
-        src.host().unwrap().contains("twitter.com") || src.host().unwrap().contains("x.com")
-    }) {
-        let url = source_url.clone().unwrap();
-        println!("[safebooru] source was twitter, will try to fetch image from there instead");
-        if let Ok(mut fetched) = _fetch_twitter_image_link(http, &url).await {
-            println!("[safebooru] fetched image from twitter");
-            fetched.new_source = Some(url);
-            return Ok(fetched);
+        const source = document.getElementById("source");
+        source.href = art.source;
+        source.textContent = document.body.dataset.sourceLabel + art.source;
+        document.getElementById("download").href = art.download_url;
+        document.getElementById("share")?.remove();
+        const position = document.getElementById("position");
+        if (position && art.position) {
+            position.href = art.permalink ?? "/art/" + art.id + "?quality=" + quality;
+            position.dataset.position = art.position;
+            position.dataset.total = art.total;
+            position.textContent = document.body.dataset.positionLabel
+                .replace("{position}", art.position)
+                .replace("{total}", art.total);
+        } else {
+            position?.remove();
+        }
+        const added = document.getElementById("added");
+        added.hidden = !art.added_at;
+        if (art.added_at) {
+            added.dataset.added = art.added_at;
+            // the date part of the rfc 3339 timestamp, like the page shows it
+            added.textContent = document.body.dataset.addedLabel + art.added_at.slice(0, 10);
+        } else {
+            delete added.dataset.added;
+            added.textContent = "";
         }
+    };
+    if (preload) {
+        // load the image before swapping so we don't show a half loaded one
+        const next = new Image();
+        next.referrerPolicy = "no-referrer";
+        next.onload = swap;
+        next.src = art.image_url;
+    } else {
+        // hide the old image so the throbber shows while the new one loads
+        img.style.visibility = "hidden";
+        img.onload = () => { img.style.visibility = "visible"; };
+        swap();
     }
+}
 
-    let sample_url = data[0]
-        .get("sample_url")
-        .ok_or("safebooru did not return sample url")?
-        .as_str()
-        .ok_or("safebooru sample url wasnt a string")?;
-    let sample_url = Uri::from_str(sample_url)
-        .map_err(|err| AppError::from(format!("safebooru sample url was not valid: {err}")))?;
+async function nextArt(preload) {
+    let url = "/api/random?quality=" + quality;
+    if (collection) url += "&collection=" + encodeURIComponent(collection);
+    // keep picking from the same sources the page was opened with
+    const params = new URLSearchParams(location.search);
+    for (const key of ["source", "exclude", "nsfw"]) {
+        if (params.get(key)) url += "&" + key + "=" + encodeURIComponent(params.get(key));
+    }
+    const resp = await fetch(url);
+    if (!resp.ok) return;
+    const art = await resp.json();
+    previous.push(currentArt());
+    showArt(art, preload);
+}
 
-    let fsample_url = format!(
-        "{}://{}{}",
-        sample_url.scheme_str().unwrap(),
-        sample_url.host().unwrap(),
-        sample_url.path()
-    );
-    let ssample_url = format!(
-        "{}://{}/{}",
-        sample_url.scheme_str().unwrap(),
-        sample_url.host().unwrap(),
-        sample_url.path()
-    );
+function previousArt() {
+    const art = previous.pop();
+    if (art) showArt(art, false);
+}
 
-    let fsample_resp = http
-        .execute(http.get(&fsample_url).build()?)
-        .await
-        .and_then(|resp| resp.error_for_status());
-    let ssample_resp = http
-        .execute(http.get(&ssample_url).build()?)
-        .await
-        .and_then(|resp| resp.error_for_status());
+document.getElementById("next").addEventListener("click", (event) => {
+    event.preventDefault();
+    nextArt(false);
+});
+document.addEventListener("keydown", (event) => {
+    if (event.key === "ArrowRight" || event.key === " ") {
+        event.preventDefault();
+        nextArt(false);
+    } else if (event.key === "ArrowLeft") {
+        previousArt();
+    }
+});
 
-    let sample_url = fsample_resp
-        .is_ok()
-        .then(|| fsample_url)
-        .or_else(|| ssample_resp.is_ok().then(|| ssample_url))
-        .unwrap_or_else(|| sample_url.to_string());
+const interval = document.body.dataset.slideshow;
+if (interval) setInterval(() => nextArt(true), interval * 1000);
+"#;
 
-    Ok(FetchedLink {
-        image_url: sample_url,
-        new_source: source_url,
+// fetches through the per-source concurrency limit, with a timeout covering the wait too
+async fn fetch_limited(
+    state: &AppState,
+    art: &Art,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    let source = art.kind.to_string();
+    let timeout = Duration::from_secs(config::config().upstream.fetch_timeout_secs);
+    limits::with_timeout(timeout, &source, async {
+        let _permit = state.source_limits.acquire(&source).await;
+        state.sources.fetch(&state.http, art, quality).await
     })
+    .await
 }
 
-async fn _fetch_twitter_image_link(http: &reqwest::Client, url: &Uri) -> AppResult<FetchedLink> {
-    let fxurl = Uri::builder()
-        .scheme("https")
-        .authority("d.fxtwitter.com")
-        .path_and_query(url.path_and_query().unwrap().clone())
-        .build()?
-        .to_string();
-    println!("[fxtwitter] trying to fetch url: {fxurl}");
-    let req = http.get(&fxurl).build()?;
-    let resp = http.execute(req).await?.error_for_status()?;
-    let link = resp
+// every request gets its own span, so log lines from concurrent requests can be told apart
+fn make_request_span(req: &axum::extract::Request) -> tracing::Span {
+    let request_id = request_id::of(req);
+    let user_agent = req
         .headers()
-        .get(http::header::LOCATION)
-        .ok_or_else(|| format!("twitter link {fxurl} did not return an image location"))?
-        .to_str()?;
-    // use webp format for direct twitter links since webp is cheaper
-    Ok(FetchedLink {
-        image_url: format!("{link}?format=webp"),
-        new_source: None,
-    })
+        .get(http::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("<unknown agent>");
+    let client_ip = client_ip::ClientIp::from_request(req);
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+        %client_ip,
+        user_agent,
+        // filled in once we know which art is served
+        art_url = tracing::field::Empty,
+        cache_hit = tracing::field::Empty,
+    )
 }
 
-fn get_conf(name: &str, default: &str) -> String {
-    std::env::var(name).unwrap_or_else(|_| default.to_owned())
+// logged for every response, errors included, with the request span carrying the rest
+fn log_response(resp: &axum::response::Response, latency: Duration, _span: &tracing::Span) {
+    tracing::info!(
+        status = resp.status().as_u16(),
+        duration_ms = latency.as_millis() as u64,
+        "finished request"
+    );
 }
 
 struct InternalAppState {
-    // cached direct links to images
-    direct_links: DashMap<Uri, FetchedLink>,
+    // upstreams we are skipping because they keep failing
+    breakers: breaker::CircuitBreakers,
+    source_limits: limits::SourceLimits,
+    degraded_kinds: degraded::DegradedKinds,
+    // arts that keep failing to resolve
+    quarantine: quarantine::Quarantine,
+    // arts that recently failed to resolve, and when
+    failed_links: DashMap<Uri, Instant>,
+    // cached direct links to images, per requested quality
+    direct_links: DashMap<(Uri, ImageQuality), FetchedLink>,
+    // links being resolved through /api/resolve right now
+    resolving: DashMap<(Uri, ImageQuality), ()>,
+    resolve_jobs: resolve_all::Jobs,
+    // where direct_links are shared with other instances, if anywhere
+    shared_links: Option<Arc<dyn shared_cache::SharedCache>>,
+    // where runtime state is kept across restarts, if anywhere
+    db: Option<db::Db>,
     data: Mutex<Data>,
+    arts_path: String,
+    blocklist_path: String,
+    // held while reading or writing the arts file, so reloads and submissions don't interleave
+    arts_file_lock: Mutex<()>,
+    serve_counts: stats::ServeCounts,
+    fetch_counters: stats::FetchCounters,
+    distinct_picks: distinct::DistinctPicks,
+    served_history: history::ServedHistory,
     http: reqwest::Client,
+    // the sources arts are fetched with
+    sources: &'static sources::Registry,
+    default_quality: ImageQuality,
+    started_at: std::time::SystemTime,
 }
 
 #[derive(Clone)]
@@ -307,25 +1504,86 @@ struct AppState {
 }
 
 impl AppState {
-    fn new(data: Data) -> Self {
+    fn new(
+        data: Data,
+        default_quality: ImageQuality,
+        arts_path: String,
+        blocklist_path: String,
+        http: reqwest::Client,
+        shared_links: Option<Arc<dyn shared_cache::SharedCache>>,
+        db: Option<db::Db>,
+        sources: &'static sources::Registry,
+    ) -> Self {
         Self {
             internal: Arc::new(InternalAppState {
+                breakers: breaker::CircuitBreakers::new(
+                    config::config().upstream.breaker_threshold,
+                    Duration::from_secs(config::config().upstream.breaker_cooldown_secs),
+                ),
+                source_limits: limits::SourceLimits::new(
+                    config::config()
+                        .upstream
+                        .source_concurrency_overrides
+                        .clone(),
+                    config::config().upstream.source_concurrency,
+                ),
+                degraded_kinds: degraded::DegradedKinds::new(
+                    config::config().cache.degraded_failure_rate,
+                ),
+                quarantine: quarantine::Quarantine::new(
+                    config::config().cache.quarantine_threshold,
+                ),
                 data: Mutex::new(data),
+                arts_path,
+                blocklist_path,
+                arts_file_lock: Mutex::new(()),
+                fetch_counters: Default::default(),
+                distinct_picks: Default::default(),
+                served_history: history::ServedHistory::new(config::config().arts.history_size),
+                serve_counts: match &config::config().arts.stats_path {
+                    Some(path) => stats::ServeCounts::load(path),
+                    None => Default::default(),
+                },
                 direct_links: Default::default(),
-                http: reqwest::ClientBuilder::new()
-                    .redirect(reqwest::redirect::Policy::none())
-                    .user_agent(format!(
-                        "{}/{}",
-                        env!("CARGO_PKG_NAME"),
-                        env!("CARGO_PKG_VERSION")
-                    ))
-                    .build()
-                    .unwrap(),
+                resolving: Default::default(),
+                resolve_jobs: Default::default(),
+                shared_links,
+                db,
+                failed_links: Default::default(),
+                default_quality,
+                started_at: std::time::SystemTime::now(),
+                sources,
+                http,
             }),
         }
     }
 }
 
+fn build_http_client() -> Result<reqwest::Client, String> {
+    let upstream = &config::config().upstream;
+    let user_agent = upstream
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    let mut builder = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(user_agent);
+    if let Some(proxy_url) = &upstream.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url).map_err(|err| {
+            format!("UPSTREAM_PROXY {proxy_url} is not a valid http, https or socks5 url: {err}")
+        })?;
+        builder = builder.proxy(proxy);
+    }
+    // only for running behind a tls intercepting proxy
+    if upstream.accept_invalid_certs {
+        tracing::warn!("accepting invalid certificates from upstreams");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder
+        .build()
+        .map_err(|err| format!("could not build the http client: {err}"))
+}
+
 impl Deref for AppState {
     type Target = InternalAppState;
 
@@ -333,3 +1591,25 @@ impl Deref for AppState {
         &self.internal
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_toggle_keeps_the_query() {
+        config::init_default();
+        assert_eq!(
+            quality_toggle_href(Some("seed=abc&photo=2"), ImageQuality::Sample),
+            "?seed=abc&photo=2&quality=low"
+        );
+        assert_eq!(
+            quality_toggle_href(Some("quality=low&seed=abc"), ImageQuality::Low),
+            "?seed=abc"
+        );
+        assert_eq!(
+            quality_toggle_href(None, ImageQuality::Sample),
+            "?quality=low"
+        );
+    }
+}
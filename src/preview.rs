@@ -0,0 +1,100 @@
+use std::io::Cursor;
+
+use base64::Engine;
+use futures_util::StreamExt;
+use http::Uri;
+use image::{imageops::FilterType, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{AppErrorKind, AppResult},
+    proxy::MAX_PROXY_SIZE,
+    shared_cache, AppState,
+};
+
+// the placeholder is scaled up and smoothed by the browser, so it only needs a few pixels
+const PLACEHOLDER_SIZE: u32 = 16;
+
+// what an image looks like before it has loaded
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Preview {
+    // a tiny version of the image as a data uri
+    pub(crate) placeholder: String,
+    // the average color of the image, like #1e1e1e
+    pub(crate) color: String,
+}
+
+// computes the preview of a freshly resolved link in the background, so resolving doesn't wait on
+// downloading the whole image, the link is served without one until it's done
+pub(crate) fn spawn(state: &AppState, cache_key: (Uri, ImageQuality), image_link: &FetchedLink) {
+    if !config().cache.previews {
+        return;
+    }
+    let state = state.clone();
+    let image_url = image_link.image_url.clone();
+    tokio::spawn(async move {
+        let preview = match compute(&state.http, &image_url).await {
+            Ok(preview) => preview,
+            Err(err) => {
+                tracing::debug!(image_url, error = %err, "could not compute image preview");
+                return;
+            }
+        };
+        // the link could have been refreshed while we were at it
+        let updated = state
+            .direct_links
+            .get_mut(&cache_key)
+            .filter(|image_link| image_link.image_url == image_url)
+            .map(|mut image_link| {
+                image_link.preview = Some(preview);
+                image_link.clone()
+            });
+        if let Some(image_link) = updated {
+            shared_cache::store(&state, cache_key, &image_link);
+        }
+    });
+}
+
+async fn compute(http: &reqwest::Client, image_url: &str) -> AppResult<Preview> {
+    let resp = http
+        .execute(http.get(image_url).build()?)
+        .await?
+        .error_for_status()?;
+    let too_large = || AppErrorKind::UpstreamUnavailable("upstream image is too large".to_owned());
+    if resp
+        .content_length()
+        .map_or(false, |len| len > MAX_PROXY_SIZE)
+    {
+        return Err(too_large().into());
+    }
+    let mut bytes = Vec::new();
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() as u64 > MAX_PROXY_SIZE {
+            return Err(too_large().into());
+        }
+    }
+
+    // decoding a big image takes a while, so keep it off the async workers
+    tokio::task::spawn_blocking(move || -> AppResult<Preview> {
+        let image = image::load_from_memory(&bytes)?;
+        let thumbnail = image.resize(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, FilterType::Triangle);
+        let mut png = Vec::new();
+        thumbnail.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(png);
+        // scaling down to one pixel averages every pixel of the thumbnail
+        let [r, g, b] = thumbnail
+            .resize_exact(1, 1, FilterType::Triangle)
+            .to_rgb8()
+            .get_pixel(0, 0)
+            .0;
+        Ok(Preview {
+            placeholder: format!("data:image/png;base64,{encoded}"),
+            color: format!("#{r:02x}{g:02x}{b:02x}"),
+        })
+    })
+    .await?
+}
@@ -0,0 +1,245 @@
+use std::sync::OnceLock;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use http::{header, HeaderValue, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    caching,
+    config::config,
+    data::Art,
+    error::{AppError, AppErrorKind, AppResult},
+    AppState,
+};
+
+// don't proxy anything bigger than this, no art should be this big
+pub(crate) const MAX_PROXY_SIZE: u64 = 20 * 1024 * 1024;
+
+static SIGNING_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+// links are only signed for images we resolved ourselves, so this can't be used as an open proxy
+fn signing_key() -> &'static [u8; 32] {
+    SIGNING_KEY.get_or_init(|| {
+        let key = &config().server.proxy_signing_key;
+        if !key.is_empty() {
+            return Sha256::digest(key.as_bytes()).into();
+        }
+        tracing::warn!("no PROXY_SIGNING_KEY set, image links will stop working on restart");
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("could not generate a proxy signing key");
+        key
+    })
+}
+
+fn mac(image_url: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key()).expect("hmac takes any key size");
+    mac.update(image_url.as_bytes());
+    mac
+}
+
+// url and signature are path segments, so both are url safe base64
+fn signed_path(route: &str, image_url: &str) -> String {
+    let url = URL_SAFE_NO_PAD.encode(image_url);
+    let signature = URL_SAFE_NO_PAD.encode(mac(image_url).finalize().into_bytes());
+    format!("/{route}/{url}/{signature}")
+}
+
+pub(crate) fn image_path(image_url: &str) -> String {
+    signed_path("img", image_url)
+}
+
+// the name is only a suggestion to the browser, so it isn't signed
+pub(crate) fn download_path(image_url: &str, name: &str) -> String {
+    let path = signed_path("download", image_url);
+    let name: String = form_urlencoded::byte_serialize(name.as_bytes()).collect();
+    format!("{path}?name={name}")
+}
+
+// the image url of a signed link, if the signature is ours
+fn verify(url: &str, signature: &str) -> AppResult<String> {
+    let invalid = || AppError::from("invalid image link signature").status(StatusCode::FORBIDDEN);
+    let image_url = URL_SAFE_NO_PAD
+        .decode(url)
+        .ok()
+        .and_then(|url| String::from_utf8(url).ok())
+        .ok_or_else(invalid)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+    mac(&image_url)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+    Ok(image_url)
+}
+
+pub(crate) async fn proxy_image(
+    Path((url, signature)): Path<(String, String)>,
+    state: State<AppState>,
+) -> AppResult<axum::response::Response> {
+    let image_url = verify(&url, &signature)?;
+    let (content_type, body) = fetch_image(&state, &image_url).await?;
+
+    let mut resp = body.into_response();
+    let headers = resp.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(caching::IMMUTABLE),
+    );
+    Ok(resp)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DownloadQuery {
+    name: Option<String>,
+}
+
+// the same image as /img, but saved under a name that says where it's from
+pub(crate) async fn download_image(
+    Path((url, signature)): Path<(String, String)>,
+    Query(query): Query<DownloadQuery>,
+    state: State<AppState>,
+) -> AppResult<axum::response::Response> {
+    let image_url = verify(&url, &signature)?;
+    let (content_type, body) = fetch_image(&state, &image_url).await?;
+    let filename = download_filename(query.name.as_deref(), &content_type);
+
+    let mut resp = body.into_response();
+    let headers = resp.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))?,
+    );
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(caching::IMMUTABLE),
+    );
+    Ok(resp)
+}
+
+// streams the image, so big ones are never held in memory whole
+async fn fetch_image(state: &AppState, image_url: &str) -> AppResult<(HeaderValue, Body)> {
+    let resp = state
+        .http
+        .execute(state.http.get(image_url).build()?)
+        .await?
+        .error_for_status()?;
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .filter(|ct| ct.to_str().map_or(false, |ct| ct.starts_with("image/")))
+        .cloned()
+        .ok_or_else(|| {
+            AppErrorKind::UpstreamUnavailable("upstream did not return an image".to_owned())
+        })?;
+    if resp
+        .content_length()
+        .map_or(false, |len| len > MAX_PROXY_SIZE)
+    {
+        let err = AppErrorKind::UpstreamUnavailable("upstream image is too large".to_owned());
+        return Err(err.into());
+    }
+
+    // content length can be missing or lie, so also enforce the limit while streaming
+    let mut received: u64 = 0;
+    let stream = resp.bytes_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len() as u64;
+        if received > MAX_PROXY_SIZE {
+            return Err(std::io::Error::other("upstream image is too large"));
+        }
+        Ok(chunk)
+    });
+    Ok((content_type, Body::from_stream(stream)))
+}
+
+// like twitter_1234567890, from the source and the post id in its url
+pub(crate) fn download_name(art: &Art, art_no: usize) -> String {
+    // boorus keep the id in the query, everything else at the end of the path
+    let query_id = art.url.query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "id")
+            .map(|(_, id)| id.into_owned())
+    });
+    let post = query_id
+        .or_else(|| {
+            let segment = art.url.path().split('/').rev().find(|s| !s.is_empty())?;
+            Some(segment.to_owned())
+        })
+        .map(|post| {
+            post.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|post| !post.is_empty())
+        .unwrap_or_else(|| art_no.to_string());
+    format!("{}_{post}", art.kind)
+}
+
+// the name from the link with the extension of what the upstream sent
+fn download_filename(name: Option<&str>, content_type: &HeaderValue) -> String {
+    // it ends up in a header, so only keep what can't break out of the quotes
+    let name = name
+        .map(|name| {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "art".to_owned());
+    // image/svg+xml and friends only keep the part before the +
+    let extension = content_type
+        .to_str()
+        .ok()
+        .and_then(|ct| ct.split(';').next()?.trim().strip_prefix("image/"))
+        .and_then(|subtype| subtype.split('+').next())
+        .filter(|subtype| !subtype.is_empty() && subtype.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .map_or(
+            "bin",
+            |subtype| if subtype == "jpeg" { "jpg" } else { subtype },
+        );
+    format!("{name}.{extension}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &str = "https://pbs.twimg.com/media/1.jpg";
+
+    // the url and signature segments of a signed path
+    fn segments(path: &str) -> (String, String) {
+        let mut segments = path.split('?').next().unwrap().rsplit('/');
+        let signature = segments.next().unwrap().to_owned();
+        (segments.next().unwrap().to_owned(), signature)
+    }
+
+    #[test]
+    fn signed_links_verify() {
+        crate::config::init_default();
+        let (url, signature) = segments(&image_path(IMAGE));
+        assert_eq!(verify(&url, &signature).unwrap(), IMAGE);
+        let (url, signature) = segments(&download_path(IMAGE, "art 1.jpg"));
+        assert_eq!(verify(&url, &signature).unwrap(), IMAGE);
+    }
+
+    #[test]
+    fn tampered_links_dont_verify() {
+        crate::config::init_default();
+        let (_, signature) = segments(&image_path(IMAGE));
+        let other = URL_SAFE_NO_PAD.encode("https://example.com/1.jpg");
+        assert!(verify(&other, &signature).is_err());
+
+        let (url, _) = segments(&image_path(IMAGE));
+        let (_, other_signature) = segments(&image_path("https://example.com/1.jpg"));
+        assert!(verify(&url, &other_signature).is_err());
+        assert!(verify(&url, "not base64!").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use http::Uri;
+use serde::Serialize;
+
+// how long an art is left alone after each time it's quarantined, the last one repeats
+const COOLDOWNS: [Duration; 3] = [
+    Duration::from_secs(10 * 60),
+    Duration::from_secs(60 * 60),
+    Duration::from_secs(6 * 60 * 60),
+];
+
+// stops picking arts that keep failing to resolve, like the circuit breakers do for hosts
+pub(crate) struct Quarantine {
+    threshold: u32,
+    arts: DashMap<Uri, ArtState>,
+}
+
+#[derive(Default)]
+struct ArtState {
+    consecutive_failures: u32,
+    // how many times it was quarantined without resolving in between
+    times: u32,
+    until: Option<Instant>,
+    // the cooldown is over and one request is trying the art again
+    probing: bool,
+}
+
+// the claim on probing an art, if one was made
+pub(crate) struct Probe<'a>(Option<(&'a Quarantine, Uri)>);
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        if let Some((quarantine, url)) = &self.0 {
+            if let Some(mut state) = quarantine.arts.get_mut(url) {
+                state.probing = false;
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct QuarantinedArt {
+    url: String,
+    consecutive_failures: u32,
+    times: u32,
+    // 0 while the art is being probed
+    remaining_secs: u64,
+}
+
+impl Quarantine {
+    pub(crate) fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            arts: Default::default(),
+        }
+    }
+
+    pub(crate) fn is_quarantined(&self, url: &Uri) -> bool {
+        self.arts.get(url).map_or(false, |state| {
+            state.probing || state.until.map_or(false, |until| Instant::now() < until)
+        })
+    }
+
+    // whether the art can be fetched, after the cooldown only the first caller gets to probe it
+    // the probe is given back when the returned guard is dropped, even if the fetch never finished
+    pub(crate) fn allows(&self, url: &Uri) -> Option<Probe<'_>> {
+        let Some(mut state) = self.arts.get_mut(url) else {
+            return Some(Probe(None));
+        };
+        match state.until {
+            Some(_) if state.probing => None,
+            Some(until) if Instant::now() < until => None,
+            Some(_) => {
+                state.probing = true;
+                tracing::info!(art_url = %url, "quarantine over, trying art again");
+                Some(Probe(Some((self, url.clone()))))
+            }
+            None => Some(Probe(None)),
+        }
+    }
+
+    pub(crate) fn record_success(&self, url: &Uri) {
+        if self
+            .arts
+            .remove(url)
+            .is_some_and(|(_, state)| state.until.is_some())
+        {
+            tracing::info!(art_url = %url, "art resolved again, lifting quarantine");
+        }
+    }
+
+    pub(crate) fn record_failure(&self, url: &Uri) {
+        let mut state = self.arts.entry(url.clone()).or_default();
+        state.consecutive_failures += 1;
+        // a failed probe goes right back, for longer
+        if !state.probing && state.consecutive_failures < self.threshold {
+            return;
+        }
+        let cooldown = COOLDOWNS[(state.times as usize).min(COOLDOWNS.len() - 1)];
+        state.times += 1;
+        state.probing = false;
+        state.until = Some(Instant::now() + cooldown);
+        tracing::warn!(
+            art_url = %url,
+            failures = state.consecutive_failures,
+            cooldown_secs = cooldown.as_secs(),
+            "quarantined art that keeps failing"
+        );
+    }
+
+    pub(crate) fn quarantined(&self) -> Vec<QuarantinedArt> {
+        let now = Instant::now();
+        let mut arts = self
+            .arts
+            .iter()
+            .filter_map(|entry| {
+                let until = entry.until?;
+                Some(QuarantinedArt {
+                    url: entry.key().to_string(),
+                    consecutive_failures: entry.consecutive_failures,
+                    times: entry.times,
+                    remaining_secs: until.saturating_duration_since(now).as_secs(),
+                })
+            })
+            .collect::<Vec<_>>();
+        arts.sort_by(|a, b| a.url.cmp(&b.url));
+        arts
+    }
+}
@@ -0,0 +1,68 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::HeaderValue;
+
+use crate::client_ip;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+// longer ids from a proxy are replaced, they'd only bloat the logs
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// gives every request an id, or keeps the one our proxy gave it, so reports can be matched to logs
+pub(crate) async fn assign_request_id(mut req: Request, next: Next) -> Response {
+    // anyone else could put whatever they want in our logs
+    let forwarded = client_ip::is_trusted_peer(req.extensions())
+        .then(|| req.headers().get(REQUEST_ID_HEADER))
+        .flatten();
+    let id = forwarded
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{:08x}", fastrand::u32(..)));
+    let header = HeaderValue::from_str(&id).expect("request ids are always valid header values");
+    // the request span is made from the headers, so it picks the id up from here
+    req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+
+    let mut resp = REQUEST_ID.scope(id, next.run(req)).await;
+    resp.headers_mut().insert(REQUEST_ID_HEADER, header);
+    resp
+}
+
+// ids like proxies make them, uuids and hex, nothing that could be mistaken for log syntax
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+// the id of the request being handled, if we are in one
+pub(crate) fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub(crate) fn of(req: &Request) -> &str {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_ids_are_kept() {
+        assert!(is_valid("3f2a9c1e-7b4d-4e8a-9f00-1c2d3e4f5a6b"));
+        assert!(is_valid("req_01.abc"));
+        assert!(!is_valid(""));
+        assert!(!is_valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        assert!(!is_valid("id=\"forged\""));
+        assert!(!is_valid("a b"));
+    }
+}
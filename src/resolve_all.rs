@@ -0,0 +1,149 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::{
+    admin::authorize,
+    error::{AppErrorKind, AppResult},
+    get_background_link, AppState,
+};
+
+// per host limits still apply on top, this only bounds how much is in flight overall
+const RESOLVE_ALL_CONCURRENCY: usize = 4;
+
+// resolving every art that isn't cached yet, in the background
+struct Job {
+    id: String,
+    total: usize,
+    done: AtomicUsize,
+    failures: Mutex<Vec<Failure>>,
+    running: AtomicBool,
+    started_at: Instant,
+}
+
+#[derive(Clone, Serialize)]
+struct Failure {
+    url: String,
+    error: String,
+}
+
+// only the latest job is kept, there is only ever one running
+#[derive(Default)]
+pub(crate) struct Jobs {
+    latest: Mutex<Option<Arc<Job>>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Progress {
+    id: String,
+    done: usize,
+    total: usize,
+    running: bool,
+    elapsed_secs: u64,
+    // doubles as a list of dead links
+    failures: Vec<Failure>,
+}
+
+impl Job {
+    fn progress(&self) -> Progress {
+        Progress {
+            id: self.id.clone(),
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total,
+            running: self.running.load(Ordering::Relaxed),
+            elapsed_secs: self.started_at.elapsed().as_secs(),
+            failures: self.failures.lock().unwrap().clone(),
+        }
+    }
+}
+
+// starts a job, or reports on the one that's already running
+pub(crate) async fn start(headers: HeaderMap, state: State<AppState>) -> AppResult<Json<Progress>> {
+    authorize(&headers)?;
+    let mut latest = state.resolve_jobs.latest.lock().unwrap();
+    if let Some(job) = latest
+        .as_ref()
+        .filter(|job| job.running.load(Ordering::Relaxed))
+    {
+        return Ok(Json(job.progress()));
+    }
+
+    let quality = state.default_quality;
+    let arts = state
+        .data
+        .lock()
+        .unwrap()
+        .arts()
+        .iter()
+        .filter(|art| !state.direct_links.contains_key(&(art.url.clone(), quality)))
+        .cloned()
+        .collect::<Vec<_>>();
+    let job = Arc::new(Job {
+        id: format!("{:08x}", fastrand::u32(..)),
+        total: arts.len(),
+        done: AtomicUsize::new(0),
+        failures: Mutex::new(Vec::new()),
+        running: AtomicBool::new(true),
+        started_at: Instant::now(),
+    });
+    *latest = Some(job.clone());
+    tracing::info!(
+        job = job.id,
+        arts = job.total,
+        "started resolving every art"
+    );
+
+    let state = state.0.clone();
+    let progress = job.progress();
+    tokio::spawn(async move {
+        futures_util::stream::iter(arts)
+            .for_each_concurrent(RESOLVE_ALL_CONCURRENCY, |art| {
+                let (state, job) = (&state, &job);
+                async move {
+                    if let Err(err) = get_background_link(state, &art, quality).await {
+                        job.failures.lock().unwrap().push(Failure {
+                            url: art.url.to_string(),
+                            error: err.to_string(),
+                        });
+                    }
+                    job.done.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .await;
+        job.running.store(false, Ordering::Relaxed);
+        tracing::info!(
+            job = job.id,
+            arts = job.total,
+            failed = job.failures.lock().unwrap().len(),
+            elapsed_secs = job.started_at.elapsed().as_secs(),
+            "finished resolving every art"
+        );
+    });
+    Ok(Json(progress))
+}
+
+pub(crate) async fn show(
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    state: State<AppState>,
+) -> AppResult<Json<Progress>> {
+    authorize(&headers)?;
+    let latest = state.resolve_jobs.latest.lock().unwrap();
+    let job = latest
+        .as_ref()
+        .filter(|job| job.id == id)
+        .ok_or_else(|| AppErrorKind::NotFound(format!("no resolve job {id}")))?;
+    Ok(Json(job.progress()))
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use http::{header, HeaderValue, StatusCode, Uri};
+
+use crate::{
+    config::config,
+    data::ImageQuality,
+    db,
+    error::{check_throttled, AppResult},
+    fetch_limited, limits, preview, prune_failed_links, shared_cache, AppState,
+};
+
+// what an upstream api sent along with the response a link was resolved from
+#[derive(Clone)]
+pub(crate) struct Validators {
+    url: String,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    // none if the upstream didn't send any, there would be nothing to ask with
+    pub(crate) fn from_response(resp: &reqwest::Response) -> Option<Self> {
+        let etag = resp.headers().get(header::ETAG).cloned();
+        let last_modified = resp.headers().get(header::LAST_MODIFIED).cloned();
+        (etag.is_some() || last_modified.is_some()).then(|| Self {
+            url: resp.url().to_string(),
+            etag,
+            last_modified,
+        })
+    }
+}
+
+enum Outcome {
+    // the image is still there
+    Valid,
+    // the link expired, but the upstream said nothing changed
+    NotModified,
+    // the image was gone, but the art resolved to a new one
+    Refreshed,
+    // the image was gone and the art didn't resolve anymore
+    Dropped,
+    // couldn't tell, the entry is left alone
+    Unknown,
+}
+
+// slowly re-checks cached image links, since we can't tell when a visitor got a broken image
+pub(crate) async fn run(state: AppState, interval: Duration) {
+    loop {
+        let keys: Vec<(Uri, ImageQuality)> = state
+            .direct_links
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        prune_failed_links(&state);
+        let (mut valid, mut not_modified, mut refreshed, mut dropped) = (0, 0, 0, 0);
+        // so an empty cache doesn't busy loop
+        tokio::time::sleep(interval).await;
+        for (url, quality) in keys {
+            match revalidate(&state, &url, quality).await {
+                Outcome::Valid => valid += 1,
+                Outcome::NotModified => not_modified += 1,
+                Outcome::Refreshed => refreshed += 1,
+                Outcome::Dropped => dropped += 1,
+                Outcome::Unknown => {}
+            }
+            tokio::time::sleep(interval).await;
+        }
+        tracing::info!(
+            valid,
+            not_modified,
+            refreshed,
+            dropped,
+            "finished revalidation pass"
+        );
+    }
+}
+
+async fn revalidate(state: &AppState, url: &Uri, quality: ImageQuality) -> Outcome {
+    let cache_key = (url.clone(), quality);
+    let Some((image_url, fetched_at, validators)) =
+        state.direct_links.get(&cache_key).map(|link| {
+            (
+                link.image_url.clone(),
+                link.fetched_at,
+                link.validators.clone(),
+            )
+        })
+    else {
+        // evicted since the pass started
+        return Outcome::Unknown;
+    };
+
+    // upstream requests count toward the art's source limit like fetches do
+    let source = state
+        .sources
+        .get(url.host().unwrap_or_default())
+        .map_or("unknown", |source| source.name());
+    let ttl = config().cache.link_ttl_secs.map(Duration::from_secs);
+    if ttl.map_or(false, |ttl| fetched_at.elapsed() >= ttl) {
+        if let Some(validators) = validators {
+            match not_modified(state, source, &validators).await {
+                Ok(true) => {
+                    if let Some(mut link) = state.direct_links.get_mut(&cache_key) {
+                        link.fetched_at = Instant::now();
+                    }
+                    tracing::debug!(art_url = %url, "upstream did not change, keeping cached link");
+                    return Outcome::NotModified;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::debug!(art_url = %url, error = %err, "could not ask upstream for changes");
+                    return Outcome::Unknown;
+                }
+            }
+        }
+        tracing::info!(art_url = %url, image_url, "cached link expired, resolving again");
+        return resolve_again(state, url, cache_key).await;
+    }
+
+    match image_status(state, source, &image_url).await {
+        Ok(status) if status == StatusCode::NOT_FOUND || status == StatusCode::GONE => {}
+        Ok(_) => return Outcome::Valid,
+        Err(err) => {
+            tracing::debug!(art_url = %url, image_url, error = %err, "could not revalidate link");
+            return Outcome::Unknown;
+        }
+    }
+
+    tracing::info!(art_url = %url, image_url, "cached image is gone, resolving again");
+    resolve_again(state, url, cache_key).await
+}
+
+async fn resolve_again(state: &AppState, url: &Uri, cache_key: (Uri, ImageQuality)) -> Outcome {
+    let art = state
+        .data
+        .lock()
+        .unwrap()
+        .arts()
+        .iter()
+        .find(|art| art.url == *url)
+        .cloned();
+    // the art was removed, so there is nothing to refresh
+    let Some(art) = art else {
+        state.direct_links.remove(&cache_key);
+        shared_cache::remove(state, cache_key);
+        return Outcome::Dropped;
+    };
+    match fetch_limited(state, &art, quality).await {
+        Ok(image_link) => {
+            preview::spawn(state, cache_key.clone(), &image_link);
+            shared_cache::store(state, cache_key.clone(), &image_link);
+            state.direct_links.insert(cache_key, image_link);
+            Outcome::Refreshed
+        }
+        Err(err) => {
+            tracing::warn!(art_url = %url, error = %err, "could not resolve art again, dropping its link");
+            state.direct_links.remove(&cache_key);
+            shared_cache::remove(state, cache_key);
+            state.failed_links.insert(url.clone(), Instant::now());
+            state.data.lock().unwrap().set_healthy(url, false);
+            db::set_healthy(state, url, false);
+            Outcome::Dropped
+        }
+    }
+}
+
+// a conditional request for what the link was resolved from, a 304 means it would resolve the same
+async fn not_modified(state: &AppState, source: &str, validators: &Validators) -> AppResult<bool> {
+    let timeout = Duration::from_secs(config().upstream.fetch_timeout_secs);
+    limits::with_timeout(timeout, source, async {
+        let _permit = state.source_limits.acquire(source).await;
+        let mut req = state.http.get(&validators.url);
+        if let Some(etag) = &validators.etag {
+            req = req.header(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            req = req.header(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        let resp = req.send().await?;
+        check_throttled(&resp)?;
+        Ok(resp.status() == StatusCode::NOT_MODIFIED)
+    })
+    .await
+}
+
+// a HEAD request through the same per-source limit as fetches
+async fn image_status(state: &AppState, source: &str, image_url: &str) -> AppResult<StatusCode> {
+    let timeout = Duration::from_secs(config().upstream.fetch_timeout_secs);
+    limits::with_timeout(timeout, source, async {
+        let _permit = state.source_limits.acquire(source).await;
+        let resp = state.http.head(image_url).send().await?;
+        Ok(resp.status())
+    })
+    .await
+}
@@ -0,0 +1,111 @@
+use axum::{
+    extract::{Query, State},
+    response::Html,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ArtEntry, data::Art, error::AppResult, get_page_contact, get_page_head_common, i18n,
+    permalink, AppState,
+};
+
+// more than this is not a search anymore, it's the whole list
+const MAX_RESULTS: usize = 100;
+
+#[derive(Deserialize)]
+pub(crate) struct SearchQuery {
+    q: Option<String>,
+}
+
+// arts whose source, artist, tags or cached description contain the query
+fn search(state: &AppState, query: &str) -> Vec<(usize, Art)> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    // matching happens outside the lock, since it also looks at the link cache
+    let arts = state.data.lock().unwrap().arts().to_vec();
+    arts.into_iter()
+        .enumerate()
+        .filter(|(_, art)| {
+            art.matches(&query)
+                || state
+                    .direct_links
+                    .get(&(art.url.clone(), state.default_quality))
+                    .and_then(|link| link.description.clone())
+                    .map_or(false, |description| {
+                        description.to_lowercase().contains(&query)
+                    })
+        })
+        .take(MAX_RESULTS)
+        .collect()
+}
+
+pub(crate) async fn show_search(
+    Query(query): Query<SearchQuery>,
+    state: State<AppState>,
+) -> AppResult<Html<String>> {
+    let q = query.q.unwrap_or_default();
+    let results = search(&state, &q);
+    let content = maud::html! {
+        (maud::DOCTYPE)
+        head {
+            (get_page_head_common(None))
+        }
+        body style="flex-direction: column; height: auto; min-height: 100vh; overflow-x: hidden;" {
+            form style="margin: 2vh auto;" action="/search" method="get" {
+                input type="search" name="q" value=(q) placeholder="source, artist or tag" autofocus;
+                " "
+                button type="submit" { "search" }
+            }
+            div style="display: flex; flex-direction: column; gap: 1vh; margin: 0 auto; max-width: 90vw;" {
+                @if q.trim().is_empty() {
+                    span class="about" { "search arts by their source, artist or tags" }
+                } @else if results.is_empty() {
+                    span class="about" { "nothing matches \"" (q.trim()) "\"" }
+                } @else {
+                    span class="about" {
+                        (results.len()) " results"
+                        @if results.len() == MAX_RESULTS { ", only the first " (MAX_RESULTS) " are shown" }
+                    }
+                    @for (art_no, art) in &results {
+                        a class="about" href=(permalink(*art_no)) {
+                            "#" (art_no) " " (art.url)
+                            @if let Some(artist) = art.artist() { " by " (artist) }
+                            @if !art.tags.is_empty() { " [" (art.tags.join(", ")) "]" }
+                        }
+                    }
+                }
+            }
+            div style="margin: 2vh auto;" {
+                (get_page_contact(i18n::current()))
+            }
+        }
+    };
+    Ok(Html(content.into_string()))
+}
+
+#[derive(Serialize)]
+pub(crate) struct SearchResults {
+    query: String,
+    // capped at MAX_RESULTS
+    total: usize,
+    results: Vec<ArtEntry>,
+}
+
+pub(crate) async fn api_search(
+    Query(query): Query<SearchQuery>,
+    state: State<AppState>,
+) -> Json<SearchResults> {
+    let q = query.q.unwrap_or_default();
+    let results = search(&state, &q)
+        .iter()
+        .map(|(art_no, art)| ArtEntry::new(&state, *art_no, art))
+        .collect::<Vec<_>>();
+    Json(SearchResults {
+        query: q,
+        total: results.len(),
+        results,
+    })
+}
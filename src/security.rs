@@ -0,0 +1,84 @@
+use axum::{extract::State, middleware::Next, response::Response};
+use http::{header, HeaderValue};
+
+use crate::{config::config, sources::Registry};
+
+// pages meant to be put in an iframe, no other page can be framed at all
+const EMBEDDABLE_PATHS: &[&str] = &["/daily"];
+
+#[derive(Clone)]
+pub(crate) struct Policies {
+    default: HeaderValue,
+    embeddable: HeaderValue,
+}
+
+// builds the policies once at startup, since the allowed hosts don't change while running
+pub(crate) fn content_security_policy(sources: &Registry) -> Result<Policies, String> {
+    // data: for the placeholders shown while images load
+    let mut img_hosts = vec!["'self'".to_owned(), "data:".to_owned()];
+    for host in sources.all().flat_map(|source| source.image_hosts()) {
+        let host = format!("https://{host}");
+        if !img_hosts.contains(&host) {
+            img_hosts.push(host);
+        }
+    }
+    for mirror in config().upstream.pixiv_proxies() {
+        img_hosts.push(format!("https://{mirror}"));
+    }
+    // escape hatch for image hosts we don't know about
+    img_hosts.extend(
+        config()
+            .site
+            .csp_extra_img_hosts
+            .split([',', ' '])
+            .filter(|host| !host.is_empty())
+            .map(str::to_owned),
+    );
+
+    let (style_src, font_src) = if config().site.use_cdn {
+        (
+            "'self' 'unsafe-inline' https://fonts.googleapis.com https://cdn.jsdelivr.net",
+            "'self' https://fonts.gstatic.com",
+        )
+    } else {
+        ("'self' 'unsafe-inline'", "'self'")
+    };
+
+    let img_src = img_hosts.join(" ");
+    let policy = |frame_ancestors: &str| {
+        HeaderValue::from_str(&format!(
+            "default-src 'self'; img-src {img_src}; style-src {style_src}; font-src {font_src}; script-src 'self' 'unsafe-inline'; object-src 'none'; base-uri 'none'; frame-ancestors {frame_ancestors}"
+        ))
+        .map_err(|_| {
+            "CSP_EXTRA_IMG_HOSTS or FRAME_ANCESTORS contains invalid characters".to_owned()
+        })
+    };
+    Ok(Policies {
+        default: policy("'none'")?,
+        embeddable: policy(&config().site.frame_ancestors)?,
+    })
+}
+
+pub(crate) async fn security_headers(
+    State(csp): State<Policies>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let csp = if EMBEDDABLE_PATHS.contains(&req.uri().path()) {
+        csp.embeddable
+    } else {
+        csp.default
+    };
+    let mut resp = next.run(req).await;
+    let headers = resp.headers_mut();
+    headers.insert(header::CONTENT_SECURITY_POLICY, csp);
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    resp
+}
@@ -0,0 +1,280 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::async_trait;
+use futures_util::StreamExt;
+use http::Uri;
+use redis::{aio::ConnectionManager, AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+use crate::{
+    data::{FetchedLink, ImageQuality},
+    db,
+    preview::Preview,
+    AppState,
+};
+
+// a visitor waits this long on redis at most before we fetch without it
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+const KEY_PREFIX: &str = "limbusart:link:";
+// evictions are published here so every instance drops them from its own map
+const EVICT_CHANNEL: &str = "limbusart:evict";
+const EVICT_ALL: &str = "*";
+const QUALITIES: [ImageQuality; 3] = [
+    ImageQuality::Original,
+    ImageQuality::Sample,
+    ImageQuality::Low,
+];
+
+pub(crate) type CacheKey = (Uri, ImageQuality);
+
+// links shared with other instances, behind the in-process map so hits don't need the network
+// failing is only ever a cache miss, visitors are served by fetching instead
+#[async_trait]
+pub(crate) trait SharedCache: Send + Sync {
+    async fn get(&self, key: &CacheKey) -> Option<FetchedLink>;
+    async fn insert(&self, key: &CacheKey, link: &FetchedLink);
+    async fn remove(&self, key: &CacheKey);
+    // every quality of the url, or everything without one, on every instance
+    async fn evict(&self, url: Option<&Uri>);
+}
+
+// what goes into redis, instants only mean something to the process that made them
+#[derive(Serialize, Deserialize)]
+struct StoredLink {
+    image_url: String,
+    new_source: Option<String>,
+    description: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    artist: Option<String>,
+    preview: Option<Preview>,
+    // unix seconds
+    fetched_at: u64,
+}
+
+impl StoredLink {
+    fn new(link: &FetchedLink) -> Self {
+        let fetched_at = SystemTime::now() - link.fetched_at.elapsed();
+        Self {
+            image_url: link.image_url.clone(),
+            new_source: link.new_source.as_ref().map(Uri::to_string),
+            description: link.description.clone(),
+            width: link.width,
+            height: link.height,
+            artist: link.artist.clone(),
+            preview: link.preview.clone(),
+            fetched_at: fetched_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        }
+    }
+
+    // validators aren't kept, an expired link from redis is resolved again in full
+    fn into_link(self) -> FetchedLink {
+        let fetched_at = SystemTime::UNIX_EPOCH + Duration::from_secs(self.fetched_at);
+        let age = SystemTime::now()
+            .duration_since(fetched_at)
+            .unwrap_or_default();
+        FetchedLink {
+            image_url: self.image_url,
+            new_source: self.new_source.and_then(|src| src.parse().ok()),
+            description: self.description,
+            width: self.width,
+            height: self.height,
+            artist: self.artist,
+            preview: self.preview,
+            validators: None,
+            fetched_at: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+        }
+    }
+}
+
+pub(crate) struct Redis {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+    ttl_secs: u64,
+}
+
+impl Redis {
+    pub(crate) fn new(url: &str, ttl_secs: u64) -> RedisResult<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            conn: OnceCell::new(),
+            ttl_secs,
+        })
+    }
+
+    // connects on first use and again after that failed, so redis can be down at startup
+    async fn conn(&self) -> RedisResult<ConnectionManager> {
+        self.conn
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+    }
+
+    // drops links other instances evicted from our map, for as long as we run
+    pub(crate) async fn listen(self: Arc<Self>, state: AppState) {
+        loop {
+            if let Err(err) = self.forward_evictions(&state).await {
+                tracing::warn!(error = %err, "lost the redis eviction channel, resubscribing");
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    async fn forward_evictions(&self, state: &AppState) -> RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(EVICT_CHANNEL).await?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: String = message.get_payload()?;
+            if payload == EVICT_ALL {
+                state.direct_links.clear();
+            } else if let Ok(url) = payload.parse::<Uri>() {
+                state
+                    .direct_links
+                    .retain(|(cached_url, _), _| cached_url != &url);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn redis_key((url, quality): &CacheKey) -> String {
+    format!("{KEY_PREFIX}{quality}:{url}")
+}
+
+async fn or_miss<T>(op: &str, fut: impl Future<Output = RedisResult<T>>) -> Option<T> {
+    match tokio::time::timeout(REDIS_TIMEOUT, fut).await {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(err)) => {
+            tracing::warn!(op, error = %err, "redis request failed");
+            None
+        }
+        Err(_) => {
+            tracing::warn!(op, "redis request timed out");
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl SharedCache for Redis {
+    async fn get(&self, key: &CacheKey) -> Option<FetchedLink> {
+        let stored: Option<String> = or_miss("get", async {
+            self.conn().await?.get(redis_key(key)).await
+        })
+        .await??;
+        match serde_json::from_str::<StoredLink>(&stored) {
+            Ok(stored) => Some(stored.into_link()),
+            Err(err) => {
+                tracing::warn!(art_url = %key.0, error = %err, "could not read link from redis");
+                None
+            }
+        }
+    }
+
+    async fn insert(&self, key: &CacheKey, link: &FetchedLink) {
+        let Ok(stored) = serde_json::to_string(&StoredLink::new(link)) else {
+            return;
+        };
+        or_miss("set", async {
+            self.conn()
+                .await?
+                .set_ex::<_, _, ()>(redis_key(key), stored, self.ttl_secs)
+                .await
+        })
+        .await;
+    }
+
+    async fn remove(&self, key: &CacheKey) {
+        or_miss("del", async {
+            self.conn().await?.del::<_, ()>(redis_key(key)).await
+        })
+        .await;
+    }
+
+    async fn evict(&self, url: Option<&Uri>) {
+        or_miss("evict", async {
+            let mut conn = self.conn().await?;
+            let keys: Vec<String> = match url {
+                Some(url) => QUALITIES
+                    .iter()
+                    .map(|quality| redis_key(&(url.clone(), *quality)))
+                    .collect(),
+                None => {
+                    let mut found = conn
+                        .scan_match::<_, String>(format!("{KEY_PREFIX}*"))
+                        .await?;
+                    let mut keys = Vec::new();
+                    while let Some(key) = found.next_item().await {
+                        keys.push(key);
+                    }
+                    keys
+                }
+            };
+            if !keys.is_empty() {
+                conn.del::<_, ()>(keys).await?;
+            }
+            let payload = url.map_or_else(|| EVICT_ALL.to_owned(), Uri::to_string);
+            conn.publish::<_, _, ()>(EVICT_CHANNEL, payload).await
+        })
+        .await;
+    }
+}
+
+// the shared link, also kept in our own map from then on
+pub(crate) async fn load(state: &AppState, key: &CacheKey) -> Option<FetchedLink> {
+    let link = state.shared_links.as_ref()?.get(key).await?;
+    state.direct_links.insert(key.clone(), link.clone());
+    Some(link)
+}
+
+// writes don't hold up whoever resolved the link
+pub(crate) fn store(state: &AppState, key: CacheKey, link: &FetchedLink) {
+    let Some(shared) = state.shared_links.clone() else {
+        return;
+    };
+    let link = link.clone();
+    tokio::spawn(async move { shared.insert(&key, &link).await });
+}
+
+pub(crate) fn remove(state: &AppState, key: CacheKey) {
+    let Some(shared) = state.shared_links.clone() else {
+        return;
+    };
+    tokio::spawn(async move { shared.remove(&key).await });
+}
+
+// drops the links of the url, or every link, from every cache they are kept in. returns how many
+// were dropped here, the other instances don't tell
+pub(crate) async fn evict_links(state: &AppState, url: Option<&Uri>) -> usize {
+    let before = state.direct_links.len();
+    match url {
+        Some(url) => {
+            state
+                .direct_links
+                .retain(|(cached_url, _), _| cached_url != url);
+            state.failed_links.remove(url);
+        }
+        None => {
+            state.direct_links.clear();
+            state.failed_links.clear();
+        }
+    }
+    evict(state, url).await;
+    db::clear_links(state, url);
+    before.saturating_sub(state.direct_links.len())
+}
+
+async fn evict(state: &AppState, url: Option<&Uri>) {
+    if let Some(shared) = &state.shared_links {
+        shared.evict(url).await;
+    }
+}
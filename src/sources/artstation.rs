@@ -0,0 +1,122 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::{StatusCode, Uri};
+use serde_json::Value;
+
+use super::{truncate_description, ArtSource};
+use crate::{
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+pub(crate) struct ArtStation;
+
+#[async_trait]
+impl ArtSource for ArtStation {
+    fn name(&self) -> &'static str {
+        "artstation"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &["www.artstation.com", "artstation.com"]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["*.artstation.com"]
+    }
+
+    fn accepts(&self, url: &Uri) -> bool {
+        project_hash(url).is_some()
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let hash = project_hash(url).ok_or_else(|| {
+            AppErrorKind::InvalidArtUrl(format!("{url} is not an artstation artwork"))
+        })?;
+        let apiurl = format!("https://www.artstation.com/projects/{hash}.json");
+        tracing::debug!(url = %apiurl, "fetching artstation project");
+        let resp = http.execute(http.get(&apiurl).build()?).await?;
+        check_throttled(&resp)?;
+        // that's cloudflare asking for a browser, retrying won't get us past it
+        if resp.status() == StatusCode::FORBIDDEN {
+            return Err(AppErrorKind::UpstreamBlocked(format!(
+                "artstation refused to serve {apiurl}"
+            ))
+            .into());
+        }
+        let project = resp.error_for_status()?.json::<Value>().await?;
+
+        let asset = project
+            .get("assets")
+            .and_then(Value::as_array)
+            .and_then(|assets| {
+                assets
+                    .iter()
+                    .find(|asset| asset.get("asset_type").and_then(Value::as_str) == Some("image"))
+            })
+            .ok_or_else(|| {
+                AppErrorKind::UpstreamNotFound(format!("artstation project {hash} has no images"))
+            })?;
+        let large_url = asset
+            .get("image_url")
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                AppErrorKind::UpstreamUnavailable(format!(
+                    "artstation project {hash} image has no url"
+                ))
+            })?;
+        let dimension = |field: &str| {
+            asset
+                .get(field)
+                .and_then(Value::as_u64)
+                .and_then(|value| u32::try_from(value).ok())
+                .filter(|value| *value > 0)
+        };
+        // the image url points at the large size, the others only differ in the path
+        let (image_url, width, height) = match quality {
+            ImageQuality::Low => (large_url.replacen("/large/", "/medium/", 1), None, None),
+            ImageQuality::Original | ImageQuality::Sample => (
+                large_url.to_owned(),
+                dimension("width"),
+                dimension("height"),
+            ),
+        };
+
+        let field = |name: &str| project.get(name).and_then(Value::as_str);
+        let description = field("title")
+            .map(|text| truncate_description(text.trim()))
+            .filter(|text| !text.is_empty());
+        let artist = project
+            .pointer("/user/full_name")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        Ok(FetchedLink {
+            image_url,
+            new_source: None,
+            description,
+            width,
+            height,
+            artist,
+            preview: None,
+            validators: None,
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+// artwork urls look like /artwork/{hash}
+fn project_hash(url: &Uri) -> Option<&str> {
+    let mut segments = url.path().split('/').filter(|s| !s.is_empty());
+    if segments.next()? != "artwork" {
+        return None;
+    }
+    segments
+        .next()
+        .filter(|hash| hash.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
@@ -0,0 +1,285 @@
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use http::{StatusCode, Uri};
+
+use super::{
+    post::{self, Post, PostSource},
+    twitter::Twitter,
+    ArtSource,
+};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppError, AppErrorKind, AppResult},
+    revalidate::Validators,
+};
+
+const BOORU_RETRY_BASE: Duration = Duration::from_millis(250);
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+// a booru speaking the gelbooru dapi, like safebooru and the ones in EXTRA_BOORUS
+pub(crate) struct Booru {
+    name: &'static str,
+    hosts: Vec<&'static str>,
+    image_hosts: Vec<&'static str>,
+    api_url: String,
+    max_retries: u32,
+    // for posts sourced from twitter
+    twitter: Twitter,
+}
+
+impl Booru {
+    pub(crate) fn safebooru() -> Self {
+        let upstream = &config().upstream;
+        Self::safebooru_at(
+            &upstream.safebooru_url,
+            upstream.safebooru_max_retries,
+            Twitter::new(),
+        )
+    }
+
+    // safebooru behind another url, like a mock upstream in tests
+    pub(crate) fn safebooru_at(api_url: &str, max_retries: u32, twitter: Twitter) -> Self {
+        Self {
+            name: "safebooru",
+            hosts: vec!["safebooru.org"],
+            // posts sourced from twitter are served from twitter
+            image_hosts: vec!["safebooru.org", "*.safebooru.org", "pbs.twimg.com"],
+            api_url: api_url.to_owned(),
+            max_retries,
+            twitter,
+        }
+    }
+
+    // named after its host, since that's all we know about it
+    pub(crate) fn extra(host: &'static str) -> Self {
+        // the registry lives as long as the program, so this is only leaked once per booru
+        let subdomains: &'static str = Box::leak(format!("*.{host}").into_boxed_str());
+        Self {
+            name: host,
+            hosts: vec![host],
+            image_hosts: vec![host, subdomains, "pbs.twimg.com"],
+            api_url: format!("https://{host}"),
+            // every dapi booru throttles like safebooru does
+            max_retries: config().upstream.safebooru_max_retries,
+            twitter: Twitter::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtSource for Booru {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &self.hosts
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &self.image_hosts
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        fetch_image_link(self, http, url, quality).await
+    }
+}
+
+async fn fetch_image_link(
+    booru: &Booru,
+    http: &reqwest::Client,
+    url: &Uri,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    let mut id = String::new();
+    for (name, value) in form_urlencoded::parse(url.query().unwrap_or_default().as_bytes()) {
+        if name == "id" {
+            id = value.into_owned();
+        }
+    }
+    if id.is_empty() {
+        return Err(AppErrorKind::InvalidArtUrl(format!(
+            "{} url {url} has no post id",
+            booru.name
+        ))
+        .into());
+    }
+
+    let url = format!(
+        "{}/index.php?page=dapi&s=post&q=index&json=1&id={id}",
+        booru.api_url
+    );
+    let max_retries = booru.max_retries;
+    type Data = (Vec<Post>, Option<Validators>);
+    let try_request = || {
+        let url = url.clone();
+        let http = http.clone();
+        async move {
+            tracing::debug!(%url, booru = booru.name, "fetching booru post");
+            let req = http.get(url).build()?;
+            let resp = http.execute(req).await?;
+            check_throttled(&resp)?;
+            let resp = resp.error_for_status()?;
+            let validators = Validators::from_response(&resp);
+            let posts = resp.json::<Vec<Post>>().await?;
+            AppResult::Ok((posts, validators))
+        }
+    };
+
+    let mut attempts: u32 = 0;
+    let ((posts, validators), _) = futures_retry::FutureRetry::new(try_request, |e: AppError| {
+        let wait = e
+            .retry_after()
+            .unwrap_or_else(|| BOORU_RETRY_BASE * 2u32.pow(attempts));
+        // don't keep visitors waiting if the booru wants us to back off for long
+        if attempts >= max_retries || wait > MAX_RETRY_WAIT {
+            futures_retry::RetryPolicy::<AppError>::ForwardError(e)
+        } else {
+            attempts += 1;
+            tracing::warn!(
+                %url,
+                attempt = attempts,
+                wait_ms = wait.as_millis() as u64,
+                upstream_status = e.upstream_status().map(|status| status.as_u16()),
+                error = %e,
+                booru = booru.name,
+                "retrying booru fetch"
+            );
+            futures_retry::RetryPolicy::<AppError>::WaitRetry(wait)
+        }
+    })
+    .await
+    .map_err(|(e, _)| e)?;
+    let post = posts.first().ok_or_else(|| {
+        AppErrorKind::UpstreamNotFound(format!("{} post {id} does not exist", booru.name))
+    })?;
+
+    let source = PostSource::of(post);
+    let description = post::tag_description(post);
+    if let Some(mut fetched) =
+        post::fetch_from_twitter(&booru.twitter, http, &source, quality, description.clone()).await
+    {
+        // the post decides which tweet we link to, so it's what has to stay the same
+        fetched.validators = validators;
+        return Ok(fetched);
+    }
+
+    let sample_url = post::image_field(booru.name, post, "sample_url")?;
+
+    let original_size = (
+        post::dimension(post, "width"),
+        post::dimension(post, "height"),
+    );
+    let mut picked = None;
+    match quality {
+        ImageQuality::Original => match post::image_field(booru.name, post, "file_url") {
+            Ok(file_url) => {
+                picked = probe_image(http, &file_url)
+                    .await?
+                    .map(|image_url| (image_url, original_size))
+            }
+            Err(err) => {
+                tracing::debug!(error = %err, "no original booru image, using sample instead")
+            }
+        },
+        // previews are small thumbnails, and their size isn't reported
+        ImageQuality::Low => match post::image_field(booru.name, post, "preview_url") {
+            Ok(preview_url) => {
+                picked = probe_image(http, &preview_url)
+                    .await?
+                    .map(|image_url| (image_url, (None, None)))
+            }
+            Err(err) => {
+                tracing::debug!(error = %err, "no booru preview image, using sample instead")
+            }
+        },
+        ImageQuality::Sample => {}
+    }
+    let (image_url, (width, height)) = match picked {
+        Some(picked) => picked,
+        None => {
+            let image_url = match probe_image(http, &sample_url).await? {
+                Some(image_url) => image_url,
+                None => match &source.pximg {
+                    Some(pximg) if config().upstream.pixiv_proxies().next().is_some() => {
+                        post::pixiv_mirror_image(http, pximg).await?
+                    }
+                    _ => sample_url.to_string(),
+                },
+            };
+            // posts without a sample report a zero sample size, their sample is the original
+            let sample_size = (
+                post::dimension(post, "sample_width"),
+                post::dimension(post, "sample_height"),
+            );
+            match sample_size {
+                (Some(width), Some(height)) => (image_url, (Some(width), Some(height))),
+                _ => (image_url, original_size),
+            }
+        }
+    };
+
+    Ok(FetchedLink {
+        image_url,
+        new_source: source.url,
+        description,
+        width,
+        height,
+        artist: None,
+        preview: None,
+        validators,
+        fetched_at: Instant::now(),
+    })
+}
+
+// booru image paths are inconsistent, the api sends them with doubled slashes now and then
+// the cleaned up path is tried first, and the path as sent only if that one isn't there
+async fn probe_image(http: &reqwest::Client, url: &Uri) -> AppResult<Option<String>> {
+    let (Some(scheme), Some(authority)) = (url.scheme_str(), url.authority()) else {
+        return Ok(None);
+    };
+    let query = url
+        .query()
+        .map(|query| format!("?{query}"))
+        .unwrap_or_default();
+    let mut path = String::with_capacity(url.path().len());
+    for segment in url.path().split('/').filter(|s| !s.is_empty()) {
+        path.push('/');
+        path.push_str(segment);
+    }
+    let mut candidates = vec![format!("{scheme}://{authority}{path}{query}")];
+    let as_sent = format!("{scheme}://{authority}{}{query}", url.path());
+    if as_sent != candidates[0] {
+        candidates.push(as_sent);
+    }
+
+    for image_url in candidates {
+        let status = match http.execute(http.get(&image_url).build()?).await {
+            Ok(resp) => resp.status(),
+            Err(err) => {
+                tracing::debug!(image_url, error = %err, "could not probe booru image");
+                return Ok(None);
+            }
+        };
+        if status.is_success() {
+            return Ok(Some(image_url));
+        }
+        // anything but a missing image would fail the same way for the other path
+        if status != StatusCode::NOT_FOUND {
+            tracing::debug!(
+                image_url,
+                status = status.as_u16(),
+                "booru image probe failed"
+            );
+            return Ok(None);
+        }
+    }
+    Ok(None)
+}
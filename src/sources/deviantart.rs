@@ -0,0 +1,106 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::{StatusCode, Uri};
+use serde_json::Value;
+
+use super::{truncate_description, ArtSource};
+use crate::{
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+const OEMBED_URL: &str = "https://backend.deviantart.com/oembed";
+
+pub(crate) struct DeviantArt;
+
+#[async_trait]
+impl ArtSource for DeviantArt {
+    fn name(&self) -> &'static str {
+        "deviantart"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &["www.deviantart.com", "deviantart.com"]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["*.wixmp.com", "*.deviantart.net"]
+    }
+
+    // deviations look like /{artist}/art/{slug}
+    fn accepts(&self, url: &Uri) -> bool {
+        url.path().split('/').nth(2) == Some("art")
+    }
+
+    fn artist<'a>(&self, url: &'a Uri) -> Option<&'a str> {
+        url.path().split('/').nth(1).filter(|s| !s.is_empty())
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        tracing::debug!(%url, "fetching deviantart oembed");
+        let req = http
+            .get(OEMBED_URL)
+            .query(&[("url", url.to_string())])
+            .build()?;
+        let resp = http.execute(req).await?;
+        check_throttled(&resp)?;
+        // mature deviations need a logged in account, trying again won't help
+        if resp.status() == StatusCode::FORBIDDEN {
+            return Err(AppErrorKind::UpstreamRestricted(format!(
+                "deviation {url} is only visible when logged in"
+            ))
+            .into());
+        }
+        let data = resp.error_for_status()?.json::<Value>().await?;
+        if let Some(err) = data.get("error").and_then(Value::as_str) {
+            return Err(AppErrorKind::UpstreamRestricted(format!(
+                "deviantart won't embed {url}: {err}"
+            ))
+            .into());
+        }
+
+        let field = |name: &str| data.get(name).and_then(Value::as_str);
+        let dimension = |name: &str| {
+            data.get(name)
+                .and_then(|value| value.as_u64().or_else(|| value.as_str()?.parse().ok()))
+                .and_then(|value| u32::try_from(value).ok())
+                .filter(|value| *value > 0)
+        };
+        let (image_url, width, height) = match (quality, field("thumbnail_url")) {
+            (ImageQuality::Low, Some(thumbnail)) => (
+                Some(thumbnail),
+                dimension("thumbnail_width"),
+                dimension("thumbnail_height"),
+            ),
+            _ => (field("url"), dimension("width"), dimension("height")),
+        };
+        let image_url = image_url.ok_or_else(|| {
+            AppErrorKind::UpstreamNotFound(format!("deviation {url} is not an image"))
+        })?;
+        let description = field("title")
+            .map(|title| match field("author_name") {
+                Some(author) => format!("{title} by {author}"),
+                None => title.to_owned(),
+            })
+            .map(|text| truncate_description(text.trim()))
+            .filter(|text| !text.is_empty());
+
+        Ok(FetchedLink {
+            image_url: image_url.to_owned(),
+            new_source: None,
+            description,
+            width,
+            height,
+            artist: field("author_name").map(str::to_owned),
+            preview: None,
+            validators: None,
+            fetched_at: Instant::now(),
+        })
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::{StatusCode, Uri};
+use serde_json::Value;
+
+use super::{truncate_description, ArtSource};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// misskey and mastodon posts from the instances in FEDI_INSTANCES
+pub(crate) struct Fedi {
+    hosts: Vec<&'static str>,
+    image_hosts: Vec<&'static str>,
+}
+
+impl Fedi {
+    pub(crate) fn new() -> Self {
+        let hosts = config().upstream.fedi_instances().collect::<Vec<_>>();
+        // media is usually served from the instance or a subdomain of it, other media hosts
+        // have to go in CSP_EXTRA_IMG_HOSTS
+        let mut image_hosts = hosts.clone();
+        for host in &hosts {
+            // the registry lives as long as the program, so this is only leaked once per instance
+            image_hosts.push(Box::leak(format!("*.{host}").into_boxed_str()));
+        }
+        Self { hosts, image_hosts }
+    }
+}
+
+enum FediPost<'a> {
+    // misskey, /notes/{id}
+    Note(&'a str),
+    // mastodon, /@user/{id}
+    Status(&'a str),
+}
+
+// instances host plenty of pages that aren't posts, so the path has to look like one
+fn fedi_post(url: &Uri) -> Option<FediPost<'_>> {
+    let mut segments = url.path().split('/').filter(|s| !s.is_empty());
+    let post = match (segments.next()?, segments.next()?) {
+        ("notes", id) => FediPost::Note(id),
+        (user, id) if user.starts_with('@') && id.bytes().all(|b| b.is_ascii_digit()) => {
+            FediPost::Status(id)
+        }
+        _ => return None,
+    };
+    segments.next().is_none().then_some(post)
+}
+
+#[async_trait]
+impl ArtSource for Fedi {
+    fn name(&self) -> &'static str {
+        "fedi"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &self.hosts
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &self.image_hosts
+    }
+
+    fn accepts(&self, url: &Uri) -> bool {
+        fedi_post(url).is_some()
+    }
+
+    fn artist<'a>(&self, url: &'a Uri) -> Option<&'a str> {
+        let user = url.path().split('/').nth(1)?.strip_prefix('@')?;
+        (!user.is_empty()).then_some(user)
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let host = url.host().unwrap_or_default();
+        let image = match fedi_post(url) {
+            Some(FediPost::Note(id)) => fetch_note(http, host, id, quality).await?,
+            Some(FediPost::Status(id)) => fetch_status(http, host, id, quality).await?,
+            None => {
+                return Err(AppErrorKind::InvalidArtUrl(format!("{url} is not a fedi post")).into())
+            }
+        };
+        image.ok_or_else(|| AppErrorKind::UpstreamNotFound(format!("{url} has no image")).into())
+    }
+}
+
+async fn fetch_json(host: &str, req: reqwest::RequestBuilder) -> AppResult<Value> {
+    let resp = req.send().await?;
+    // instances with authorized fetch or private posts refuse anonymous requests
+    if resp.status() == StatusCode::UNAUTHORIZED || resp.status() == StatusCode::FORBIDDEN {
+        return Err(AppErrorKind::UpstreamRestricted(format!(
+            "{host} requires logging in to see the post"
+        ))
+        .into());
+    }
+    check_throttled(&resp)?;
+    Ok(resp.error_for_status()?.json::<Value>().await?)
+}
+
+async fn fetch_note(
+    http: &reqwest::Client,
+    host: &str,
+    id: &str,
+    quality: ImageQuality,
+) -> AppResult<Option<FetchedLink>> {
+    let apiurl = format!("https://{host}/api/notes/show");
+    tracing::debug!(url = %apiurl, note = id, "fetching misskey note");
+    let req = http
+        .post(&apiurl)
+        .json(&serde_json::json!({ "noteId": id }));
+    let note = fetch_json(host, req).await?;
+
+    let text = note.get("text").and_then(Value::as_str);
+    let files = note.get("files").and_then(Value::as_array);
+    let Some(file) = files.and_then(|files| {
+        files.iter().find(|file| {
+            file.get("type")
+                .and_then(Value::as_str)
+                .map_or(false, |kind| kind.starts_with("image/"))
+        })
+    }) else {
+        return Ok(None);
+    };
+
+    let field = |name: &str| file.get(name).and_then(Value::as_str);
+    // thumbnails are small and their size isn't reported
+    let (image_url, size) = match (quality, field("thumbnailUrl")) {
+        (ImageQuality::Low, Some(thumbnail)) => (thumbnail, (None, None)),
+        _ => {
+            let properties = file.get("properties");
+            (
+                field("url").unwrap_or_default(),
+                (
+                    dimension(properties, "width"),
+                    dimension(properties, "height"),
+                ),
+            )
+        }
+    };
+    Ok(fetched(image_url, field("comment").or(text), size))
+}
+
+async fn fetch_status(
+    http: &reqwest::Client,
+    host: &str,
+    id: &str,
+    quality: ImageQuality,
+) -> AppResult<Option<FetchedLink>> {
+    let apiurl = format!("https://{host}/api/v1/statuses/{id}");
+    tracing::debug!(url = %apiurl, "fetching mastodon status");
+    let status = fetch_json(host, http.get(&apiurl)).await?;
+
+    let attachments = status.get("media_attachments").and_then(Value::as_array);
+    let Some(attachment) = attachments.and_then(|attachments| {
+        attachments
+            .iter()
+            .find(|media| media.get("type").and_then(Value::as_str) == Some("image"))
+    }) else {
+        return Ok(None);
+    };
+
+    let field = |name: &str| attachment.get(name).and_then(Value::as_str);
+    let meta = attachment.get("meta");
+    let (image_url, size) = match quality {
+        ImageQuality::Low => (
+            field("preview_url").or(field("url")),
+            meta.and_then(|meta| meta.get("small")),
+        ),
+        _ => (field("url"), meta.and_then(|meta| meta.get("original"))),
+    };
+    let size = (dimension(size, "width"), dimension(size, "height"));
+    // the status content is html, so only the alt text is used
+    Ok(fetched(
+        image_url.unwrap_or_default(),
+        field("description"),
+        size,
+    ))
+}
+
+fn dimension(object: Option<&Value>, field: &str) -> Option<u32> {
+    object
+        .and_then(|object| object.get(field)?.as_u64())
+        .and_then(|value| u32::try_from(value).ok())
+        .filter(|value| *value > 0)
+}
+
+fn fetched(
+    image_url: &str,
+    description: Option<&str>,
+    (width, height): (Option<u32>, Option<u32>),
+) -> Option<FetchedLink> {
+    if image_url.is_empty() {
+        return None;
+    }
+    Some(FetchedLink {
+        image_url: image_url.to_owned(),
+        new_source: None,
+        description: description
+            .map(|text| truncate_description(text.trim()))
+            .filter(|text| !text.is_empty()),
+        width,
+        height,
+        artist: None,
+        preview: None,
+        validators: None,
+        fetched_at: Instant::now(),
+    })
+}
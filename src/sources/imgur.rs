@@ -0,0 +1,203 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::Uri;
+use serde_json::Value;
+
+use super::{meta_contents, truncate_description, ArtSource};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// where imgur redirects images that were deleted
+const REMOVED_PATH: &str = "/removed.png";
+
+pub(crate) struct Imgur;
+
+enum ImgurLink<'a> {
+    // i.imgur.com/{id}.{ext}, already an image
+    Direct,
+    // imgur.com/{id}
+    Image(&'a str),
+    // imgur.com/a/{id} or imgur.com/gallery/{id}
+    Album(&'a str),
+}
+
+fn imgur_link(url: &Uri) -> Option<ImgurLink<'_>> {
+    let segments = url
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let link = match (url.host()?, segments.as_slice()) {
+        ("i.imgur.com", [file]) if file.contains('.') => ImgurLink::Direct,
+        // newer album links have the title before the id, like /a/some-title-AbCdEf
+        (_, ["a" | "gallery", album]) => {
+            ImgurLink::Album(album.rsplit('-').next().filter(|id| !id.is_empty())?)
+        }
+        (_, [image]) if !image.contains('.') => ImgurLink::Image(image),
+        _ => return None,
+    };
+    Some(link)
+}
+
+#[async_trait]
+impl ArtSource for Imgur {
+    fn name(&self) -> &'static str {
+        "imgur"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &["imgur.com", "www.imgur.com", "m.imgur.com", "i.imgur.com"]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["i.imgur.com"]
+    }
+
+    fn accepts(&self, url: &Uri) -> bool {
+        imgur_link(url).is_some()
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let client_id = config().upstream.imgur_client_id.as_deref();
+        let mut fetched = match imgur_link(url) {
+            Some(ImgurLink::Direct) => image_link(url.to_string()),
+            Some(ImgurLink::Image(id)) => match client_id {
+                Some(client_id) => fetch_from_api(http, client_id, &format!("image/{id}")).await?,
+                // imgur serves an image under any extension
+                None => image_link(format!("https://i.imgur.com/{id}.jpg")),
+            },
+            Some(ImgurLink::Album(id)) => match client_id {
+                Some(client_id) => {
+                    fetch_from_api(http, client_id, &format!("album/{id}/images")).await?
+                }
+                None => fetch_from_page(http, url).await?,
+            },
+            None => {
+                return Err(
+                    AppErrorKind::InvalidArtUrl(format!("{url} is not an imgur link")).into(),
+                )
+            }
+        };
+        check_not_removed(http, &fetched.image_url).await?;
+
+        // imgur has thumbnails of every image, l is the large one
+        if quality == ImageQuality::Low {
+            if let Some((path, ext)) = fetched.image_url.rsplit_once('.') {
+                fetched.image_url = format!("{path}l.{ext}");
+                fetched.width = None;
+                fetched.height = None;
+            }
+        }
+        Ok(fetched)
+    }
+}
+
+fn image_link(image_url: String) -> FetchedLink {
+    FetchedLink {
+        image_url,
+        new_source: None,
+        description: None,
+        width: None,
+        height: None,
+        artist: None,
+        preview: None,
+        validators: None,
+        fetched_at: Instant::now(),
+    }
+}
+
+// albums return a list of images, the first one is used
+async fn fetch_from_api(
+    http: &reqwest::Client,
+    client_id: &str,
+    endpoint: &str,
+) -> AppResult<FetchedLink> {
+    let apiurl = format!("https://api.imgur.com/3/{endpoint}");
+    tracing::debug!(url = %apiurl, "fetching imgur image info");
+    let req = http
+        .get(&apiurl)
+        .header(
+            http::header::AUTHORIZATION,
+            format!("Client-ID {client_id}"),
+        )
+        .build()?;
+    let resp = http.execute(req).await?;
+    check_throttled(&resp)?;
+    let data = resp.error_for_status()?.json::<Value>().await?;
+    let image = match data.get("data") {
+        Some(Value::Array(images)) => images.first(),
+        image => image,
+    }
+    .ok_or_else(|| AppErrorKind::UpstreamNotFound(format!("imgur {endpoint} has no images")))?;
+
+    let field = |name: &str| {
+        image
+            .get(name)
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+    };
+    let dimension = |name: &str| {
+        image
+            .get(name)
+            .and_then(Value::as_u64)
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| *value > 0)
+    };
+    let image_url = field("link").ok_or_else(|| {
+        AppErrorKind::UpstreamUnavailable(format!("imgur {endpoint} image has no link"))
+    })?;
+    Ok(FetchedLink {
+        description: field("description")
+            .or(field("title"))
+            .map(|text| truncate_description(text.trim())),
+        width: dimension("width"),
+        height: dimension("height"),
+        ..image_link(image_url.to_owned())
+    })
+}
+
+// album pages have their first image in the og tags
+async fn fetch_from_page(http: &reqwest::Client, url: &Uri) -> AppResult<FetchedLink> {
+    tracing::debug!(%url, "fetching imgur album page");
+    let resp = http.execute(http.get(url.to_string()).build()?).await?;
+    check_throttled(&resp)?;
+    let page = resp.error_for_status()?.text().await?;
+    let image_url = meta_contents(&page, "og:image")
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            AppErrorKind::UpstreamNotFound(format!("imgur album {url} has no images"))
+        })?;
+    // og images sometimes carry a resize query we don't want
+    let image_url = match image_url.split_once('?') {
+        Some((image_url, _)) => image_url.to_owned(),
+        None => image_url,
+    };
+    Ok(image_link(image_url))
+}
+
+// deleted images redirect to a placeholder instead of a 404, which we shouldn't serve
+async fn check_not_removed(http: &reqwest::Client, image_url: &str) -> AppResult<()> {
+    let resp = http.execute(http.head(image_url).build()?).await?;
+    check_throttled(&resp)?;
+    let redirect = resp
+        .headers()
+        .get(http::header::LOCATION)
+        .and_then(|location| location.to_str().ok());
+    if redirect.map_or(false, |location| location.ends_with(REMOVED_PATH)) {
+        return Err(
+            AppErrorKind::UpstreamNotFound(format!("imgur image {image_url} was removed")).into(),
+        );
+    }
+    resp.error_for_status()?;
+    Ok(())
+}
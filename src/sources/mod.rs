@@ -0,0 +1,171 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use axum::async_trait;
+use http::Uri;
+
+use crate::{
+    config::config,
+    data::{Art, FetchedLink, ImageQuality},
+    error::{AppErrorKind, AppResult},
+};
+
+mod artstation;
+mod booru;
+mod deviantart;
+mod fedi;
+mod imgur;
+mod moebooru;
+mod post;
+mod reddit;
+#[cfg(test)]
+mod tests;
+mod tumblr;
+mod twitter;
+
+// a website arts can be linked from, adding one only needs an impl and an entry in builtin
+#[async_trait]
+pub(crate) trait ArtSource: Send + Sync {
+    // used for the art kind in logs, stats and the api
+    fn name(&self) -> &'static str;
+
+    // hosts of art urls from this source, exactly as written in arts lists, a leading *.
+    // matches any subdomain
+    fn hosts(&self) -> &[&'static str];
+
+    // hosts the resolved image links can point to
+    fn image_hosts(&self) -> &[&'static str];
+
+    // whether the url is one we can fetch, for hosts that serve more than art pages
+    fn accepts(&self, _url: &Uri) -> bool {
+        true
+    }
+
+    // the url caches and lists key the art by, for sources reachable through several urls
+    fn canonical(&self, url: Uri) -> Uri {
+        url
+    }
+
+    // the url of one image of a multi image post, counting from 1, for sources that can pin one
+    fn photo(&self, _url: &Uri, _photo: usize) -> Option<Uri> {
+        None
+    }
+
+    // the artist, if it can be told from the url alone
+    fn artist<'a>(&self, _url: &'a Uri) -> Option<&'a str> {
+        None
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink>;
+}
+
+// the sources we know, by the hosts they handle
+pub(crate) struct Registry {
+    sources: Vec<Box<dyn ArtSource>>,
+    by_host: HashMap<&'static str, usize>,
+}
+
+impl Registry {
+    pub(crate) fn new(sources: Vec<Box<dyn ArtSource>>) -> Self {
+        let mut by_host = HashMap::new();
+        for (index, source) in sources.iter().enumerate() {
+            for host in source.hosts() {
+                by_host.insert(*host, index);
+            }
+        }
+        Self { sources, by_host }
+    }
+
+    fn builtin() -> Self {
+        let mut sources: Vec<Box<dyn ArtSource>> = vec![
+            Box::new(twitter::Twitter::new()),
+            Box::new(booru::Booru::safebooru()),
+            Box::new(moebooru::Moebooru::yandere()),
+            Box::new(moebooru::Moebooru::konachan()),
+            Box::new(fedi::Fedi::new()),
+            Box::new(tumblr::Tumblr),
+            Box::new(deviantart::DeviantArt),
+            Box::new(reddit::Reddit),
+            Box::new(artstation::ArtStation),
+            Box::new(imgur::Imgur),
+        ];
+        for host in config().upstream.extra_boorus() {
+            sources.push(Box::new(booru::Booru::extra(host)));
+        }
+        Self::new(sources)
+    }
+
+    pub(crate) fn get(&self, host: &str) -> Option<&dyn ArtSource> {
+        let index = self.by_host.get(host).or_else(|| {
+            let (_, parent) = host.split_once('.')?;
+            self.by_host.get(format!("*.{parent}").as_str())
+        })?;
+        Some(self.sources[*index].as_ref())
+    }
+
+    pub(crate) fn all(&self) -> impl Iterator<Item = &dyn ArtSource> {
+        self.sources.iter().map(|source| source.as_ref())
+    }
+
+    pub(crate) async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        art: &Art,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let host = art.url.host().unwrap_or_default();
+        let source = self
+            .get(host)
+            .ok_or_else(|| AppErrorKind::InvalidArtUrl(format!("no source handles {host}")))?;
+        source.fetch(http, &art.url, quality).await
+    }
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+// the registry arts are parsed with, needs the config to be loaded first
+pub(crate) fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::builtin)
+}
+
+// the content of every <meta property="..."> tag with the property, good enough for og tags
+fn meta_contents(html: &str, property: &str) -> Vec<String> {
+    html.split("<meta")
+        .skip(1)
+        .filter_map(|tag| {
+            let tag = &tag[..tag.find('>')?];
+            let name = attribute(tag, "property").or_else(|| attribute(tag, "name"))?;
+            let content = attribute(tag, "content")?;
+            (name == property).then(|| unescape_html(content))
+        })
+        .collect()
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(&tag[start..end])
+}
+
+// only the entities pages actually use in urls and descriptions
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+const MAX_DESCRIPTION_LEN: usize = 300;
+
+fn truncate_description(text: &str) -> String {
+    match text.char_indices().nth(MAX_DESCRIPTION_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_owned(),
+    }
+}
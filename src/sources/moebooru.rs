@@ -0,0 +1,146 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::Uri;
+
+use super::{
+    post::{self, Post, PostSource},
+    twitter::Twitter,
+    ArtSource,
+};
+use crate::{
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// boorus running moebooru, which has its own api instead of the dapi
+pub(crate) struct Moebooru {
+    name: &'static str,
+    hosts: &'static [&'static str],
+    image_hosts: &'static [&'static str],
+    // for posts sourced from twitter
+    twitter: Twitter,
+}
+
+impl Moebooru {
+    pub(crate) fn yandere() -> Self {
+        Self {
+            name: "yandere",
+            hosts: &["yande.re"],
+            image_hosts: &["files.yande.re", "assets.yande.re", "pbs.twimg.com"],
+            twitter: Twitter::new(),
+        }
+    }
+
+    pub(crate) fn konachan() -> Self {
+        Self {
+            name: "konachan",
+            hosts: &["konachan.com"],
+            image_hosts: &["konachan.com", "*.konachan.com", "pbs.twimg.com"],
+            twitter: Twitter::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtSource for Moebooru {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        self.hosts
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        self.image_hosts
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        fetch_image_link(self, http, url, quality).await
+    }
+}
+
+// post urls look like /post/show/12345, sometimes followed by the tags
+fn post_id(url: &Uri) -> Option<u64> {
+    let mut segments = url.path().split('/').filter(|s| !s.is_empty());
+    if segments.next()? != "post" || segments.next()? != "show" {
+        return None;
+    }
+    segments.next()?.parse().ok()
+}
+
+async fn fetch_image_link(
+    booru: &Moebooru,
+    http: &reqwest::Client,
+    url: &Uri,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    let id = post_id(url).ok_or_else(|| {
+        AppErrorKind::InvalidArtUrl(format!("{} url {url} has no post id", booru.name))
+    })?;
+    let host = url.host().unwrap_or(booru.hosts[0]);
+    let apiurl = format!("https://{host}/post.json?tags=id:{id}");
+    tracing::debug!(url = %apiurl, booru = booru.name, "fetching moebooru post");
+    let resp = http.execute(http.get(&apiurl).build()?).await?;
+    check_throttled(&resp)?;
+    let data = resp.error_for_status()?.json::<Vec<Post>>().await?;
+    let post = data.first().ok_or_else(|| {
+        AppErrorKind::UpstreamNotFound(format!("{} post {id} does not exist", booru.name))
+    })?;
+
+    let source = PostSource::of(post);
+    let description = post::tag_description(post);
+    if let Some(fetched) =
+        post::fetch_from_twitter(&booru.twitter, http, &source, quality, description.clone()).await
+    {
+        return Ok(fetched);
+    }
+
+    let original_size = (
+        post::dimension(post, "width"),
+        post::dimension(post, "height"),
+    );
+    // the sample is used whenever the wanted image is missing
+    let picked = match quality {
+        ImageQuality::Original => post::image_field(booru.name, post, "file_url")
+            .ok()
+            .map(|file_url| (file_url, original_size)),
+        // previews are small thumbnails, and their size isn't reported
+        ImageQuality::Low => post::image_field(booru.name, post, "preview_url")
+            .ok()
+            .map(|preview_url| (preview_url, (None, None))),
+        ImageQuality::Sample => None,
+    };
+    let (image_url, (width, height)) = match picked {
+        Some(picked) => picked,
+        None => {
+            let sample_url = post::image_field(booru.name, post, "sample_url")?;
+            let sample_size = (
+                post::dimension(post, "sample_width"),
+                post::dimension(post, "sample_height"),
+            );
+            match sample_size {
+                (Some(width), Some(height)) => (sample_url, (Some(width), Some(height))),
+                _ => (sample_url, original_size),
+            }
+        }
+    };
+
+    Ok(FetchedLink {
+        image_url: image_url.to_string(),
+        new_source: source.url,
+        description,
+        width,
+        height,
+        artist: None,
+        preview: None,
+        validators: None,
+        fetched_at: Instant::now(),
+    })
+}
@@ -0,0 +1,234 @@
+// helpers for booru posts, shared by the dapi and moebooru sources
+use std::str::FromStr;
+
+use http::Uri;
+
+use super::{truncate_description, twitter::Twitter, ArtSource};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{AppErrorKind, AppResult},
+};
+
+pub(super) type Post = serde_json::Map<String, serde_json::Value>;
+
+// where a post says its art came from
+pub(super) struct PostSource {
+    // the link we credit, pixiv images are turned into their artwork page
+    pub(super) url: Option<Uri>,
+    // pixiv doesn't allow hotlinking, but mirrors can serve the image if the booru can't
+    pub(super) pximg: Option<Uri>,
+}
+
+impl PostSource {
+    pub(super) fn of(post: &Post) -> Self {
+        let original_source = post
+            .get("source")
+            .and_then(|src| Uri::from_str(src.as_str()?).ok());
+        let pximg = original_source
+            .clone()
+            .filter(|src| src.host() == Some("i.pximg.net"));
+        // pixiv links we can't make sense of are credited as they are
+        let url = original_source.map(|src| {
+            pixiv_artwork_id(&src)
+                .and_then(|id| format!("https://pixiv.net/en/artworks/{id}").parse().ok())
+                .unwrap_or(src)
+        });
+        Self { url, pximg }
+    }
+
+    // exactly the hosts the twitter source takes, so lookalikes like notx.com aren't fetched
+    fn is_twitter(&self, twitter: &Twitter) -> bool {
+        self.url
+            .as_ref()
+            .and_then(|src| src.host())
+            .map_or(false, |host| {
+                twitter
+                    .hosts()
+                    .contains(&host.to_ascii_lowercase().as_str())
+            })
+    }
+}
+
+// the artwork id of any pixiv link, from images like /img-original/img/.../12345_p0.png, pages
+// like /artworks/12345 and /i/12345, or the old member_illust.php?illust_id=12345
+fn pixiv_artwork_id(src: &Uri) -> Option<&str> {
+    let is_id = |id: &&str| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+    match src.host()? {
+        "i.pximg.net" => {
+            let file = src.path().rsplit('/').next()?;
+            let id = file.split(['_', '.']).next()?;
+            Some(id).filter(is_id)
+        }
+        "pixiv.net" | "www.pixiv.net" => {
+            if let Some(query) = src.query() {
+                let id = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("illust_id="));
+                if let Some(id) = id {
+                    return Some(id).filter(is_id);
+                }
+            }
+            let mut segments = src.path().split('/').filter(|s| !s.is_empty());
+            // the artwork pages can have a language in front, like /en/artworks/12345
+            let mut kind = segments.next()?;
+            if kind.len() == 2 {
+                kind = segments.next()?;
+            }
+            let id = segments.next()?;
+            (matches!(kind, "artworks" | "i") && segments.next().is_none())
+                .then_some(id)
+                .filter(is_id)
+        }
+        _ => None,
+    }
+}
+
+// the post's tags, readable enough to be alt text
+pub(super) fn tag_description(post: &Post) -> Option<String> {
+    post.get("tags")
+        .and_then(|tags| tags.as_str())
+        .map(|tags| {
+            let tags = tags
+                .split_whitespace()
+                .map(|tag| tag.replace('_', " "))
+                .collect::<Vec<_>>()
+                .join(", ");
+            truncate_description(&tags)
+        })
+        .filter(|tags| !tags.is_empty())
+}
+
+// the image straight from twitter if the post was sourced from there, it's usually better quality
+pub(super) async fn fetch_from_twitter(
+    twitter: &Twitter,
+    http: &reqwest::Client,
+    source: &PostSource,
+    quality: ImageQuality,
+    description: Option<String>,
+) -> Option<FetchedLink> {
+    if !source.is_twitter(twitter) {
+        return None;
+    }
+    let url = source.url.clone()?;
+    tracing::debug!(source = %url, "booru source was twitter, fetching image from there");
+    let mut fetched = twitter.fetch_image_link(http, &url, quality).await.ok()?;
+    tracing::debug!(source = %url, "fetched booru image from twitter");
+    fetched.new_source = Some(url);
+    fetched.description = fetched.description.or(description);
+    Some(fetched)
+}
+
+// the first mirror in PIXIV_PROXIES that has the image
+pub(super) async fn pixiv_mirror_image(http: &reqwest::Client, pximg: &Uri) -> AppResult<String> {
+    let path = pximg.path_and_query().map_or("/", |path| path.as_str());
+    for mirror in config().upstream.pixiv_proxies() {
+        let image_url = format!("https://{mirror}{path}");
+        match http.head(&image_url).send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(image_url),
+            Ok(resp) => {
+                tracing::debug!(
+                    image_url,
+                    status = resp.status().as_u16(),
+                    "pixiv mirror does not have the image"
+                )
+            }
+            Err(err) => tracing::debug!(image_url, error = %err, "pixiv mirror is unreachable"),
+        }
+    }
+    Err(AppErrorKind::NoWorkingMirror(pximg.to_string()).into())
+}
+
+// boorus send sizes as numbers, but be lenient in case they come as strings
+pub(super) fn dimension(post: &Post, field: &str) -> Option<u32> {
+    let value = post.get(field)?;
+    value
+        .as_u64()
+        .and_then(|value| u32::try_from(value).ok())
+        .or_else(|| value.as_str()?.parse().ok())
+        .filter(|value| *value > 0)
+}
+
+pub(super) fn image_field(site: &str, post: &Post, field: &str) -> AppResult<Uri> {
+    let url = post
+        .get(field)
+        .ok_or_else(|| AppErrorKind::UpstreamUnavailable(format!("{site} did not return {field}")))?
+        .as_str()
+        .ok_or_else(|| {
+            AppErrorKind::UpstreamUnavailable(format!("{site} {field} wasnt a string"))
+        })?;
+    Uri::from_str(url).map_err(|err| {
+        AppErrorKind::UpstreamUnavailable(format!("{site} {field} was not valid: {err}")).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(url: &str) -> PostSource {
+        PostSource {
+            url: Some(url.parse().unwrap()),
+            pximg: None,
+        }
+    }
+
+    fn artwork_id(src: &str) -> Option<String> {
+        pixiv_artwork_id(&src.parse().unwrap()).map(str::to_owned)
+    }
+
+    #[test]
+    fn pixiv_image_ids() {
+        assert_eq!(
+            artwork_id("https://i.pximg.net/img-original/img/2024/01/01/00/00/00/12345_p0.png"),
+            Some("12345".to_owned())
+        );
+    }
+
+    #[test]
+    fn pixiv_artwork_page_ids() {
+        assert_eq!(
+            artwork_id("https://www.pixiv.net/artworks/12345"),
+            Some("12345".to_owned())
+        );
+        assert_eq!(artwork_id("https://pixiv.net/artworks/12345/extra"), None);
+    }
+
+    #[test]
+    fn pixiv_localized_page_ids() {
+        assert_eq!(
+            artwork_id("https://www.pixiv.net/en/artworks/12345"),
+            Some("12345".to_owned())
+        );
+    }
+
+    #[test]
+    fn pixiv_short_link_ids() {
+        assert_eq!(
+            artwork_id("https://pixiv.net/i/12345"),
+            Some("12345".to_owned())
+        );
+        assert_eq!(artwork_id("https://pixiv.net/users/12345"), None);
+    }
+
+    #[test]
+    fn pixiv_old_page_ids() {
+        assert_eq!(
+            artwork_id("https://www.pixiv.net/member_illust.php?mode=medium&illust_id=12345"),
+            Some("12345".to_owned())
+        );
+        assert_eq!(
+            artwork_id("https://www.pixiv.net/member_illust.php?illust_id=abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn only_twitter_hosts_are_twitter() {
+        let twitter = Twitter::at("", "");
+        assert!(source("https://twitter.com/someone/status/1").is_twitter(&twitter));
+        assert!(source("https://X.com/someone/status/1").is_twitter(&twitter));
+        assert!(!source("https://notx.com/someone/status/1").is_twitter(&twitter));
+        assert!(!source("https://twitter.com.example/someone/status/1").is_twitter(&twitter));
+    }
+}
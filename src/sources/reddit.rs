@@ -0,0 +1,185 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::{StatusCode, Uri};
+use serde_json::Value;
+
+use super::{truncate_description, unescape_html, ArtSource};
+use crate::{
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// low quality images shouldn't be wider than this
+const LOW_MAX_WIDTH: u64 = 640;
+
+pub(crate) struct Reddit;
+
+#[async_trait]
+impl ArtSource for Reddit {
+    fn name(&self) -> &'static str {
+        "reddit"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &["www.reddit.com", "reddit.com", "old.reddit.com"]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["i.redd.it", "*.redd.it"]
+    }
+
+    fn accepts(&self, url: &Uri) -> bool {
+        post_path(url).is_some()
+    }
+
+    // every reddit host shows the same post, so they should share a cache entry
+    fn canonical(&self, url: Uri) -> Uri {
+        match post_path(&url) {
+            Some(path) => format!("https://www.reddit.com{path}")
+                .parse()
+                .unwrap_or(url),
+            None => url,
+        }
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let path = post_path(url)
+            .ok_or_else(|| AppErrorKind::InvalidArtUrl(format!("{url} is not a reddit post")))?;
+        let apiurl = format!("https://www.reddit.com{}.json", path.trim_end_matches('/'));
+        tracing::debug!(url = %apiurl, "fetching reddit post");
+        let resp = http.execute(http.get(&apiurl).build()?).await?;
+        check_throttled(&resp)?;
+        // private subreddits and nsfw posts need an account
+        if resp.status() == StatusCode::FORBIDDEN {
+            return Err(AppErrorKind::UpstreamRestricted(format!(
+                "reddit post {url} is only visible when logged in"
+            ))
+            .into());
+        }
+        let data = resp.error_for_status()?.json::<Value>().await?;
+        let post = data.pointer("/0/data/children/0/data").ok_or_else(|| {
+            AppErrorKind::UpstreamNotFound(format!("reddit post {url} does not exist"))
+        })?;
+        if post
+            .get("removed_by_category")
+            .map_or(false, |removed| !removed.is_null())
+        {
+            return Err(
+                AppErrorKind::UpstreamNotFound(format!("reddit post {url} was removed")).into(),
+            );
+        }
+
+        let image = if post.get("is_gallery").and_then(Value::as_bool) == Some(true) {
+            gallery_image(post, quality)
+        } else {
+            preview_image(post, quality)
+        };
+        let Some((image_url, width, height)) = image else {
+            // nsfw posts have their previews hidden from logged out visitors
+            if post.get("over_18").and_then(Value::as_bool) == Some(true) {
+                return Err(AppErrorKind::UpstreamRestricted(format!(
+                    "reddit post {url} is nsfw and has no visible image"
+                ))
+                .into());
+            }
+            return Err(
+                AppErrorKind::UpstreamNotFound(format!("reddit post {url} has no image")).into(),
+            );
+        };
+        let description = post
+            .get("title")
+            .and_then(Value::as_str)
+            .map(|title| truncate_description(unescape_html(title).trim()))
+            .filter(|title| !title.is_empty());
+
+        Ok(FetchedLink {
+            image_url,
+            new_source: None,
+            description,
+            width,
+            height,
+            artist: None,
+            preview: None,
+            validators: None,
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+// post urls look like /r/{subreddit}/comments/{id}/{slug}
+fn post_path(url: &Uri) -> Option<&str> {
+    let segments = url
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["r", _, "comments", _, ..] => Some(url.path()),
+        _ => None,
+    }
+}
+
+type Image = (String, Option<u32>, Option<u32>);
+
+// images reddit sends have their urls html escaped
+fn image(url: Option<&Value>, width: Option<&Value>, height: Option<&Value>) -> Option<Image> {
+    let dimension = |value: Option<&Value>| {
+        value
+            .and_then(Value::as_u64)
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| *value > 0)
+    };
+    Some((
+        unescape_html(url?.as_str()?),
+        dimension(width),
+        dimension(height),
+    ))
+}
+
+// the largest resolution that fits low quality, from resolutions sorted from small to large
+fn low_resolution<'a>(resolutions: Option<&'a Value>, width_field: &str) -> Option<&'a Value> {
+    resolutions?
+        .as_array()?
+        .iter()
+        .take_while(|resolution| {
+            resolution
+                .get(width_field)
+                .and_then(Value::as_u64)
+                .map_or(false, |width| width <= LOW_MAX_WIDTH)
+        })
+        .last()
+}
+
+fn preview_image(post: &Value, quality: ImageQuality) -> Option<Image> {
+    let preview = post.pointer("/preview/images/0")?;
+    let picked = match quality {
+        ImageQuality::Low => low_resolution(preview.get("resolutions"), "width"),
+        ImageQuality::Original | ImageQuality::Sample => None,
+    }
+    .or_else(|| preview.get("source"))?;
+    image(picked.get("url"), picked.get("width"), picked.get("height"))
+}
+
+// like multi image tweets, any image of the gallery will do
+fn gallery_image(post: &Value, quality: ImageQuality) -> Option<Image> {
+    let items = post.pointer("/gallery_data/items")?.as_array()?;
+    if items.is_empty() {
+        return None;
+    }
+    let media_id = items[fastrand::usize(..items.len())]
+        .get("media_id")?
+        .as_str()?;
+    let media = post.get("media_metadata")?.get(media_id)?;
+    let picked = match quality {
+        ImageQuality::Low => low_resolution(media.get("p"), "x"),
+        ImageQuality::Original | ImageQuality::Sample => None,
+    }
+    .or_else(|| media.get("s"))?;
+    image(picked.get("u"), picked.get("x"), picked.get("y"))
+}
@@ -0,0 +1,217 @@
+// the twitter and safebooru fetchers against a mock upstream, through the whole app
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use super::{booru::Booru, twitter::Twitter, Registry};
+use crate::{data::Art, testing};
+
+const TWEET: &str = "https://twitter.com/someone/status/1";
+const POST: &str = "https://safebooru.org/index.php?page=post&s=view&id=1";
+// keeps the backoff between retries short
+const RETRIES: u32 = 2;
+
+// twitter and safebooru, both talking to the mock instead of the real hosts
+fn mock_sources(upstream: &MockServer) -> &'static Registry {
+    let twitter = || Twitter::at(&upstream.uri(), &format!("{}/api", upstream.uri()));
+    Box::leak(Box::new(Registry::new(vec![
+        Box::new(twitter()),
+        Box::new(Booru::safebooru_at(&upstream.uri(), RETRIES, twitter())),
+    ])))
+}
+
+// a seed always picks the same art, so there are no rerolls to other arts
+async fn get_page(app: &str) -> reqwest::Response {
+    reqwest::get(format!("{app}/?seed=test")).await.unwrap()
+}
+
+async fn mock_tweet(upstream: &MockServer) {
+    let image = format!("{}/media/1.jpg", upstream.uri());
+    Mock::given(method("GET"))
+        .and(path("/someone/status/1"))
+        .respond_with(ResponseTemplate::new(302).insert_header("location", image.as_str()))
+        .expect(1)
+        .mount(upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/someone/status/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "tweet": {
+                "text": "an art",
+                "media": {"photos": [{"url": image, "width": 800, "height": 600}]},
+            },
+        })))
+        .mount(upstream)
+        .await;
+}
+
+#[tokio::test]
+async fn tweet_page_shows_the_redirected_image() {
+    let upstream = MockServer::start().await;
+    mock_tweet(&upstream).await;
+    let state = testing::state(mock_sources(&upstream), TWEET);
+    let app = testing::serve(&state).await;
+
+    let resp = get_page(&app).await;
+    assert_eq!(resp.status(), 200);
+    let page = resp.text().await.unwrap();
+    let src = format!(r#"src="{}/media/1.jpg?format=webp""#, upstream.uri());
+    assert!(page.contains(&src), "{src} is not in the page");
+    assert!(page.contains(r#"alt="an art""#));
+    assert!(page.contains(r#"width="800""#));
+}
+
+#[tokio::test]
+async fn cache_hits_skip_the_upstream() {
+    let upstream = MockServer::start().await;
+    // the redirect expects to be fetched once
+    mock_tweet(&upstream).await;
+    let state = testing::state(mock_sources(&upstream), TWEET);
+    let app = testing::serve(&state).await;
+
+    assert_eq!(get_page(&app).await.status(), 200);
+    assert_eq!(get_page(&app).await.status(), 200);
+}
+
+#[tokio::test]
+async fn safebooru_retries_the_configured_times() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/index.php"))
+        .and(query_param("id", "1"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(u64::from(RETRIES) + 1)
+        .mount(&upstream)
+        .await;
+    let state = testing::state(mock_sources(&upstream), POST);
+    let app = testing::serve(&state).await;
+
+    assert!(get_page(&app).await.status().is_server_error());
+}
+
+#[tokio::test]
+async fn deleted_posts_are_not_server_errors() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/index.php"))
+        .and(query_param("id", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    let state = testing::state(mock_sources(&upstream), POST);
+    let app = testing::serve(&state).await;
+
+    let status = get_page(&app).await.status();
+    assert_ne!(status, 500);
+    assert!(status.is_client_error(), "{status}");
+}
+
+// a post whose sample is at the given path of the mock, which safebooru sends with doubled slashes
+async fn mock_post(upstream: &MockServer, sample_path: &str) {
+    Mock::given(method("GET"))
+        .and(path("/index.php"))
+        .and(query_param("id", "1"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "sample_url": format!("{}{sample_path}", upstream.uri()),
+                "file_url": format!("{}/images/1.jpg", upstream.uri()),
+                "width": 800,
+                "height": 600,
+            }])),
+        )
+        .mount(upstream)
+        .await;
+}
+
+#[tokio::test]
+async fn booru_images_are_probed_at_the_normalized_path() {
+    let upstream = MockServer::start().await;
+    mock_post(&upstream, "//samples//1.jpg").await;
+    Mock::given(method("GET"))
+        .and(path("/samples/1.jpg"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    // the path as sent is only a fallback
+    Mock::given(method("GET"))
+        .and(path("//samples//1.jpg"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&upstream)
+        .await;
+    let state = testing::state(mock_sources(&upstream), POST);
+    let app = testing::serve(&state).await;
+
+    let page = get_page(&app).await.text().await.unwrap();
+    let src = format!(r#"src="{}/samples/1.jpg""#, upstream.uri());
+    assert!(page.contains(&src), "{src} is not in the page");
+}
+
+#[tokio::test]
+async fn booru_images_fall_back_to_the_path_as_sent() {
+    let upstream = MockServer::start().await;
+    mock_post(&upstream, "//samples//1.jpg").await;
+    Mock::given(method("GET"))
+        .and(path("/samples/1.jpg"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("//samples//1.jpg"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    let state = testing::state(mock_sources(&upstream), POST);
+    let app = testing::serve(&state).await;
+
+    let page = get_page(&app).await.text().await.unwrap();
+    let src = format!(r#"src="{}//samples//1.jpg""#, upstream.uri());
+    assert!(page.contains(&src), "{src} is not in the page");
+}
+
+// parsing without a mock upstream, the urls are never fetched
+fn offline_sources() -> &'static Registry {
+    let twitter = || Twitter::at("", "");
+    Box::leak(Box::new(Registry::new(vec![
+        Box::new(twitter()),
+        Box::new(Booru::safebooru_at("", RETRIES, twitter())),
+    ])))
+}
+
+#[test]
+fn tweet_links_collapse_to_one_key() {
+    let sources = offline_sources();
+    for link in [
+        "https://twitter.com/someone/status/1",
+        "https://twitter.com/someone/status/1?s=20&t=abc",
+        "https://TWITTER.com/someone/status/1",
+        "https://twitter.com/someone/status/1/",
+        "https://twitter.com/someone/status/1/photo/2",
+        "https://x.com/someone/status/1?s=46",
+    ] {
+        let art = Art::parse(link, sources).unwrap();
+        assert_eq!(art.url, TWEET, "{link}");
+    }
+}
+
+#[test]
+fn booru_post_ids_are_kept() {
+    let sources = offline_sources();
+    let art = Art::parse(
+        "https://Safebooru.org/index.php?page=post&s=view&id=1",
+        sources,
+    )
+    .unwrap();
+    assert_eq!(art.url, POST);
+    let other = Art::parse(
+        "https://safebooru.org/index.php?page=post&s=view&id=2",
+        sources,
+    )
+    .unwrap();
+    assert_ne!(art.url, other.url);
+}
@@ -0,0 +1,177 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::Uri;
+use serde_json::Value;
+
+use super::{meta_contents, truncate_description, ArtSource};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// low quality images shouldn't be wider than this
+const LOW_MAX_WIDTH: u64 = 500;
+
+pub(crate) struct Tumblr;
+
+#[async_trait]
+impl ArtSource for Tumblr {
+    fn name(&self) -> &'static str {
+        "tumblr"
+    }
+
+    // every blog has its own subdomain, custom domains aren't supported
+    fn hosts(&self) -> &[&'static str] {
+        &["*.tumblr.com"]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["*.media.tumblr.com"]
+    }
+
+    fn accepts(&self, url: &Uri) -> bool {
+        blog(url).is_some() && post_id(url).is_some()
+    }
+
+    fn artist<'a>(&self, url: &'a Uri) -> Option<&'a str> {
+        blog(url)
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let (Some(blog), Some(id)) = (blog(url), post_id(url)) else {
+            return Err(AppErrorKind::InvalidArtUrl(format!("{url} is not a tumblr post")).into());
+        };
+        match &config().upstream.tumblr_api_key {
+            Some(api_key) => fetch_from_api(http, blog, id, api_key, quality).await,
+            None => fetch_from_page(http, url).await,
+        }
+    }
+}
+
+fn blog(url: &Uri) -> Option<&str> {
+    url.host()?
+        .strip_suffix(".tumblr.com")
+        .filter(|blog| !blog.is_empty() && *blog != "www")
+}
+
+// post urls look like /post/12345, sometimes followed by a slug
+fn post_id(url: &Uri) -> Option<&str> {
+    let mut segments = url.path().split('/').filter(|s| !s.is_empty());
+    if segments.next()? != "post" {
+        return None;
+    }
+    segments
+        .next()
+        .filter(|id| id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+async fn fetch_from_api(
+    http: &reqwest::Client,
+    blog: &str,
+    id: &str,
+    api_key: &str,
+    quality: ImageQuality,
+) -> AppResult<FetchedLink> {
+    let apiurl = format!("https://api.tumblr.com/v2/blog/{blog}.tumblr.com/posts?id={id}");
+    tracing::debug!(url = %apiurl, "fetching tumblr post");
+    let req = http.get(&apiurl).query(&[("api_key", api_key)]).build()?;
+    let resp = http.execute(req).await?;
+    check_throttled(&resp)?;
+    let data = resp.error_for_status()?.json::<Value>().await?;
+    let post = data.pointer("/response/posts/0").ok_or_else(|| {
+        AppErrorKind::UpstreamNotFound(format!("tumblr post {id} does not exist"))
+    })?;
+
+    let photos = post
+        .get("photos")
+        .and_then(Value::as_array)
+        .filter(|photos| !photos.is_empty())
+        .ok_or_else(|| AppErrorKind::UpstreamNotFound(format!("tumblr post {id} has no photos")))?;
+    // like multi image tweets, any photo of the post will do
+    let photo = &photos[fastrand::usize(..photos.len())];
+    let size = match quality {
+        // alt sizes go from the largest to the smallest
+        ImageQuality::Low => photo
+            .get("alt_sizes")
+            .and_then(Value::as_array)
+            .and_then(|sizes| {
+                sizes.iter().find(|size| {
+                    size.get("width")
+                        .and_then(Value::as_u64)
+                        .map_or(false, |width| width <= LOW_MAX_WIDTH)
+                })
+            }),
+        // tumblr doesn't keep anything larger than the original size
+        ImageQuality::Original | ImageQuality::Sample => None,
+    }
+    .or_else(|| photo.get("original_size"))
+    .ok_or_else(|| {
+        AppErrorKind::UpstreamUnavailable(format!("tumblr post {id} photo has no sizes"))
+    })?;
+
+    let image_url = size.get("url").and_then(Value::as_str).ok_or_else(|| {
+        AppErrorKind::UpstreamUnavailable(format!("tumblr post {id} photo has no url"))
+    })?;
+    let dimension = |field: &str| {
+        size.get(field)
+            .and_then(Value::as_u64)
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| *value > 0)
+    };
+    // captions are html, the summary is plain text
+    let description = post
+        .get("summary")
+        .and_then(Value::as_str)
+        .map(|text| truncate_description(text.trim()))
+        .filter(|text| !text.is_empty());
+    Ok(FetchedLink {
+        image_url: image_url.to_owned(),
+        new_source: None,
+        description,
+        width: dimension("width"),
+        height: dimension("height"),
+        artist: None,
+        preview: None,
+        validators: None,
+        fetched_at: Instant::now(),
+    })
+}
+
+// without an api key the images can still be found in the page's og tags
+async fn fetch_from_page(http: &reqwest::Client, url: &Uri) -> AppResult<FetchedLink> {
+    tracing::debug!(%url, "fetching tumblr post page");
+    let resp = http.execute(http.get(url.to_string()).build()?).await?;
+    check_throttled(&resp)?;
+    let page = resp.error_for_status()?.text().await?;
+
+    let images = meta_contents(&page, "og:image");
+    if images.is_empty() {
+        return Err(
+            AppErrorKind::UpstreamNotFound(format!("tumblr post {url} has no photos")).into(),
+        );
+    }
+    let image_url = images[fastrand::usize(..images.len())].clone();
+    let description = meta_contents(&page, "og:description")
+        .into_iter()
+        .next()
+        .map(|text| truncate_description(text.trim()))
+        .filter(|text| !text.is_empty());
+    Ok(FetchedLink {
+        image_url,
+        new_source: None,
+        description,
+        width: None,
+        height: None,
+        artist: None,
+        preview: None,
+        validators: None,
+        fetched_at: Instant::now(),
+    })
+}
@@ -0,0 +1,225 @@
+use std::time::Instant;
+
+use axum::async_trait;
+use http::Uri;
+
+use super::{truncate_description, ArtSource};
+use crate::{
+    config::config,
+    data::{FetchedLink, ImageQuality},
+    error::{check_throttled, AppErrorKind, AppResult},
+};
+
+// query params twitter adds to shared links
+const TRACKING_PARAMS: &[&str] = &["s", "t", "ref_src"];
+// tweets can't have more photos than this
+const MAX_PHOTOS: usize = 4;
+
+pub(crate) struct Twitter {
+    fxtwitter_url: String,
+    api_url: String,
+}
+
+impl Twitter {
+    pub(crate) fn new() -> Self {
+        let upstream = &config().upstream;
+        Self::at(&upstream.fxtwitter_url, &upstream.fxtwitter_api_url)
+    }
+
+    // fxtwitter behind other urls, like a mock upstream in tests
+    pub(crate) fn at(fxtwitter_url: &str, api_url: &str) -> Self {
+        Self {
+            fxtwitter_url: fxtwitter_url.to_owned(),
+            api_url: api_url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtSource for Twitter {
+    fn name(&self) -> &'static str {
+        "twitter"
+    }
+
+    fn hosts(&self) -> &[&'static str] {
+        &[
+            "twitter.com",
+            "www.twitter.com",
+            "mobile.twitter.com",
+            "x.com",
+            "www.x.com",
+        ]
+    }
+
+    fn image_hosts(&self) -> &[&'static str] {
+        &["pbs.twimg.com"]
+    }
+
+    // links copied from twitter carry share tracking and point at a photo of the tweet, but it's
+    // all the same tweet
+    fn canonical(&self, url: Uri) -> Uri {
+        let mut path = url.path();
+        if let Some((tweet, index)) = path.rsplit_once("/photo/") {
+            if index.bytes().all(|b| b.is_ascii_digit()) {
+                path = tweet;
+            }
+        }
+        let query = url
+            .query()
+            .map(|query| {
+                form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(
+                        form_urlencoded::parse(query.as_bytes())
+                            .filter(|(name, _)| !TRACKING_PARAMS.contains(&name.as_ref())),
+                    )
+                    .finish()
+            })
+            .filter(|query| !query.is_empty())
+            .map(|query| format!("?{query}"))
+            .unwrap_or_default();
+        format!("https://twitter.com{path}{query}")
+            .parse()
+            .unwrap_or(url)
+    }
+
+    // fxtwitter serves any photo of a tweet the same way twitter links to it
+    fn photo(&self, url: &Uri, photo: usize) -> Option<Uri> {
+        if !(1..=MAX_PHOTOS).contains(&photo) {
+            return None;
+        }
+        format!("https://twitter.com{}/photo/{photo}", url.path())
+            .parse()
+            .ok()
+    }
+
+    fn artist<'a>(&self, url: &'a Uri) -> Option<&'a str> {
+        url.path().split('/').nth(1).filter(|s| !s.is_empty())
+    }
+
+    async fn fetch(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        self.fetch_image_link(http, url, quality).await
+    }
+}
+
+impl Twitter {
+    // also used for booru posts that were sourced from twitter
+    pub(super) async fn fetch_image_link(
+        &self,
+        http: &reqwest::Client,
+        url: &Uri,
+        quality: ImageQuality,
+    ) -> AppResult<FetchedLink> {
+        let fxurl = format!(
+            "{}{}",
+            self.fxtwitter_url,
+            url.path_and_query().map_or("/", |path| path.as_str())
+        );
+        tracing::debug!(url = %fxurl, "fetching fxtwitter link");
+        let req = http.get(&fxurl).build()?;
+        let resp = http.execute(req).await?;
+        check_throttled(&resp)?;
+        let resp = resp.error_for_status()?;
+        let link = resp
+            .headers()
+            .get(http::header::LOCATION)
+            .ok_or_else(|| {
+                AppErrorKind::UpstreamNotFound(format!(
+                    "twitter link {fxurl} did not return an image location"
+                ))
+            })?
+            .to_str()?;
+        let image_url = image_variant(link, quality);
+        let info = fetch_tweet_info(http, &self.api_url, url, link)
+            .await
+            .unwrap_or_default();
+        Ok(FetchedLink {
+            image_url,
+            new_source: None,
+            description: info.text,
+            width: info.width,
+            height: info.height,
+            artist: None,
+            preview: None,
+            validators: None,
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+// without a name twitter serves a downscaled image, orig is the full resolution one
+fn image_variant(link: &str, quality: ImageQuality) -> String {
+    let separator = if link.contains('?') { '&' } else { '?' };
+    let format = config().upstream.twitter_image_format;
+    match quality {
+        ImageQuality::Original => format!("{link}{separator}format={format}&name=orig"),
+        ImageQuality::Sample => format!("{link}{separator}format={format}"),
+        // webp regardless of the configured format, since it's the smallest
+        ImageQuality::Low => format!("{link}{separator}format=webp&name=small"),
+    }
+}
+
+#[derive(Default)]
+struct TweetInfo {
+    text: Option<String>,
+    // of the photo we link to
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+// only used for alt text and sizing, so failing to get it shouldn't fail the whole fetch
+async fn fetch_tweet_info(
+    http: &reqwest::Client,
+    api_url: &str,
+    url: &Uri,
+    image_url: &str,
+) -> Option<TweetInfo> {
+    // the api only knows about tweets, not their photos
+    let path = url.path();
+    let path = path.rsplit_once("/photo/").map_or(path, |(tweet, _)| tweet);
+    let apiurl = format!("{api_url}{path}");
+    tracing::debug!(url = %apiurl, "fetching tweet info");
+    let resp = http
+        .execute(http.get(&apiurl).build().ok()?)
+        .await
+        .and_then(|resp| resp.error_for_status());
+    let data = match resp {
+        Ok(resp) => resp.json::<serde_json::Value>().await.ok()?,
+        Err(err) => {
+            tracing::warn!(url = %apiurl, error = %err, "could not fetch tweet info");
+            return None;
+        }
+    };
+    let tweet = data.get("tweet")?;
+    let text = tweet
+        .get("text")
+        .and_then(|text| text.as_str())
+        .map(|text| truncate_description(text.trim()))
+        .filter(|text| !text.is_empty());
+
+    let photos = tweet
+        .get("media")
+        .and_then(|media| media.get("photos"))
+        .and_then(|photos| photos.as_array());
+    let photo = photos.and_then(|photos| {
+        photos
+            .iter()
+            .find(|photo| photo.get("url").and_then(|url| url.as_str()) == Some(image_url))
+            .or_else(|| photos.first())
+    });
+    let dimension = |field: &str| {
+        photo
+            .and_then(|photo| photo.get(field)?.as_u64())
+            .and_then(|value| u32::try_from(value).ok())
+            .filter(|value| *value > 0)
+    };
+    Some(TweetInfo {
+        text,
+        width: dimension("width"),
+        height: dimension("height"),
+    })
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axum::{extract::State, http::HeaderMap, response::Html};
+use dashmap::DashMap;
+use http::Uri;
+
+use crate::{
+    admin, config::config, data::ArtKind, error::AppResult, get_page_head_common, AppState,
+    FAILED_LINK_TTL,
+};
+
+// how many times each art was served, keyed by url so counts survive the arts file changing
+#[derive(Default)]
+pub(crate) struct ServeCounts {
+    counts: DashMap<Uri, u64>,
+}
+
+impl ServeCounts {
+    pub(crate) fn load(path: &str) -> Self {
+        let this = Self::default();
+        let saved = match std::fs::read_to_string(path) {
+            Ok(saved) => saved,
+            Err(err) => {
+                tracing::info!(path, error = %err, "no saved serve counts, starting from zero");
+                return this;
+            }
+        };
+        match serde_json::from_str::<HashMap<String, u64>>(&saved) {
+            Ok(saved) => {
+                for (url, count) in saved {
+                    if let Ok(url) = url.parse() {
+                        this.counts.insert(url, count);
+                    }
+                }
+            }
+            Err(err) => tracing::warn!(path, error = %err, "could not parse saved serve counts"),
+        }
+        this
+    }
+
+    pub(crate) fn save(&self, path: &str) {
+        let counts = self
+            .counts
+            .iter()
+            .map(|entry| (entry.key().to_string(), *entry.value()))
+            .collect::<HashMap<_, _>>();
+        let result = serde_json::to_string(&counts)
+            .map_err(std::io::Error::from)
+            .and_then(|counts| std::fs::write(path, counts));
+        match result {
+            Ok(()) => tracing::info!(path, "saved serve counts"),
+            Err(err) => tracing::error!(path, error = %err, "could not save serve counts"),
+        }
+    }
+
+    pub(crate) fn record(&self, url: &Uri) {
+        *self.counts.entry(url.clone()).or_default() += 1;
+    }
+
+    pub(crate) fn restore(&self, counts: HashMap<Uri, u64>) {
+        for (url, count) in counts {
+            self.counts.insert(url, count);
+        }
+    }
+
+    pub(crate) fn counts(&self) -> impl Iterator<Item = (Uri, u64)> + '_ {
+        self.counts
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+    }
+
+    fn get(&self, url: &Uri) -> u64 {
+        self.counts.get(url).map_or(0, |count| *count)
+    }
+}
+
+// how well the link cache works, and how often each kind of upstream fails
+#[derive(Default)]
+pub(crate) struct FetchCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    failures: DashMap<ArtKind, u64>,
+}
+
+impl FetchCounters {
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_failure(&self, kind: &ArtKind) {
+        *self.failures.entry(*kind).or_default() += 1;
+    }
+
+    fn summary(&self) -> String {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let mut failures = self
+            .failures
+            .iter()
+            .map(|entry| format!("{}={}", entry.key(), entry.value()))
+            .collect::<Vec<_>>();
+        failures.sort();
+        let failures = failures.join(" ");
+        format!("hits={hits} misses={misses} failures: {failures}")
+    }
+}
+
+// logs the counters every interval, they are totals since startup
+pub(crate) async fn run_summary(state: AppState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        tracing::info!(counters = %state.fetch_counters.summary(), "fetch counters");
+    }
+}
+
+pub(crate) async fn show_stats(
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> AppResult<Html<String>> {
+    // public unless there is an admin token to protect it with
+    if !config().server.admin_token.is_empty() {
+        admin::authorize(&headers)?;
+    }
+
+    let arts = state.data.lock().unwrap().arts().to_vec();
+    let mut rows = arts
+        .into_iter()
+        .map(|art| {
+            let count = state.serve_counts.get(&art.url);
+            let cached = state
+                .direct_links
+                .contains_key(&(art.url.clone(), state.default_quality));
+            let recently_failed = state
+                .failed_links
+                .get(&art.url)
+                .map_or(false, |failed_at| failed_at.elapsed() < FAILED_LINK_TTL);
+            let cache_status = if cached {
+                "cached"
+            } else if recently_failed {
+                "failed"
+            } else {
+                "not cached"
+            };
+            (art, count, cache_status)
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.1.cmp(&a.1));
+    let total: u64 = rows.iter().map(|row| row.1).sum();
+
+    let cell_style = "padding: 0.2vh 1vw; text-align: left;";
+    let content = maud::html! {
+        (maud::DOCTYPE)
+        head {
+            (get_page_head_common(None))
+        }
+        body style="flex-direction: column; height: auto; min-height: 100vh;" {
+            h1 style="margin: 2vh auto; font-size: 1.5vmax;" { (total) " serves over " (rows.len()) " arts" }
+            p class="about" style="margin: 0 auto 2vh auto;" { "link cache: " (state.fetch_counters.summary()) }
+            table style="margin: 0 auto 2vh auto; border-collapse: collapse;" {
+                tr {
+                    th class="about" style=(cell_style) { "url" }
+                    th class="about" style=(cell_style) { "kind" }
+                    th class="about" style=(cell_style) { "list" }
+                    th class="about" style=(cell_style) { "times served" }
+                    th class="about" style=(cell_style) { "cache" }
+                }
+                @for (art, count, cache_status) in &rows {
+                    tr {
+                        td class="about" style=(cell_style) { a class="about" href=(art.url) target="_blank" { (art.url) } }
+                        td class="about" style=(cell_style) { (art.kind) }
+                        td class="about" style=(cell_style) { (art.list.as_deref().unwrap_or_default()) }
+                        td class="about" style=(cell_style) { (count) }
+                        td class="about" style=(cell_style) { (cache_status) }
+                    }
+                }
+            }
+        }
+    };
+    Ok(Html(content.into_string()))
+}
@@ -0,0 +1,69 @@
+// readiness notifications and socket activation, both do nothing when not started by systemd
+
+// sends a state like READY=1 to systemd
+pub(crate) fn notify(state: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        if let Err(err) = send_notify(&path, state) {
+            tracing::warn!(state, error = %err, "could not notify systemd");
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+}
+
+#[cfg(target_os = "linux")]
+fn send_notify(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    };
+
+    // names starting with @ are abstract sockets
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+// the socket systemd is holding for us, so it can keep accepting connections across restarts
+pub(crate) fn activated_listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+
+        // see sd_listen_fds(3), passed sockets start at fd 3
+        const LISTEN_FDS_START: i32 = 3;
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|fds| fds.parse::<u32>().ok())
+            .unwrap_or(0);
+        if !for_us || fds == 0 {
+            return Ok(None);
+        }
+        if fds > 1 {
+            tracing::warn!(
+                fds,
+                "got more than one socket from systemd, only using the first"
+            );
+        }
+        // SAFETY: systemd passed this fd to us and nothing else in the process owns it
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+        listener.set_nonblocking(true)?;
+        Ok(Some(listener))
+    }
+    #[cfg(not(unix))]
+    Ok(None)
+}
@@ -0,0 +1,97 @@
+// an app to send requests to in tests, with the arts and sources a test needs
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+};
+
+use axum::Router;
+
+use crate::{
+    app, build_http_client, config,
+    data::{ArtList, Data, PickMode},
+    sources::{self, Registry},
+    with_middleware, AppState,
+};
+
+// the state with the default config, the arts are lines of an arts list
+pub(crate) fn state(sources: &'static Registry, arts: &str) -> AppState {
+    build_state(sources, arts, "arts.txt", "blocklist.txt")
+}
+
+// like state, but with the arts in a file that reloads read again, and the blocklist next to it
+pub(crate) fn state_from_file(sources: &'static Registry, path: &str) -> AppState {
+    let blocklist_path = Path::new(path).with_file_name("blocklist.txt");
+    build_state(
+        sources,
+        &std::fs::read_to_string(path).unwrap(),
+        path,
+        blocklist_path.to_str().unwrap(),
+    )
+}
+
+fn build_state(
+    sources: &'static Registry,
+    arts: &str,
+    arts_path: &str,
+    blocklist_path: &str,
+) -> AppState {
+    config::init_default();
+    let list = ArtList {
+        name: arts_path.into(),
+        contents: arts.to_owned(),
+    };
+    let data = Data::parse(
+        &[list],
+        sources,
+        PickMode::Random,
+        HashSet::new(),
+        &HashMap::new(),
+    )
+    .unwrap();
+    AppState::new(
+        data,
+        config::config().arts.image_quality,
+        arts_path.to_owned(),
+        blocklist_path.to_owned(),
+        build_http_client().unwrap(),
+        None,
+        None,
+        sources,
+    )
+}
+
+// a state with the builtin sources, for tests that don't fetch anything
+pub(crate) fn builtin_state() -> AppState {
+    config::init_default();
+    state(sources::registry(), "https://twitter.com/someone/status/1")
+}
+
+// serves the app on a free port, returning its base url
+pub(crate) async fn serve(state: &AppState) -> String {
+    let Ok(router) = app(state) else {
+        panic!("could not build the app");
+    };
+    listen(router).await
+}
+
+// serves the routes with the middleware of the app, for routes only tests have
+pub(crate) async fn serve_routes(routes: Router<AppState>, state: &AppState) -> String {
+    let Ok(router) = with_middleware(routes, state) else {
+        panic!("could not build the app");
+    };
+    listen(router).await
+}
+
+async fn listen(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    format!("http://{addr}")
+}
@@ -0,0 +1,172 @@
+use std::sync::{OnceLock, RwLock};
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::{header, HeaderValue};
+use maud::PreEscaped;
+
+use crate::config::config;
+
+const THEME_COOKIE: &str = "theme";
+const LIGHT_BACKGROUND: &str = "#f4f4f4";
+const LIGHT_TEXT: &str = "#0e0e0e";
+
+const PAGE_CSS: &str = "body{color:var(--text-color);margin:0;background:var(--bg-color);height:100vh;width:100vw;display:flex;font-family:\"PT Mono\",monospace;font-weight:400;font-style:normal;font-optical-sizing:auto}.about{font-size:1vmax;color:var(--text-color)}";
+
+// read from CUSTOM_CSS_PATH at startup and on reload signals
+static CUSTOM_CSS: RwLock<String> = RwLock::new(String::new());
+
+pub(crate) fn load_custom_css() {
+    let Some(path) = &config().site.custom_css_path else {
+        return;
+    };
+    match std::fs::read_to_string(path) {
+        Ok(css) => {
+            tracing::info!(path, "loaded custom css");
+            *CUSTOM_CSS.write().unwrap() = css;
+        }
+        // keep whatever was loaded before, a half written file shouldn't unstyle the site
+        Err(err) => tracing::warn!(path, error = %err, "could not read custom css"),
+    }
+}
+
+// hex colors, rgb() and friends, or named colors, anything else could break out of the style
+fn is_css_color(value: &str) -> bool {
+    if let Some(hex) = value.strip_prefix('#') {
+        return matches!(hex.len(), 3 | 4 | 6 | 8) && hex.bytes().all(|b| b.is_ascii_hexdigit());
+    }
+    if let Some((function, args)) = value.split_once('(') {
+        return matches!(function, "rgb" | "rgba" | "hsl" | "hsla")
+            && args.strip_suffix(')').map_or(false, |args| {
+                args.bytes()
+                    .all(|b| b.is_ascii_digit() || b" .,%/".contains(&b))
+            });
+    }
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+fn color(name: &str, value: &str, default: &'static str) -> String {
+    if is_css_color(value) {
+        return value.to_owned();
+    }
+    tracing::warn!(name, value, default, "not a css color, using the default");
+    default.to_owned()
+}
+
+// BG_COLOR and TEXT_COLOR, checked once
+fn dark_colors() -> &'static (String, String) {
+    static COLORS: OnceLock<(String, String)> = OnceLock::new();
+    COLORS.get_or_init(|| {
+        let site = &config().site;
+        (
+            color("BG_COLOR", &site.bg_color, "#0e0e0e"),
+            color("TEXT_COLOR", &site.text_color, "#ffffff"),
+        )
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "dark" => Some(Self::Dark),
+            "light" => Some(Self::Light),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Dark => "dark",
+            Self::Light => "light",
+        }
+    }
+
+    pub(crate) fn background(self) -> &'static str {
+        match self {
+            Self::Dark => &dark_colors().0,
+            Self::Light => LIGHT_BACKGROUND,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            Self::Dark => &dark_colors().1,
+            Self::Light => LIGHT_TEXT,
+        }
+    }
+
+    fn variables(self) -> String {
+        format!(
+            ":root{{--bg-color:{};--text-color:{}}}",
+            self.background(),
+            self.text()
+        )
+    }
+}
+
+// the page styles, in the visitor's theme if they picked one and following their system if not,
+// plus the custom css so it can override anything
+pub(crate) fn page_style() -> PreEscaped<String> {
+    let variables = match current() {
+        Some(theme) => theme.variables(),
+        None => format!(
+            "{}@media(prefers-color-scheme:light){{{}}}",
+            Theme::Dark.variables(),
+            Theme::Light.variables()
+        ),
+    };
+    let custom_css = CUSTOM_CSS.read().unwrap();
+    maud::html! {
+        style { (PreEscaped(variables)) (PreEscaped(PAGE_CSS)) (PreEscaped(custom_css.as_str())) }
+    }
+}
+
+tokio::task_local! {
+    static THEME: Option<Theme>;
+}
+
+// ?theme= wins over the cookie and is remembered in it, ?theme=auto forgets it again
+pub(crate) async fn select_theme(req: Request, next: Next) -> Response {
+    let requested = req.uri().query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "theme")
+            .map(|(_, theme)| theme.into_owned())
+    });
+    let remembered = req
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(THEME_COOKIE)?.strip_prefix('='))
+        .and_then(Theme::parse);
+    let theme = match requested.as_deref() {
+        Some("auto") => None,
+        Some(requested) => Theme::parse(requested).or(remembered),
+        None => remembered,
+    };
+
+    let mut resp = THEME.scope(theme, next.run(req)).await;
+    let cookie = match (requested.as_deref(), theme) {
+        (Some("auto"), _) => Some(format!("{THEME_COOKIE}=; Path=/; Max-Age=0; SameSite=Lax")),
+        (Some(_), Some(theme)) => Some(format!(
+            "{THEME_COOKIE}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            theme.name()
+        )),
+        _ => None,
+    };
+    if let Some(cookie) = cookie.and_then(|cookie| HeaderValue::from_str(&cookie).ok()) {
+        resp.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    resp
+}
+
+// the theme the visitor picked, if they did
+pub(crate) fn current() -> Option<Theme> {
+    THEME.try_with(|theme| *theme).ok().flatten()
+}
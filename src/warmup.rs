@@ -0,0 +1,41 @@
+use std::time::Instant;
+
+use futures_util::StreamExt;
+
+use crate::{get_background_link, AppState};
+
+// how many arts are resolved at the same time, kept low so upstreams don't rate limit us
+const WARMUP_CONCURRENCY: usize = 3;
+
+// pre-resolves up to `count` random arts so the first visitors don't have to wait
+pub(crate) async fn run(state: AppState, count: usize) {
+    let start = Instant::now();
+    let mut arts = state.data.lock().unwrap().arts().to_vec();
+    fastrand::shuffle(&mut arts);
+    arts.truncate(count);
+
+    let total = arts.len();
+    let quality = state.default_quality;
+    let warmed = futures_util::stream::iter(arts)
+        .map(|art| {
+            let state = state.clone();
+            async move {
+                match get_background_link(&state, &art, quality).await {
+                    Ok(_) => true,
+                    Err(err) => {
+                        tracing::warn!(art_url = %art.url, error = %err, "could not warm up link");
+                        false
+                    }
+                }
+            }
+        })
+        .buffer_unordered(WARMUP_CONCURRENCY)
+        .filter(|warmed| futures_util::future::ready(*warmed))
+        .count()
+        .await;
+
+    tracing::info!(
+        "warmed {warmed}/{total} links in {:.1}s",
+        start.elapsed().as_secs_f32()
+    );
+}